@@ -1,26 +1,46 @@
 #![no_std]
 
 use core::cmp::max;
+use core::sync::atomic::{AtomicU64, Ordering};
 use aya_ebpf::bindings::BPF_ANY;
 use aya_ebpf::cty::c_ulong;
-use aya_ebpf::helpers::{bpf_get_current_task, bpf_probe_read_kernel};
-use aya_ebpf::maps::{Array, HashMap};
-use ebpf_memory_monitor_common::RLIMIT_AS_NOT_HIT;
-use crate::vmlinux::{mm_struct, signal_struct, task_struct};
+use aya_ebpf::EbpfContext;
+use aya_ebpf::helpers::{
+    bpf_get_current_pid_tgid, bpf_get_current_task, bpf_ktime_get_ns, bpf_probe_read_kernel,
+    bpf_probe_read_user, bpf_send_signal,
+};
+use aya_ebpf::maps::{Array, HashMap, LruHashMap, PerfEventArray};
+use aya_ebpf::programs::{ProbeContext, TracePointContext};
+use ebpf_memory_monitor_common::{
+    ProcessRecord, RlimitChangeEvent, NO_BUDGET, PAGE_SHIFT_INDEX, RLIMIT_AS_INDEX,
+};
+use crate::vmlinux::{mm_struct, pt_regs, rlimit, signal_struct, task_struct};
 
 #[allow(warnings)]
 pub mod vmlinux;
 
+const ENOMEM: i32 = 12;
+
+// The offsets of the arguments in the syscall tracepoints, see the `format` files in
+// /sys/kernel/tracing/events/syscalls
+const SYS_ENTER_SETRLIMIT_RESOURCE_OFFSET: usize = 16;
+const SYS_ENTER_SETRLIMIT_RLIM_OFFSET: usize = 24;
+const SYS_ENTER_PRLIMIT64_PID_OFFSET: usize = 16;
+const SYS_ENTER_PRLIMIT64_RESOURCE_OFFSET: usize = 24;
+const SYS_ENTER_PRLIMIT64_NEW_RLIM_OFFSET: usize = 32;
+const SYS_ENTER_MMAP_LEN_OFFSET: usize = 24;
+
 pub fn try_on_do_exit(
     tgid: u32,
-    vm_peak: &HashMap<u32, u64>,
+    processes: &HashMap<u32, ProcessRecord>,
     constants: &Array<u64>
 ) -> Result<u32, i64> {
-    if let Some(_) = unsafe { vm_peak.get(&tgid) } {
-        let page_shift = *constants.get(0).ok_or(1i64)?;
+    if let Some(record) = processes.get_ptr_mut(&tgid) {
+        let page_shift = *constants.get(PAGE_SHIFT_INDEX).ok_or(1i64)?;
 
+        let task = unsafe { bpf_get_current_task() } as *mut task_struct;
         let mm: *mut mm_struct = unsafe {
-            bpf_probe_read_kernel(&(*(bpf_get_current_task() as *mut task_struct)).mm)
+            bpf_probe_read_kernel(&(*task).mm)
         }?;
         let total_vm = unsafe {
             bpf_probe_read_kernel(&(*mm).__bindgen_anon_1.total_vm as *const u64)
@@ -29,9 +49,33 @@ pub fn try_on_do_exit(
             bpf_probe_read_kernel(&(*mm).__bindgen_anon_1.hiwater_vm as *const u64)
         }?;
 
-        // We need to do a max(total_vm, hiwater_vm) because the hiwater_vm is
-        // only updated when total_vm gets lower.
-        vm_peak.insert(&tgid, &(max(total_vm, hiwater_vm) << page_shift), BPF_ANY as u64)?;
+        // The counters of the threads are only added to the signal_struct when they are
+        // released, which happens after the last thread exits for a zombie group leader.
+        // Instead, every exiting thread adds its own counters to the totals.
+        let utime = unsafe { bpf_probe_read_kernel(&(*task).utime) }?;
+        let stime = unsafe { bpf_probe_read_kernel(&(*task).stime) }?;
+        let nvcsw = unsafe { bpf_probe_read_kernel(&(*task).nvcsw) }?;
+        let nivcsw = unsafe { bpf_probe_read_kernel(&(*task).nivcsw) }?;
+
+        let group_leader: *mut task_struct = unsafe {
+            bpf_probe_read_kernel(&(*task).group_leader)
+        }?;
+        let start_time = unsafe {
+            bpf_probe_read_kernel(&(*group_leader).start_time)
+        }?;
+
+        let stats = unsafe { &raw mut (*record).exit_stats };
+        unsafe {
+            // We need to do a max(total_vm, hiwater_vm) because the hiwater_vm is
+            // only updated when total_vm gets lower. The memory is shared by the threads,
+            // so the last one to exit sees the final peak.
+            atomic_max(&raw mut (*stats).vm_peak, max(total_vm, hiwater_vm) << page_shift);
+            atomic_add(&raw mut (*stats).utime, utime);
+            atomic_add(&raw mut (*stats).stime, stime);
+            atomic_add(&raw mut (*stats).nvcsw, nvcsw);
+            atomic_add(&raw mut (*stats).nivcsw, nivcsw);
+            (*stats).start_time = start_time;
+        }
 
         Ok(0)
     } else {
@@ -39,31 +83,97 @@ pub fn try_on_do_exit(
     }
 }
 
+/// Marks the process as exited once its last thread exits.
+///
+/// `do_exit` decrements the number of live threads of the process after the `do_exit`
+/// probe ran, and before this tracepoint, so once it reaches zero every thread already
+/// added its counters to the record.
+pub fn try_on_sched_process_exit(
+    tgid: u32,
+    processes: &HashMap<u32, ProcessRecord>,
+) -> Result<u32, i64> {
+    if let Some(record) = processes.get_ptr_mut(&tgid) {
+        let task = unsafe { bpf_get_current_task() } as *mut task_struct;
+        let signal: *mut signal_struct = unsafe {
+            bpf_probe_read_kernel(&(*task).signal)
+        }?;
+        let live = unsafe { bpf_probe_read_kernel(&(*signal).live.counter) }?;
+
+        // Threads exiting at the same time may all see zero, so the first time is kept.
+        if live == 0 {
+            let exit_time = unsafe { &raw mut (*record).exit_stats.exit_time };
+            let _ = unsafe { AtomicU64::from_ptr(exit_time) }
+                .compare_exchange(0, unsafe { bpf_ktime_get_ns() }, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    Ok(0)
+}
+
+// `mm` is only read with bpf_probe_read_kernel, which fails instead of faulting on an
+// invalid pointer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub fn try_on_may_expand_vm(
     mm: *const mm_struct,
     npages: c_ulong,
     tgid: u32,
-    attempted_vm_peak: &HashMap<u32, i64>,
+    processes: &HashMap<u32, ProcessRecord>,
     constants: &Array<u64>
 ) -> Result<u32, i64> {
-    if let Some(value) = unsafe { attempted_vm_peak.get(&tgid) } && *value == RLIMIT_AS_NOT_HIT {
-        let rlimit_as: usize = (*constants.get(0).ok_or(1i64)?).try_into().map_err(|_| 1)?;
-        let page_shift: u64 = *constants.get(1).ok_or(1i64)?;
+    if let Some(record) = processes.get_ptr_mut(&tgid) {
+        let limits = unsafe { &raw mut (*record).vm_limits };
+        let check_budget = unsafe { (*limits).budget } != NO_BUDGET
+            && unsafe { (*limits).budget_exceeded } == 0;
+
+        let rlimit_as: usize = (*constants.get(RLIMIT_AS_INDEX).ok_or(1i64)?).try_into().map_err(|_| 1)?;
+        let page_shift: u64 = *constants.get(PAGE_SHIFT_INDEX).ok_or(1i64)?;
 
         let total_vm = unsafe {
             bpf_probe_read_kernel(&(*mm).__bindgen_anon_1.total_vm as *const u64)
         }?;
+        let attempted_vm = (total_vm + npages) << page_shift;
 
         let signal: *mut signal_struct = unsafe {
             bpf_probe_read_kernel(&(*(bpf_get_current_task() as *mut task_struct)).signal)
         }?;
-        let current_rlimit_as: u64 = unsafe {
-            bpf_probe_read_kernel(&(*signal).rlim.get(rlimit_as).ok_or(1i64)?.rlim_cur)
+        let current_rlimit_as: rlimit = unsafe {
+            bpf_probe_read_kernel(signal_rlimit(signal, rlimit_as)?)
         }?;
 
-        if total_vm + npages > current_rlimit_as >> page_shift {
-            let to_insert: i64 = ((total_vm + npages) << page_shift).try_into().map_err(|_| 1)?;
-            attempted_vm_peak.insert(&tgid, &to_insert, BPF_ANY as u64)?;
+        if total_vm + npages > current_rlimit_as.rlim_cur >> page_shift {
+            let now = unsafe { bpf_ktime_get_ns() };
+
+            // The threads of the process may hit the limit at the same time, so the first
+            // hit is claimed with a compare-and-swap, and the counter is added atomically.
+            unsafe {
+                let first_attempted_vm = AtomicU64::from_ptr(&raw mut (*limits).first_attempted_vm);
+                if first_attempted_vm
+                    .compare_exchange(0, attempted_vm, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    (*limits).first_hit_time = now;
+                }
+                if atomic_max(&raw mut (*limits).max_attempted_vm, attempted_vm) {
+                    (*limits).max_attempted_rlimit_cur = current_rlimit_as.rlim_cur;
+                    (*limits).max_attempted_rlimit_max = current_rlimit_as.rlim_max;
+                }
+                atomic_max(&raw mut (*limits).last_hit_time, now);
+                atomic_add(&raw mut (*limits).rlimit_hits, 1);
+            }
+        }
+
+        // Only the thread which claims budget_exceeded sends the signal, so that it's sent
+        // once even if several threads cross the budget at the same time.
+        if check_budget
+            && attempted_vm > unsafe { (*limits).budget }
+            && unsafe { AtomicU64::from_ptr(&raw mut (*limits).budget_exceeded) }
+                .compare_exchange(0, attempted_vm, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let ret = unsafe { bpf_send_signal((*limits).budget_signal) };
+            if ret < 0 {
+                return Err(ret);
+            }
         }
 
         Ok(0)
@@ -71,3 +181,242 @@ pub fn try_on_may_expand_vm(
         Ok(0)
     }
 }
+
+fn signal_rlimit(signal: *const signal_struct, resource: usize) -> Result<*const rlimit, i64> {
+    Ok(unsafe { (*signal).rlim.get(resource).ok_or(1i64)? })
+}
+
+pub fn try_on_sys_enter_setrlimit(
+    ctx: &TracePointContext,
+    processes: &HashMap<u32, ProcessRecord>,
+    rlimit_changes: &PerfEventArray<RlimitChangeEvent>,
+) -> Result<u32, i64> {
+    let resource = unsafe { ctx.read_at::<u64>(SYS_ENTER_SETRLIMIT_RESOURCE_OFFSET) }?;
+    let rlim = unsafe { ctx.read_at::<*const [u64; 2]>(SYS_ENTER_SETRLIMIT_RLIM_OFFSET) }?;
+
+    try_on_rlimit_change(ctx, 0, resource as u32, rlim, processes, rlimit_changes)
+}
+
+pub fn try_on_sys_enter_prlimit64(
+    ctx: &TracePointContext,
+    processes: &HashMap<u32, ProcessRecord>,
+    rlimit_changes: &PerfEventArray<RlimitChangeEvent>,
+) -> Result<u32, i64> {
+    let pid = unsafe { ctx.read_at::<u64>(SYS_ENTER_PRLIMIT64_PID_OFFSET) }?;
+    let resource = unsafe { ctx.read_at::<u64>(SYS_ENTER_PRLIMIT64_RESOURCE_OFFSET) }?;
+    let new_rlim = unsafe { ctx.read_at::<*const [u64; 2]>(SYS_ENTER_PRLIMIT64_NEW_RLIM_OFFSET) }?;
+
+    try_on_rlimit_change(ctx, pid as u32, resource as u32, new_rlim, processes, rlimit_changes)
+}
+
+fn try_on_rlimit_change(
+    ctx: &TracePointContext,
+    target_tgid: u32,
+    resource: u32,
+    new_rlim: *const [u64; 2],
+    processes: &HashMap<u32, ProcessRecord>,
+    rlimit_changes: &PerfEventArray<RlimitChangeEvent>,
+) -> Result<u32, i64> {
+    let caller_tgid = ctx.tgid();
+    // A pid of 0 in prlimit64 refers to the calling process.
+    let target_tgid = if target_tgid == 0 { caller_tgid } else { target_tgid };
+
+    let monitored = processes.get_ptr(&caller_tgid).is_some()
+        || processes.get_ptr(&target_tgid).is_some();
+    // A null new_rlim in prlimit64 means that the limit is only read.
+    if monitored && !new_rlim.is_null() {
+        let [soft, hard] = unsafe { bpf_probe_read_user(new_rlim) }?;
+
+        rlimit_changes.output(ctx, &RlimitChangeEvent {
+            time: unsafe { bpf_ktime_get_ns() },
+            caller_tgid,
+            target_tgid,
+            resource,
+            _padding: 0,
+            soft,
+            hard,
+        }, 0);
+    }
+
+    Ok(0)
+}
+
+pub fn try_on_sys_enter_mmap(
+    ctx: &TracePointContext,
+    enforced_limits: &HashMap<u32, u64>,
+    mmap_lengths: &LruHashMap<u64, u64>,
+) -> Result<u32, i64> {
+    // The mmap_file LSM hook does not receive the length of the mapping, so it is
+    // saved here for the hook to pick up later in the same syscall.
+    if enforced_limits.get_ptr(&ctx.tgid()).is_some() {
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let len = unsafe { ctx.read_at::<u64>(SYS_ENTER_MMAP_LEN_OFFSET) }?;
+        mmap_lengths.insert(&pid_tgid, &len, BPF_ANY as u64)?;
+    }
+
+    Ok(0)
+}
+
+/// Removes the length saved by `try_on_sys_enter_mmap`, as `mmap` can fail before reaching
+/// the mmap_file hook, which would otherwise use it for a later mapping made outside of the
+/// syscall, like the ones of `execve` or `shmat`.
+pub fn try_on_sys_exit_mmap(mmap_lengths: &LruHashMap<u64, u64>) -> Result<u32, i64> {
+    // The entry is already gone if the hook ran, or if the process has no enforced limit.
+    let _ = mmap_lengths.remove(&bpf_get_current_pid_tgid());
+
+    Ok(0)
+}
+
+pub fn try_on_mmap_file(
+    tgid: u32,
+    pid_tgid: u64,
+    processes: &HashMap<u32, ProcessRecord>,
+    enforced_limits: &HashMap<u32, u64>,
+    mmap_lengths: &LruHashMap<u64, u64>,
+    constants: &Array<u64>
+) -> Result<i32, i64> {
+    if let Some(record) = processes.get_ptr_mut(&tgid)
+        && let Some(limit) = unsafe { enforced_limits.get(&tgid) }
+        && let Some(len) = unsafe { mmap_lengths.get(&pid_tgid) }
+    {
+        let len = *len;
+        mmap_lengths.remove(&pid_tgid)?;
+
+        let page_shift = *constants.get(PAGE_SHIFT_INDEX).ok_or(1i64)?;
+        let npages = page_count(len, page_shift);
+
+        let mm: *mut mm_struct = unsafe {
+            bpf_probe_read_kernel(&(*(bpf_get_current_task() as *mut task_struct)).mm)
+        }?;
+        if exceeds_enforced_limit(mm, npages, *limit, record, page_shift)? {
+            return Ok(-ENOMEM);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Returns the value `brk` has to return instead of growing the heap of the process above
+/// its enforced limit, which is the current end of the heap, or `None` to let it run.
+pub fn try_on_sys_brk(
+    ctx: &ProbeContext,
+    processes: &HashMap<u32, ProcessRecord>,
+    enforced_limits: &HashMap<u32, u64>,
+    constants: &Array<u64>
+) -> Result<Option<u64>, i64> {
+    let tgid = ctx.tgid();
+    if let Some(record) = processes.get_ptr_mut(&tgid)
+        && let Some(limit) = unsafe { enforced_limits.get(&tgid) }
+    {
+        let new_brk = syscall_arg(ctx, 0)?;
+
+        let page_shift = *constants.get(PAGE_SHIFT_INDEX).ok_or(1i64)?;
+        let mm: *mut mm_struct = unsafe {
+            bpf_probe_read_kernel(&(*(bpf_get_current_task() as *mut task_struct)).mm)
+        }?;
+        let brk = unsafe { bpf_probe_read_kernel(&(*mm).__bindgen_anon_1.brk) }?;
+
+        // Shrinking the heap, or reading its end with an address of 0, never fails.
+        let npages = page_count(new_brk, page_shift).saturating_sub(page_count(brk, page_shift));
+        if npages > 0 && exceeds_enforced_limit(mm, npages, *limit, record, page_shift)? {
+            return Ok(Some(brk));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the value `mremap` has to return instead of growing a mapping of the process
+/// above its enforced limit, which is `-ENOMEM`, or `None` to let it run.
+pub fn try_on_sys_mremap(
+    ctx: &ProbeContext,
+    processes: &HashMap<u32, ProcessRecord>,
+    enforced_limits: &HashMap<u32, u64>,
+    constants: &Array<u64>
+) -> Result<Option<u64>, i64> {
+    let tgid = ctx.tgid();
+    if let Some(record) = processes.get_ptr_mut(&tgid)
+        && let Some(limit) = unsafe { enforced_limits.get(&tgid) }
+    {
+        let old_len = syscall_arg(ctx, 1)?;
+        let new_len = syscall_arg(ctx, 2)?;
+
+        let page_shift = *constants.get(PAGE_SHIFT_INDEX).ok_or(1i64)?;
+        let mm: *mut mm_struct = unsafe {
+            bpf_probe_read_kernel(&(*(bpf_get_current_task() as *mut task_struct)).mm)
+        }?;
+
+        let npages = page_count(new_len, page_shift).saturating_sub(page_count(old_len, page_shift));
+        if npages > 0 && exceeds_enforced_limit(mm, npages, *limit, record, page_shift)? {
+            return Ok(Some(-ENOMEM as i64 as u64));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the `n`th argument of a syscall in a kprobe of its entry point, whose only argument
+/// is the registers of the caller.
+fn syscall_arg(ctx: &ProbeContext, n: usize) -> Result<u64, i64> {
+    let regs: *const pt_regs = ctx.arg(0).ok_or(1i64)?;
+    let reg = match n {
+        0 => unsafe { &raw const (*regs).di },
+        1 => unsafe { &raw const (*regs).si },
+        2 => unsafe { &raw const (*regs).dx },
+        _ => return Err(1),
+    };
+    unsafe { bpf_probe_read_kernel(reg) }
+}
+
+/// The number of pages needed to hold `len` bytes.
+fn page_count(len: u64, page_shift: u64) -> u64 {
+    len.div_ceil(1 << page_shift)
+}
+
+fn exceeds_enforced_limit(
+    mm: *const mm_struct,
+    npages: u64,
+    limit: u64,
+    record: *mut ProcessRecord,
+    page_shift: u64
+) -> Result<bool, i64> {
+    let total_vm = unsafe {
+        bpf_probe_read_kernel(&(*mm).__bindgen_anon_1.total_vm as *const u64)
+    }?;
+    let attempted_vm = (total_vm + npages) << page_shift;
+
+    if attempted_vm > limit {
+        // Threads denied at the same time race to record the first denial, like the first
+        // hit of RLIMIT_AS in `try_on_may_expand_vm`.
+        let _ = unsafe { AtomicU64::from_ptr(&raw mut (*record).denied_vm) }
+            .compare_exchange(0, attempted_vm, Ordering::Relaxed, Ordering::Relaxed);
+
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+// Compare-and-swap loops must be bounded for the verifier. Losing this many races in a row
+// would take as many threads updating the same value at the same time.
+const MAX_CAS_ATTEMPTS: usize = 16;
+
+unsafe fn atomic_add(ptr: *mut u64, value: u64) {
+    unsafe { AtomicU64::from_ptr(ptr) }.fetch_add(value, Ordering::Relaxed);
+}
+
+/// Raises the value at `ptr` to `value`, returning whether it did.
+unsafe fn atomic_max(ptr: *mut u64, value: u64) -> bool {
+    let atomic = unsafe { AtomicU64::from_ptr(ptr) };
+    let mut current = atomic.load(Ordering::Relaxed);
+    for _ in 0..MAX_CAS_ATTEMPTS {
+        if current >= value {
+            return false;
+        }
+        match atomic.compare_exchange(current, value, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(actual) => current = actual,
+        }
+    }
+    false
+}
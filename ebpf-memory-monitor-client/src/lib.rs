@@ -0,0 +1,324 @@
+#![warn(missing_docs)]
+
+//! A client of the `ebpf-memory-monitord` daemon, with the same API as
+//! `ebpf-memory-monitor`, for processes which can't load the eBPF programs themselves.
+//!
+//! `connect` takes the place of `initialize`, and the functions return a `ClientError`
+//! instead of panicking when the daemon can't be reached.
+
+pub use ebpf_memory_monitor_protocol::wire::{Event, DEFAULT_SOCKET_PATH};
+pub use ebpf_memory_monitor_protocol::{
+    BudgetAction, MapUsage, ProcessStatus, RlimitChange, RlimitHits,
+};
+
+use ebpf_memory_monitor_protocol::wire::{
+    ErrorKind, Hello, ProtocolError, Request, Response, PROTOCOL_VERSION,
+};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{error, io};
+
+/// An error returned by the client.
+#[derive(Debug)]
+pub enum ClientError {
+    /// `connect` was not called before.
+    NotConnected,
+    /// Communicating with the daemon failed.
+    Io(io::Error),
+    /// The daemon sent a malformed message, or speaks another version of the protocol.
+    Protocol(ProtocolError),
+    /// The maps of the daemon are full, see `MonitorError::MapFull`.
+    MapFull(String),
+    /// The daemon did not allow the request, or it would exceed the quota of the client.
+    Denied(String),
+    /// The request failed in the daemon.
+    Failed(String),
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::NotConnected => write!(f, "not connected to ebpf-memory-monitord"),
+            ClientError::Io(error) => write!(f, "{error}"),
+            ClientError::Protocol(error) => write!(f, "{error}"),
+            ClientError::MapFull(message)
+            | ClientError::Denied(message)
+            | ClientError::Failed(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ClientError::Io(error) => Some(error),
+            ClientError::Protocol(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::Io(error)
+    }
+}
+
+impl From<ProtocolError> for ClientError {
+    fn from(error: ProtocolError) -> Self {
+        ClientError::Protocol(error)
+    }
+}
+
+/// A connection to the daemon.
+pub struct Client {
+    socket_path: PathBuf,
+    lines: Lines<BufReader<UnixStream>>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connects to the daemon listening on `socket_path`.
+    pub fn connect(socket_path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let socket_path = socket_path.as_ref().to_path_buf();
+        let writer = UnixStream::connect(&socket_path)?;
+        let mut client = Client {
+            socket_path,
+            lines: BufReader::new(writer.try_clone()?).lines(),
+            writer,
+        };
+
+        client.send(&Hello { version: PROTOCOL_VERSION })?;
+        expect_ok(client.receive()?)?;
+        Ok(client)
+    }
+
+    /// See `ebpf_memory_monitor::start_monitoring_process`.
+    pub fn start_monitoring_process(&mut self, pid: u32) -> Result<(), ClientError> {
+        self.request_ok(Request::Start(pid))
+    }
+
+    /// See `ebpf_memory_monitor::start_monitoring_processes`.
+    pub fn start_monitoring_processes(&mut self, pids: &[u32]) -> Result<(), ClientError> {
+        self.request_ok(Request::StartMany(pids.to_vec()))
+    }
+
+    /// See `ebpf_memory_monitor::start_monitoring_process_with_budget`.
+    pub fn start_monitoring_process_with_budget(
+        &mut self,
+        pid: u32,
+        budget_bytes: u64,
+        action: BudgetAction,
+    ) -> Result<(), ClientError> {
+        self.request_ok(Request::StartWithBudget(pid, budget_bytes, action))
+    }
+
+    /// See `ebpf_memory_monitor::set_enforced_limit`.
+    pub fn set_enforced_limit(&mut self, pid: u32, limit_bytes: u64) -> Result<(), ClientError> {
+        self.request_ok(Request::SetEnforcedLimit(pid, limit_bytes))
+    }
+
+    /// See `ebpf_memory_monitor::get_process_status`.
+    pub fn get_process_status(&mut self, pid: u32) -> Result<Option<ProcessStatus>, ClientError> {
+        Ok(self.get_process_statuses(&[pid])?.pop().flatten())
+    }
+
+    /// See `ebpf_memory_monitor::get_process_statuses`.
+    pub fn get_process_statuses(&mut self, pids: &[u32]) -> Result<Vec<Option<ProcessStatus>>, ClientError> {
+        match self.request(Request::Status(pids.to_vec()))? {
+            Response::Statuses(statuses) if statuses.len() == pids.len() => Ok(statuses),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// See `ebpf_memory_monitor::take_process_status`.
+    pub fn take_process_status(&mut self, pid: u32) -> Result<Option<ProcessStatus>, ClientError> {
+        match self.request(Request::Take(pid))? {
+            Response::Statuses(mut statuses) if statuses.len() == 1 => Ok(statuses.pop().flatten()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// See `ebpf_memory_monitor::stop_monitoring_process`.
+    pub fn stop_monitoring_process(&mut self, pid: u32) -> Result<(), ClientError> {
+        self.stop_monitoring_processes(&[pid])
+    }
+
+    /// See `ebpf_memory_monitor::stop_monitoring_processes`.
+    pub fn stop_monitoring_processes(&mut self, pids: &[u32]) -> Result<(), ClientError> {
+        self.request_ok(Request::Stop(pids.to_vec()))
+    }
+
+    /// See `ebpf_memory_monitor::map_usage`.
+    pub fn map_usage(&mut self) -> Result<MapUsage, ClientError> {
+        match self.request(Request::Usage)? {
+            Response::Usage(usage) => Ok(usage),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// See `ebpf_memory_monitor::gc`.
+    pub fn gc(&mut self, ttl: Duration) -> Result<Vec<u32>, ClientError> {
+        match self.request(Request::Gc(ttl))? {
+            Response::Pids(pids) => Ok(pids),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Opens a new connection which receives the events about the processes, until all of
+    /// them exited or are no longer monitored.
+    pub fn subscribe(&self, pids: &[u32]) -> Result<Subscription, ClientError> {
+        let mut client = Client::connect(&self.socket_path)?;
+        client.request_ok(Request::Subscribe(pids.to_vec()))?;
+        Ok(Subscription { lines: client.lines })
+    }
+
+    fn send(&mut self, message: &impl ToString) -> Result<(), ClientError> {
+        let mut line = message.to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Sends the request and returns the response, or the error sent by the daemon.
+    fn request(&mut self, request: Request) -> Result<Response, ClientError> {
+        self.send(&request)?;
+        self.receive()
+    }
+
+    fn receive(&mut self) -> Result<Response, ClientError> {
+        let line = self.lines.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "ebpf-memory-monitord closed the connection")
+        })??;
+
+        match line.parse::<Response>()? {
+            Response::Error(ErrorKind::MapFull, message) => Err(ClientError::MapFull(message)),
+            Response::Error(ErrorKind::Protocol, message) => Err(ProtocolError(message).into()),
+            Response::Error(ErrorKind::Denied, message) => Err(ClientError::Denied(message)),
+            Response::Error(ErrorKind::Failed, message) => Err(ClientError::Failed(message)),
+            response => Ok(response),
+        }
+    }
+
+    fn request_ok(&mut self, request: Request) -> Result<(), ClientError> {
+        expect_ok(self.request(request)?)
+    }
+}
+
+fn expect_ok(response: Response) -> Result<(), ClientError> {
+    match response {
+        Response::Ok => Ok(()),
+        response => Err(unexpected(response)),
+    }
+}
+
+fn unexpected(response: Response) -> ClientError {
+    ProtocolError(format!("unexpected response {response}")).into()
+}
+
+/// The events about the subscribed processes, see `Client::subscribe`.
+pub struct Subscription {
+    lines: Lines<BufReader<UnixStream>>,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Event, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(error) => return Some(Err(error.into())),
+        };
+        Some(line.parse::<Event>().map_err(Into::into))
+    }
+}
+
+static CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+/// Connects to the daemon listening on `socket_path`, for use by the other functions
+/// of this crate. Does nothing if already connected.
+pub fn connect(socket_path: impl AsRef<Path>) -> Result<(), ClientError> {
+    let mut client = CLIENT.lock().unwrap();
+    if client.is_none() {
+        *client = Some(Client::connect(socket_path)?);
+    }
+    Ok(())
+}
+
+fn with_client<R>(f: impl FnOnce(&mut Client) -> Result<R, ClientError>) -> Result<R, ClientError> {
+    let mut client = CLIENT.lock().unwrap();
+    let result = f(client.as_mut().ok_or(ClientError::NotConnected)?);
+    // The connection can't be reused once its state is unknown.
+    if let Err(ClientError::Io(_) | ClientError::Protocol(_)) = result {
+        *client = None;
+    }
+    result
+}
+
+/// See `ebpf_memory_monitor::start_monitoring_process`.
+pub fn start_monitoring_process(pid: u32) -> Result<(), ClientError> {
+    with_client(|client| client.start_monitoring_process(pid))
+}
+
+/// See `ebpf_memory_monitor::start_monitoring_processes`.
+pub fn start_monitoring_processes(pids: &[u32]) -> Result<(), ClientError> {
+    with_client(|client| client.start_monitoring_processes(pids))
+}
+
+/// See `ebpf_memory_monitor::start_monitoring_process_with_budget`.
+pub fn start_monitoring_process_with_budget(
+    pid: u32,
+    budget_bytes: u64,
+    action: BudgetAction,
+) -> Result<(), ClientError> {
+    with_client(|client| client.start_monitoring_process_with_budget(pid, budget_bytes, action))
+}
+
+/// See `ebpf_memory_monitor::set_enforced_limit`.
+pub fn set_enforced_limit(pid: u32, limit_bytes: u64) -> Result<(), ClientError> {
+    with_client(|client| client.set_enforced_limit(pid, limit_bytes))
+}
+
+/// See `ebpf_memory_monitor::get_process_status`.
+pub fn get_process_status(pid: u32) -> Result<Option<ProcessStatus>, ClientError> {
+    with_client(|client| client.get_process_status(pid))
+}
+
+/// See `ebpf_memory_monitor::get_process_statuses`.
+pub fn get_process_statuses(pids: &[u32]) -> Result<Vec<Option<ProcessStatus>>, ClientError> {
+    with_client(|client| client.get_process_statuses(pids))
+}
+
+/// See `ebpf_memory_monitor::take_process_status`.
+pub fn take_process_status(pid: u32) -> Result<Option<ProcessStatus>, ClientError> {
+    with_client(|client| client.take_process_status(pid))
+}
+
+/// See `ebpf_memory_monitor::stop_monitoring_process`.
+pub fn stop_monitoring_process(pid: u32) -> Result<(), ClientError> {
+    with_client(|client| client.stop_monitoring_process(pid))
+}
+
+/// See `ebpf_memory_monitor::stop_monitoring_processes`.
+pub fn stop_monitoring_processes(pids: &[u32]) -> Result<(), ClientError> {
+    with_client(|client| client.stop_monitoring_processes(pids))
+}
+
+/// See `ebpf_memory_monitor::map_usage`.
+pub fn map_usage() -> Result<MapUsage, ClientError> {
+    with_client(|client| client.map_usage())
+}
+
+/// See `ebpf_memory_monitor::gc`.
+pub fn gc(ttl: Duration) -> Result<Vec<u32>, ClientError> {
+    with_client(|client| client.gc(ttl))
+}
+
+/// See `Client::subscribe`.
+pub fn subscribe(pids: &[u32]) -> Result<Subscription, ClientError> {
+    with_client(|client| client.subscribe(pids))
+}
@@ -1,3 +1,111 @@
 #![no_std]
 
-pub const RLIMIT_AS_NOT_HIT: i64 = -1;
+pub const NO_BUDGET: u64 = u64::MAX;
+
+// The indices of the values in the CONSTANTS map, which are passed from userspace
+// to the eBPF programs before they are loaded.
+pub const RLIMIT_AS_INDEX: u32 = 0;
+pub const PAGE_SHIFT_INDEX: u32 = 1;
+pub const CONSTANTS_COUNT: u32 = 2;
+
+/// Per-process state of the `may_expand_vm` probe.
+///
+/// `rlimit_hits` is the number of expansions rejected because of `RLIMIT_AS`. The sizes
+/// in bytes of the first and the largest of them are stored in `first_attempted_vm` and
+/// `max_attempted_vm`, and the `CLOCK_MONOTONIC` times of the first and the last of them
+/// in `first_hit_time` and `last_hit_time`. The soft and hard `RLIMIT_AS` in effect during
+/// the largest of them are stored in `max_attempted_rlimit_cur` and `max_attempted_rlimit_max`.
+/// All of these stay `0` until the first hit.
+///
+/// `budget` is the size in bytes above which `budget_signal` is sent to the process, or
+/// `NO_BUDGET`. `budget_exceeded` is the size of the first expansion above the budget,
+/// and stays `0` until it happens.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct VmLimits {
+    pub rlimit_hits: u64,
+    pub first_attempted_vm: u64,
+    pub max_attempted_vm: u64,
+    pub max_attempted_rlimit_cur: u64,
+    pub max_attempted_rlimit_max: u64,
+    pub first_hit_time: u64,
+    pub last_hit_time: u64,
+    pub budget: u64,
+    pub budget_exceeded: u64,
+    pub budget_signal: u32,
+    pub _padding: u32,
+}
+
+impl Default for VmLimits {
+    fn default() -> Self {
+        VmLimits {
+            rlimit_hits: 0,
+            first_attempted_vm: 0,
+            max_attempted_vm: 0,
+            max_attempted_rlimit_cur: 0,
+            max_attempted_rlimit_max: 0,
+            first_hit_time: 0,
+            last_hit_time: 0,
+            budget: NO_BUDGET,
+            budget_exceeded: 0,
+            budget_signal: 0,
+            _padding: 0,
+        }
+    }
+}
+
+/// A `setrlimit` or `prlimit64` call made by or on a monitored process, sent to userspace
+/// through the `RLIMIT_CHANGES` perf event array.
+///
+/// `time` is taken from `CLOCK_MONOTONIC`. `soft` and `hard` are the requested new limits
+/// of `resource`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RlimitChangeEvent {
+    pub time: u64,
+    pub caller_tgid: u32,
+    pub target_tgid: u32,
+    pub resource: u32,
+    pub _padding: u32,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// Resource usage of a monitored process, recorded by the `do_exit` probe.
+///
+/// Every exiting thread raises `vm_peak` and adds its own CPU times and context switches
+/// to the totals. All times are in nanoseconds. `start_time` and `exit_time` are taken
+/// from `CLOCK_MONOTONIC`, and `exit_time` stays `0` until the last thread exits, when the
+/// `sched_process_exit` tracepoint sets it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExitStats {
+    pub vm_peak: u64,
+    pub utime: u64,
+    pub stime: u64,
+    pub nvcsw: u64,
+    pub nivcsw: u64,
+    pub start_time: u64,
+    pub exit_time: u64,
+}
+
+/// Everything recorded about a monitored process, stored in the `PROCESSES` map shared
+/// by all the eBPF programs, so that it's inserted and removed with a single map operation.
+///
+/// The eBPF programs update the fields in place while the process runs, so reading the
+/// record from userspace may observe some fields of an update and not the others, like a
+/// hit count already incremented with the time of the previous hit. Each field is updated
+/// atomically, and the record doesn't change anymore once `exit_stats.exit_time` is set.
+///
+/// `denied_vm` is the size in bytes of the first allocation denied by the enforced limit,
+/// and stays `0` until it happens.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessRecord {
+    pub vm_limits: VmLimits,
+    pub exit_stats: ExitStats,
+    pub denied_vm: u64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for ProcessRecord {}
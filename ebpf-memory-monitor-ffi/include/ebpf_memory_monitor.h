@@ -0,0 +1,149 @@
+#ifndef EBPF_MEMORY_MONITOR_H
+#define EBPF_MEMORY_MONITOR_H
+
+/* Generated with cbindgen from ebpf-memory-monitor-ffi, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded.
+ */
+#define EMM_OK 0
+
+/**
+ * `emm_init` was not called, or failed.
+ */
+#define EMM_ERR_NOT_INITIALIZED -1
+
+/**
+ * Loading or attaching the eBPF programs failed.
+ */
+#define EMM_ERR_INIT -2
+
+/**
+ * The maps are full.
+ */
+#define EMM_ERR_MAP_FULL -3
+
+/**
+ * The process is not monitored.
+ */
+#define EMM_ERR_NOT_MONITORED -4
+
+/**
+ * A pointer argument is null.
+ */
+#define EMM_ERR_INVALID_ARGUMENT -5
+
+/**
+ * A `bpf()` syscall failed, or the library panicked.
+ */
+#define EMM_ERR_FAILED -6
+
+/**
+ * The resource usage of a monitored process, filled by `emm_status`.
+ *
+ * The limits are updated while the process runs, and the rest is recorded when it
+ * exits, staying zero until then. The optional sizes are zero when they are missing.
+ */
+typedef struct EmmStatus {
+  /**
+   * Whether the process exited.
+   */
+  bool exited;
+  /**
+   * The peak size of the virtual memory of the process.
+   */
+  uint64_t vm_peak_bytes;
+  /**
+   * The size the virtual memory would have had if `RLIMIT_AS` did not reject the first
+   * expansion, or zero if it was never hit.
+   */
+  uint64_t attempted_vm_peak_bytes;
+  /**
+   * The number of expansions rejected because of `RLIMIT_AS`.
+   */
+  uint64_t rlimit_hits;
+  /**
+   * The size of the first expansion above the budget, or zero.
+   */
+  uint64_t budget_exceeded_bytes;
+  /**
+   * The limit enforced with BPF-LSM, or zero.
+   */
+  uint64_t enforced_limit_bytes;
+  /**
+   * The size of the first allocation above the enforced limit, or zero.
+   */
+  uint64_t denied_vm_peak_bytes;
+  /**
+   * Time spent in user mode by the exited threads of the process, in nanoseconds.
+   */
+  uint64_t user_time_ns;
+  /**
+   * Time spent in kernel mode by the exited threads of the process, in nanoseconds.
+   */
+  uint64_t system_time_ns;
+  /**
+   * Number of voluntary context switches of the exited threads of the process.
+   */
+  uint64_t voluntary_context_switches;
+  /**
+   * Number of involuntary context switches of the exited threads of the process.
+   */
+  uint64_t involuntary_context_switches;
+  /**
+   * The `CLOCK_MONOTONIC` time at which the process was started, in nanoseconds.
+   */
+  uint64_t start_time_ns;
+  /**
+   * The `CLOCK_MONOTONIC` time at which the process exited, in nanoseconds.
+   */
+  uint64_t exit_time_ns;
+} EmmStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Loads and attaches the eBPF programs, with maps for `max_listeners` processes. Does
+ * nothing if the library is already initialized.
+ *
+ * Requires the `CAP_SYS_RESOURCE`, `CAP_BPF` and `CAP_PERFMON` capabilities.
+ */
+int emm_init(uint32_t max_listeners);
+
+/**
+ * Starts monitoring the process.
+ */
+int emm_start(uint32_t pid);
+
+/**
+ * Stops monitoring the process, discarding its status. Does nothing if it's not monitored.
+ */
+int emm_stop(uint32_t pid);
+
+/**
+ * Fills `status` with the status of the process, which stays monitored.
+ *
+ * # Safety
+ *
+ * `status` must be null or point to a writable `EmmStatus`.
+ */
+int emm_status(uint32_t pid, struct EmmStatus *status);
+
+/**
+ * Returns a static description of an error code.
+ */
+const char *emm_strerror(int error);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EBPF_MEMORY_MONITOR_H */
@@ -0,0 +1,165 @@
+//! A C ABI for `ebpf-memory-monitor`, for supervisors which are not written in Rust.
+//!
+//! The functions return `EMM_OK` or one of the negative `EMM_ERR_*` codes, which
+//! `emm_strerror` describes. The header is generated with cbindgen, see `cbindgen.toml`.
+
+use ebpf_memory_monitor::init::{backend_info, initialize, InitOptions};
+use ebpf_memory_monitor::{
+    get_process_status, start_monitoring_process, stop_monitoring_process, MonitorError,
+    ProcessStatus,
+};
+use libc::{c_char, c_int};
+use std::ffi::CStr;
+use std::panic::{catch_unwind, UnwindSafe};
+use std::time::Duration;
+
+/// The call succeeded.
+pub const EMM_OK: c_int = 0;
+/// `emm_init` was not called, or failed.
+pub const EMM_ERR_NOT_INITIALIZED: c_int = -1;
+/// Loading or attaching the eBPF programs failed.
+pub const EMM_ERR_INIT: c_int = -2;
+/// The maps are full.
+pub const EMM_ERR_MAP_FULL: c_int = -3;
+/// The process is not monitored.
+pub const EMM_ERR_NOT_MONITORED: c_int = -4;
+/// A pointer argument is null.
+pub const EMM_ERR_INVALID_ARGUMENT: c_int = -5;
+/// A `bpf()` syscall failed, or the library panicked.
+pub const EMM_ERR_FAILED: c_int = -6;
+
+/// The resource usage of a monitored process, filled by `emm_status`.
+///
+/// The limits are updated while the process runs, and the rest is recorded when it
+/// exits, staying zero until then. The optional sizes are zero when they are missing.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EmmStatus {
+    /// Whether the process exited.
+    pub exited: bool,
+    /// The peak size of the virtual memory of the process.
+    pub vm_peak_bytes: u64,
+    /// The size the virtual memory would have had if `RLIMIT_AS` did not reject the first
+    /// expansion, or zero if it was never hit.
+    pub attempted_vm_peak_bytes: u64,
+    /// The number of expansions rejected because of `RLIMIT_AS`.
+    pub rlimit_hits: u64,
+    /// The size of the first expansion above the budget, or zero.
+    pub budget_exceeded_bytes: u64,
+    /// The limit enforced with BPF-LSM, or zero.
+    pub enforced_limit_bytes: u64,
+    /// The size of the first allocation above the enforced limit, or zero.
+    pub denied_vm_peak_bytes: u64,
+    /// Time spent in user mode by the exited threads of the process, in nanoseconds.
+    pub user_time_ns: u64,
+    /// Time spent in kernel mode by the exited threads of the process, in nanoseconds.
+    pub system_time_ns: u64,
+    /// Number of voluntary context switches of the exited threads of the process.
+    pub voluntary_context_switches: u64,
+    /// Number of involuntary context switches of the exited threads of the process.
+    pub involuntary_context_switches: u64,
+    /// The `CLOCK_MONOTONIC` time at which the process was started, in nanoseconds.
+    pub start_time_ns: u64,
+    /// The `CLOCK_MONOTONIC` time at which the process exited, in nanoseconds.
+    pub exit_time_ns: u64,
+}
+
+impl From<&ProcessStatus> for EmmStatus {
+    fn from(status: &ProcessStatus) -> Self {
+        let nanos = |duration: Duration| u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        EmmStatus {
+            exited: status.exit_time.is_some(),
+            vm_peak_bytes: status.vm_peak_bytes,
+            attempted_vm_peak_bytes: status.attempted_vm_peak_bytes.unwrap_or(0),
+            rlimit_hits: status.rlimit_hits.as_ref().map_or(0, |hits| hits.count),
+            budget_exceeded_bytes: status.budget_exceeded_bytes.unwrap_or(0),
+            enforced_limit_bytes: status.enforced_limit_bytes.unwrap_or(0),
+            denied_vm_peak_bytes: status.denied_vm_peak_bytes.unwrap_or(0),
+            user_time_ns: nanos(status.user_time),
+            system_time_ns: nanos(status.system_time),
+            voluntary_context_switches: status.voluntary_context_switches,
+            involuntary_context_switches: status.involuntary_context_switches,
+            start_time_ns: status.start_time.map_or(0, nanos),
+            exit_time_ns: status.exit_time.map_or(0, nanos),
+        }
+    }
+}
+
+/// Runs `f` if the library is initialized, as its functions panic otherwise. Panics must
+/// not unwind into C.
+fn call(f: impl FnOnce() -> c_int + UnwindSafe) -> c_int {
+    if backend_info().is_none() {
+        return EMM_ERR_NOT_INITIALIZED;
+    }
+    catch_unwind(f).unwrap_or(EMM_ERR_FAILED)
+}
+
+fn error_code(result: Result<(), MonitorError>) -> c_int {
+    match result {
+        Ok(()) => EMM_OK,
+        Err(MonitorError::MapFull { .. }) => EMM_ERR_MAP_FULL,
+        Err(_) => EMM_ERR_FAILED,
+    }
+}
+
+/// Loads and attaches the eBPF programs, with maps for `max_listeners` processes. Does
+/// nothing if the library is already initialized.
+///
+/// Requires the `CAP_SYS_RESOURCE`, `CAP_BPF` and `CAP_PERFMON` capabilities.
+#[unsafe(no_mangle)]
+pub extern "C" fn emm_init(max_listeners: u32) -> c_int {
+    match catch_unwind(|| initialize(InitOptions::new(max_listeners))) {
+        Ok(Ok(())) => EMM_OK,
+        _ => EMM_ERR_INIT,
+    }
+}
+
+/// Starts monitoring the process.
+#[unsafe(no_mangle)]
+pub extern "C" fn emm_start(pid: u32) -> c_int {
+    call(|| error_code(start_monitoring_process(pid)))
+}
+
+/// Stops monitoring the process, discarding its status. Does nothing if it's not monitored.
+#[unsafe(no_mangle)]
+pub extern "C" fn emm_stop(pid: u32) -> c_int {
+    call(|| {
+        stop_monitoring_process(pid);
+        EMM_OK
+    })
+}
+
+/// Fills `status` with the status of the process, which stays monitored.
+///
+/// # Safety
+///
+/// `status` must be null or point to a writable `EmmStatus`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn emm_status(pid: u32, status: *mut EmmStatus) -> c_int {
+    if status.is_null() {
+        return EMM_ERR_INVALID_ARGUMENT;
+    }
+    call(|| match get_process_status(pid) {
+        Some(process_status) => {
+            unsafe { status.write(EmmStatus::from(&process_status)) };
+            EMM_OK
+        }
+        None => EMM_ERR_NOT_MONITORED,
+    })
+}
+
+/// Returns a static description of an error code.
+#[unsafe(no_mangle)]
+pub extern "C" fn emm_strerror(error: c_int) -> *const c_char {
+    let message: &'static CStr = match error {
+        EMM_OK => c"success",
+        EMM_ERR_NOT_INITIALIZED => c"ebpf-memory-monitor is not initialized",
+        EMM_ERR_INIT => c"loading the eBPF programs failed",
+        EMM_ERR_MAP_FULL => c"the maps are full",
+        EMM_ERR_NOT_MONITORED => c"the process is not monitored",
+        EMM_ERR_INVALID_ARGUMENT => c"invalid argument",
+        EMM_ERR_FAILED => c"the operation failed",
+        _ => c"unknown error",
+    };
+    message.as_ptr()
+}
@@ -0,0 +1,135 @@
+/*
+ * Exercises the C ABI of ebpf-memory-monitor. Must be run as root, see the Makefile.
+ */
+
+#define _GNU_SOURCE
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/types.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "ebpf_memory_monitor.h"
+
+/* The size mapped by the child, which its VM peak must be above. */
+#define CHILD_MAPPING_BYTES (64u << 20)
+
+static int failures = 0;
+
+#define EXPECT(condition)                                                     \
+    do {                                                                      \
+        if (!(condition)) {                                                   \
+            fprintf(stderr, "%s:%d: expected %s\n", __FILE__, __LINE__,       \
+                    #condition);                                              \
+            failures++;                                                       \
+        }                                                                     \
+    } while (0)
+
+#define EXPECT_CODE(call, expected)                                           \
+    do {                                                                      \
+        int code = (call);                                                    \
+        if (code != (expected)) {                                             \
+            fprintf(stderr, "%s:%d: %s returned %d (%s), expected %s\n",      \
+                    __FILE__, __LINE__, #call, code, emm_strerror(code),      \
+                    #expected);                                               \
+            failures++;                                                       \
+        }                                                                     \
+    } while (0)
+
+/*
+ * Forks a child which waits until a byte is written to the returned pipe, maps
+ * CHILD_MAPPING_BYTES and exits, so that it can be monitored from the start.
+ */
+static pid_t spawn_child(int *go) {
+    int fds[2];
+    if (pipe(fds) != 0) {
+        perror("pipe");
+        exit(1);
+    }
+
+    pid_t pid = fork();
+    if (pid < 0) {
+        perror("fork");
+        exit(1);
+    }
+    if (pid == 0) {
+        char byte;
+        close(fds[1]);
+        if (read(fds[0], &byte, 1) != 1) {
+            _exit(1);
+        }
+        void *mapping = mmap(NULL, CHILD_MAPPING_BYTES, PROT_READ | PROT_WRITE,
+                             MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+        _exit(mapping == MAP_FAILED ? 1 : 0);
+    }
+
+    close(fds[0]);
+    *go = fds[1];
+    return pid;
+}
+
+int main(void) {
+    struct EmmStatus status;
+
+    /* Nothing works before emm_init. */
+    EXPECT_CODE(emm_start(getpid()), EMM_ERR_NOT_INITIALIZED);
+    EXPECT_CODE(emm_status(getpid(), &status), EMM_ERR_NOT_INITIALIZED);
+
+    int code = emm_init(64);
+    if (code != EMM_OK) {
+        fprintf(stderr, "emm_init failed: %s, is the test run as root?\n", emm_strerror(code));
+        return 1;
+    }
+    /* Initializing twice does nothing. */
+    EXPECT_CODE(emm_init(64), EMM_OK);
+
+    EXPECT_CODE(emm_status(getpid(), NULL), EMM_ERR_INVALID_ARGUMENT);
+    EXPECT_CODE(emm_status(getpid(), &status), EMM_ERR_NOT_MONITORED);
+
+    int go;
+    pid_t child = spawn_child(&go);
+    EXPECT_CODE(emm_start(child), EMM_OK);
+
+    /* The status is available while the child runs. */
+    memset(&status, 0xff, sizeof(status));
+    EXPECT_CODE(emm_status(child, &status), EMM_OK);
+    EXPECT(!status.exited);
+    EXPECT(status.exit_time_ns == 0);
+
+    if (write(go, "", 1) != 1) {
+        perror("write");
+        return 1;
+    }
+    close(go);
+    int wait_status;
+    if (waitpid(child, &wait_status, 0) != child) {
+        perror("waitpid");
+        return 1;
+    }
+    EXPECT(WIFEXITED(wait_status) && WEXITSTATUS(wait_status) == 0);
+
+    EXPECT_CODE(emm_status(child, &status), EMM_OK);
+    EXPECT(status.exited);
+    EXPECT(status.vm_peak_bytes >= CHILD_MAPPING_BYTES);
+    EXPECT(status.attempted_vm_peak_bytes == 0);
+    EXPECT(status.rlimit_hits == 0);
+    EXPECT(status.exit_time_ns >= status.start_time_ns);
+
+    EXPECT_CODE(emm_stop(child), EMM_OK);
+    EXPECT_CODE(emm_status(child, &status), EMM_ERR_NOT_MONITORED);
+    /* Stopping a process which is not monitored does nothing. */
+    EXPECT_CODE(emm_stop(child), EMM_OK);
+
+    EXPECT(strcmp(emm_strerror(EMM_ERR_MAP_FULL), "the maps are full") == 0);
+    EXPECT(strcmp(emm_strerror(1), "unknown error") == 0);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all checks passed\n");
+    return 0;
+}
@@ -0,0 +1,24 @@
+//! Checks that `include/ebpf_memory_monitor.h` is the header cbindgen generates from the
+//! current API, so that it can't be left outdated by a change to `src/lib.rs`.
+
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn header_is_up_to_date() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::Builder::new()
+        .with_crate(crate_dir)
+        .with_config(config)
+        .generate()
+        .unwrap()
+        .write(&mut generated);
+
+    let committed = fs::read(crate_dir.join("include/ebpf_memory_monitor.h")).unwrap();
+    assert!(
+        generated == committed,
+        "include/ebpf_memory_monitor.h is outdated, regenerate it as described in cbindgen.toml",
+    );
+}
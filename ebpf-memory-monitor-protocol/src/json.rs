@@ -0,0 +1,234 @@
+//! The JSON representation of the types, with the `serde` feature.
+//!
+//! The representation of `ProcessStatus` is described by the JSON Schema in
+//! `schema/process-status.v1.schema.json`, and versioned by `JSON_SCHEMA_VERSION`. Fields
+//! may be added within a version, but are only renamed, removed or changed in a new one.
+//!
+//! - Sizes are integers of bytes, in the fields ending in `_bytes`.
+//! - Durations and `CLOCK_MONOTONIC` times are integers of nanoseconds, in the fields
+//!   ending in `_ns`.
+//! - Missing optional values are `null`, and are never omitted.
+//! - `BudgetAction` is one of the strings `"kill"`, `"cpu_limit_exceeded"` and `"user1"`.
+//! - `ProcessStatus` has a `schema_version` field, which is `JSON_SCHEMA_VERSION`. Other
+//!   versions are rejected when deserializing.
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use crate::ProcessStatus;
+
+/// The version of the JSON representation.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+impl Serialize for ProcessStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Versioned<'a> {
+            schema_version: u32,
+            #[serde(flatten, serialize_with = "ProcessStatus::serialize")]
+            status: &'a ProcessStatus,
+        }
+
+        Versioned { schema_version: JSON_SCHEMA_VERSION, status: self }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProcessStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            schema_version: u32,
+            #[serde(flatten, deserialize_with = "ProcessStatus::deserialize")]
+            status: ProcessStatus,
+        }
+
+        let versioned = Versioned::deserialize(deserializer)?;
+        if versioned.schema_version != JSON_SCHEMA_VERSION {
+            return Err(D::Error::custom(format!(
+                "unsupported schema_version {}, expected {JSON_SCHEMA_VERSION}",
+                versioned.schema_version
+            )));
+        }
+        Ok(versioned.status)
+    }
+}
+
+/// Represents a `Duration` as an integer of nanoseconds.
+pub(crate) mod nanos {
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub(crate) fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let nanos = u64::try_from(duration.as_nanos()).map_err(|_| S::Error::custom("duration too large"))?;
+        serializer.serialize_u64(nanos)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer)
+            .map(Duration::from_nanos)
+            .map_err(|error| D::Error::custom(format!("expected nanoseconds: {error}")))
+    }
+}
+
+/// Represents an `Option<Duration>` as an integer of nanoseconds or `null`.
+pub(crate) mod option_nanos {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub(crate) fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::nanos::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_nanos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RlimitChange, RlimitHits};
+    use serde_json::{json, Map, Value};
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    const SCHEMA: &str = include_str!("../schema/process-status.v1.schema.json");
+
+    fn complete_status() -> ProcessStatus {
+        ProcessStatus {
+            vm_peak_bytes: 1 << 40,
+            attempted_vm_peak_bytes: Some(3 << 30),
+            rlimit_hits: Some(RlimitHits {
+                count: 2,
+                first_attempted_bytes: 3 << 30,
+                max_attempted_bytes: 5 << 30,
+                first_hit_time: Duration::from_nanos(1_000_000_001),
+                last_hit_time: Duration::from_nanos(2_000_000_002),
+                max_attempted_soft_limit: 2 << 30,
+                max_attempted_hard_limit: u64::MAX,
+            }),
+            rlimit_changes: vec![RlimitChange {
+                time: Duration::from_nanos(999),
+                caller_pid: 1,
+                target_pid: u32::MAX,
+                resource: 9,
+                soft_limit: 2 << 30,
+                hard_limit: u64::MAX,
+            }],
+            rlimit_changes_lost: true,
+            budget_exceeded_bytes: Some(4 << 30),
+            enforced_limit_bytes: Some(6 << 30),
+            denied_vm_peak_bytes: Some(7 << 30),
+            user_time: Duration::new(12, 345_678_901),
+            system_time: Duration::from_nanos(1),
+            voluntary_context_switches: 10,
+            involuntary_context_switches: 20,
+            // Above 2^53, so it would lose precision as a float.
+            start_time: Some(Duration::from_nanos((1 << 60) + 1)),
+            exit_time: Some(Duration::from_nanos(u64::MAX)),
+        }
+    }
+
+    fn empty_status() -> ProcessStatus {
+        ProcessStatus {
+            vm_peak_bytes: 0,
+            attempted_vm_peak_bytes: None,
+            rlimit_hits: None,
+            rlimit_changes: Vec::new(),
+            rlimit_changes_lost: false,
+            budget_exceeded_bytes: None,
+            enforced_limit_bytes: None,
+            denied_vm_peak_bytes: None,
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            voluntary_context_switches: 0,
+            involuntary_context_switches: 0,
+            start_time: None,
+            exit_time: None,
+        }
+    }
+
+    fn keys(object: &Map<String, Value>) -> BTreeSet<&str> {
+        object.keys().map(String::as_str).collect()
+    }
+
+    // Checks that the object has exactly the properties of the schema, and all the
+    // required ones.
+    fn check_fields(object: &Value, schema: &Value) {
+        let object = object.as_object().unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        assert_eq!(keys(object), keys(properties));
+        for required in schema["required"].as_array().unwrap() {
+            assert!(object.contains_key(required.as_str().unwrap()), "{required} is missing");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for status in [complete_status(), empty_status()] {
+            let json = serde_json::to_string(&status).unwrap();
+            assert_eq!(serde_json::from_str::<ProcessStatus>(&json).unwrap(), status);
+        }
+    }
+
+    #[test]
+    fn field_names_match_the_schema() {
+        let schema: Value = serde_json::from_str(SCHEMA).unwrap();
+        let value = serde_json::to_value(complete_status()).unwrap();
+
+        check_fields(&value, &schema);
+        check_fields(&value["rlimit_hits"], &schema["$defs"]["rlimit_hits"]);
+        check_fields(&value["rlimit_changes"][0], &schema["$defs"]["rlimit_change"]);
+        assert_eq!(value["schema_version"], schema["properties"]["schema_version"]["const"]);
+        check_fields(&serde_json::to_value(empty_status()).unwrap(), &schema);
+    }
+
+    #[test]
+    fn missing_values_are_null() {
+        let value = serde_json::to_value(empty_status()).unwrap();
+        for field in [
+            "attempted_vm_peak_bytes",
+            "rlimit_hits",
+            "budget_exceeded_bytes",
+            "enforced_limit_bytes",
+            "denied_vm_peak_bytes",
+            "start_time_ns",
+            "exit_time_ns",
+        ] {
+            assert_eq!(value.get(field), Some(&Value::Null), "{field}");
+        }
+        assert_eq!(value["rlimit_changes"], json!([]));
+    }
+
+    #[test]
+    fn times_are_integers_of_nanoseconds() {
+        let value = serde_json::to_value(complete_status()).unwrap();
+        assert_eq!(value["user_time_ns"].as_u64(), Some(12_345_678_901));
+        assert_eq!(value["system_time_ns"].as_u64(), Some(1));
+        assert_eq!(value["start_time_ns"].as_u64(), Some((1 << 60) + 1));
+        assert_eq!(value["exit_time_ns"].as_u64(), Some(u64::MAX));
+        assert_eq!(value["rlimit_hits"]["first_hit_time_ns"].as_u64(), Some(1_000_000_001));
+        assert_eq!(value["rlimit_changes"][0]["time_ns"].as_u64(), Some(999));
+
+        // Durations which don't fit in a u64 of nanoseconds are rejected.
+        let mut status = empty_status();
+        status.user_time = Duration::MAX;
+        assert!(serde_json::to_value(status).is_err());
+    }
+
+    #[test]
+    fn schema_version() {
+        let mut value = serde_json::to_value(complete_status()).unwrap();
+        assert_eq!(value["schema_version"], json!(JSON_SCHEMA_VERSION));
+
+        let mut unversioned = value.clone();
+        unversioned.as_object_mut().unwrap().remove("schema_version");
+        let error = serde_json::from_value::<ProcessStatus>(unversioned).unwrap_err();
+        assert!(error.to_string().contains("missing field `schema_version`"), "{error}");
+
+        value["schema_version"] = json!(2);
+        let error = serde_json::from_value::<ProcessStatus>(value).unwrap_err();
+        assert!(error.to_string().contains("unsupported schema_version 2"), "{error}");
+    }
+}
@@ -0,0 +1,144 @@
+#![warn(missing_docs)]
+
+//! The types shared by `ebpf-memory-monitor`, the `ebpf-memory-monitord` daemon and its
+//! client, and the wire protocol spoken between the daemon and the client.
+//!
+//! With the `serde` feature, the types implement `Serialize` and `Deserialize`, with the
+//! JSON representation documented in the `json` module.
+
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod wire;
+
+use std::time::Duration;
+
+/// The signal sent to a monitored process when it exceeds its memory budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum BudgetAction {
+    /// Send `SIGKILL`.
+    Kill,
+    /// Send `SIGXCPU`.
+    CpuLimitExceeded,
+    /// Send `SIGUSR1`.
+    User1,
+}
+
+/// A `setrlimit` or `prlimit64` call made by or on a monitored process.
+///
+/// The call is recorded when it's made, so it's also recorded if it fails later,
+/// for example because of missing permissions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RlimitChange {
+    /// The `CLOCK_MONOTONIC` time of the call.
+    #[cfg_attr(feature = "serde", serde(rename = "time_ns", with = "json::nanos"))]
+    pub time: Duration,
+    /// The PID of the process which made the call.
+    pub caller_pid: u32,
+    /// The PID of the process whose limit was changed.
+    pub target_pid: u32,
+    /// The changed resource, one of the `RLIMIT_*` constants.
+    pub resource: u32,
+    /// The requested soft limit.
+    pub soft_limit: u64,
+    /// The requested hard limit.
+    pub hard_limit: u64,
+}
+
+/// The expansions of the virtual memory of a process rejected because of `RLIMIT_AS`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RlimitHits {
+    /// The number of rejected expansions.
+    pub count: u64,
+    /// The size the virtual memory would have had after the first rejected expansion.
+    pub first_attempted_bytes: u64,
+    /// The size the virtual memory would have had after the largest rejected expansion.
+    pub max_attempted_bytes: u64,
+    /// The `CLOCK_MONOTONIC` time of the first rejected expansion.
+    #[cfg_attr(feature = "serde", serde(rename = "first_hit_time_ns", with = "json::nanos"))]
+    pub first_hit_time: Duration,
+    /// The `CLOCK_MONOTONIC` time of the last rejected expansion.
+    #[cfg_attr(feature = "serde", serde(rename = "last_hit_time_ns", with = "json::nanos"))]
+    pub last_hit_time: Duration,
+    /// The soft `RLIMIT_AS` in effect during the largest rejected expansion.
+    pub max_attempted_soft_limit: u64,
+    /// The hard `RLIMIT_AS` in effect during the largest rejected expansion.
+    pub max_attempted_hard_limit: u64,
+}
+
+/// Resource usage of a monitored process.
+///
+/// The fields describing the limits are updated while the process runs, and the rest
+/// is recorded as its threads exit, staying zero (or `None`) until then. They are final
+/// once `exit_time` is set, which happens when the last thread exits. Until then, a status
+/// may be read in the middle of an update, with some of its fields not updated yet.
+///
+/// With the `serde` feature, its JSON representation also has a `schema_version` field,
+/// see the `json` module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+// The derived functions are wrapped by the implementations in `json`, which add the version.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(remote = "Self"))]
+pub struct ProcessStatus {
+    /// The peak size of the virtual memory of the process.
+    pub vm_peak_bytes: u64,
+    /// The size the virtual memory would have had if `RLIMIT_AS` did not reject the first
+    /// expansion, if it was ever hit. Same as `RlimitHits::first_attempted_bytes`.
+    pub attempted_vm_peak_bytes: Option<u64>,
+    /// All expansions rejected because of `RLIMIT_AS`, if there were any.
+    pub rlimit_hits: Option<RlimitHits>,
+    /// The `setrlimit` and `prlimit64` calls made by or on the process. Only recorded
+    /// if `InitOptions::log_rlimit_changes` was enabled.
+    pub rlimit_changes: Vec<RlimitChange>,
+    /// Whether some of the calls may be missing from `rlimit_changes`, because they were
+    /// made while the buffers they are sent through were full.
+    pub rlimit_changes_lost: bool,
+    /// The size of the first expansion of the virtual memory above the budget set with
+    /// `start_monitoring_process_with_budget`, if there was one.
+    pub budget_exceeded_bytes: Option<u64>,
+    /// The limit set with `set_enforced_limit`, if there is one.
+    pub enforced_limit_bytes: Option<u64>,
+    /// The size of the first allocation above the limit set with `set_enforced_limit`,
+    /// if there was one.
+    pub denied_vm_peak_bytes: Option<u64>,
+    /// Time spent in user mode by the threads of the process which exited while it was
+    /// monitored.
+    #[cfg_attr(feature = "serde", serde(rename = "user_time_ns", with = "json::nanos"))]
+    pub user_time: Duration,
+    /// Time spent in kernel mode by the threads of the process which exited while it was
+    /// monitored.
+    #[cfg_attr(feature = "serde", serde(rename = "system_time_ns", with = "json::nanos"))]
+    pub system_time: Duration,
+    /// Number of voluntary context switches of the threads of the process which exited
+    /// while it was monitored.
+    pub voluntary_context_switches: u64,
+    /// Number of involuntary context switches of the threads of the process which exited
+    /// while it was monitored.
+    pub involuntary_context_switches: u64,
+    /// The `CLOCK_MONOTONIC` time at which the process was started.
+    #[cfg_attr(feature = "serde", serde(rename = "start_time_ns", with = "json::option_nanos"))]
+    pub start_time: Option<Duration>,
+    /// The `CLOCK_MONOTONIC` time at which the last thread of the process exited.
+    #[cfg_attr(feature = "serde", serde(rename = "exit_time_ns", with = "json::option_nanos"))]
+    pub exit_time: Option<Duration>,
+}
+
+impl ProcessStatus {
+    /// Returns the wall-clock time between the start and the exit of the process.
+    pub fn wall_time(&self) -> Option<Duration> {
+        Some(self.exit_time?.saturating_sub(self.start_time?))
+    }
+}
+
+/// The occupancy of the maps used for monitoring the processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MapUsage {
+    /// The number of monitored processes.
+    pub processes: usize,
+    /// The number of limits set with `set_enforced_limit`, if LSM enforcement is enabled.
+    pub enforced_limits: Option<usize>,
+    /// The maximum number of entries of each map.
+    pub max_entries: u32,
+}
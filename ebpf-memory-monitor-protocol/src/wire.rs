@@ -0,0 +1,689 @@
+//! The line-based protocol spoken over the Unix socket of `ebpf-memory-monitord`.
+//!
+//! Every message is a single line of words separated by spaces. A connection starts with
+//! the client sending `HELLO <version>`, to which the daemon answers `OK` if it speaks
+//! that version of the protocol, or `ERR protocol <message>` before closing the
+//! connection. Then the client sends `Request`s, and the daemon answers each of them
+//! with a `Response`. After answering `SUBSCRIBE` with `OK`, the daemon only sends
+//! `Event`s on the connection.
+//!
+//! Durations are sent as nanoseconds, and missing values as `-`. A `ProcessStatus` is
+//! sent as a single word of comma-separated `key=value` pairs.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+use crate::{BudgetAction, MapUsage, ProcessStatus, RlimitChange, RlimitHits};
+
+/// The version of the protocol, incremented on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The path of the socket the daemon listens on by default.
+pub const DEFAULT_SOCKET_PATH: &str = "/run/ebpf-memory-monitord.sock";
+
+/// A malformed message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolError(pub String);
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "protocol error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// The first message of a connection, sent by the client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hello {
+    /// The version of the protocol spoken by the client.
+    pub version: u32,
+}
+
+/// A request sent by the client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// `start_monitoring_process`, answered with `Response::Ok`.
+    Start(u32),
+    /// `start_monitoring_process_with_budget`, answered with `Response::Ok`.
+    StartWithBudget(u32, u64, BudgetAction),
+    /// `start_monitoring_processes`, answered with `Response::Ok`.
+    StartMany(Vec<u32>),
+    /// `set_enforced_limit`, answered with `Response::Ok`.
+    SetEnforcedLimit(u32, u64),
+    /// `get_process_statuses`, answered with `Response::Statuses`.
+    Status(Vec<u32>),
+    /// `take_process_status`, answered with `Response::Statuses`.
+    Take(u32),
+    /// `stop_monitoring_processes`, answered with `Response::Ok`.
+    Stop(Vec<u32>),
+    /// `map_usage`, answered with `Response::Usage`.
+    Usage,
+    /// `gc`, answered with `Response::Pids`.
+    Gc(Duration),
+    /// Turns the connection into a stream of `Event`s about the processes, answered with
+    /// `Response::Ok` before the first event.
+    Subscribe(Vec<u32>),
+}
+
+/// The kind of a failed request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The maps are full, see `MonitorError::MapFull`.
+    MapFull,
+    /// The request was malformed.
+    Protocol,
+    /// The request is not allowed for this client, or would exceed its quota.
+    Denied,
+    /// The request failed for another reason.
+    Failed,
+}
+
+/// A response of the daemon to a `Request`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    /// The request succeeded.
+    Ok,
+    /// The statuses of the requested processes, `None` for the ones which are not monitored.
+    Statuses(Vec<Option<ProcessStatus>>),
+    /// The occupancy of the maps.
+    Usage(MapUsage),
+    /// The PIDs reclaimed by `Request::Gc`.
+    Pids(Vec<u32>),
+    /// The request failed.
+    Error(ErrorKind, String),
+}
+
+/// An event sent after `Request::Subscribe`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The process hit its `RLIMIT_AS`, and the number of hits so far.
+    RlimitHit(u32, u64),
+    /// The process exceeded its budget, with the size of the expansion.
+    BudgetExceeded(u32, u64),
+    /// An allocation of the process was denied by the enforced limit, with its size.
+    Denied(u32, u64),
+    /// The process exited, with its final status.
+    Exited(u32, Box<ProcessStatus>),
+    /// The process is no longer monitored, and no more events will be sent about it.
+    Stopped(u32),
+}
+
+impl Display for Hello {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HELLO {}", self.version)
+    }
+}
+
+impl FromStr for Hello {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = Words::new(line);
+        words.expect("HELLO")?;
+        let version = words.parse()?;
+        words.end()?;
+        Ok(Hello { version })
+    }
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Request::Start(pid) => write!(f, "START {pid}"),
+            Request::StartWithBudget(pid, budget, action) => {
+                write!(f, "START_BUDGET {pid} {budget} {}", encode_action(*action))
+            }
+            Request::StartMany(pids) => write!(f, "START_MANY{}", List(pids)),
+            Request::SetEnforcedLimit(pid, limit) => write!(f, "LIMIT {pid} {limit}"),
+            Request::Status(pids) => write!(f, "STATUS{}", List(pids)),
+            Request::Take(pid) => write!(f, "TAKE {pid}"),
+            Request::Stop(pids) => write!(f, "STOP{}", List(pids)),
+            Request::Usage => write!(f, "USAGE"),
+            Request::Gc(ttl) => write!(f, "GC {}", ttl.as_nanos()),
+            Request::Subscribe(pids) => write!(f, "SUBSCRIBE{}", List(pids)),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = Words::new(line);
+        let request = match words.next()? {
+            "START" => Request::Start(words.parse()?),
+            "START_BUDGET" => Request::StartWithBudget(
+                words.parse()?,
+                words.parse()?,
+                decode_action(words.next()?)?,
+            ),
+            "START_MANY" => Request::StartMany(words.rest()?),
+            "LIMIT" => Request::SetEnforcedLimit(words.parse()?, words.parse()?),
+            "STATUS" => Request::Status(words.rest()?),
+            "TAKE" => Request::Take(words.parse()?),
+            "STOP" => Request::Stop(words.rest()?),
+            "USAGE" => Request::Usage,
+            "GC" => Request::Gc(Duration::from_nanos(words.parse()?)),
+            "SUBSCRIBE" => Request::Subscribe(words.rest()?),
+            command => return Err(ProtocolError(format!("unknown request {command}"))),
+        };
+        words.end()?;
+        Ok(request)
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Response::Ok => write!(f, "OK"),
+            Response::Statuses(statuses) => {
+                write!(f, "STATUSES")?;
+                for status in statuses {
+                    match status {
+                        Some(status) => write!(f, " {}", EncodedStatus(status))?,
+                        None => write!(f, " -")?,
+                    }
+                }
+                Ok(())
+            }
+            Response::Usage(usage) => write!(
+                f,
+                "USAGE {} {} {}",
+                usage.processes,
+                Opt(usage.enforced_limits),
+                usage.max_entries,
+            ),
+            Response::Pids(pids) => write!(f, "PIDS{}", List(pids)),
+            Response::Error(kind, message) => {
+                // The message is the rest of the line, so it must not contain a newline.
+                write!(f, "ERR {} {}", encode_error_kind(*kind), message.replace('\n', " "))
+            }
+        }
+    }
+}
+
+impl FromStr for Response {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = Words::new(line);
+        let response = match words.next()? {
+            "OK" => Response::Ok,
+            "STATUSES" => Response::Statuses(
+                words.by_ref()
+                    .map(|word| match word {
+                        "-" => Ok(None),
+                        word => decode_status(word).map(Some),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            "USAGE" => Response::Usage(MapUsage {
+                processes: words.parse()?,
+                enforced_limits: words.parse_opt()?,
+                max_entries: words.parse()?,
+            }),
+            "PIDS" => Response::Pids(words.rest()?),
+            "ERR" => {
+                let kind = decode_error_kind(words.next()?)?;
+                let message = line.splitn(3, ' ').nth(2).unwrap_or_default();
+                return Ok(Response::Error(kind, message.to_string()));
+            }
+            response => return Err(ProtocolError(format!("unknown response {response}"))),
+        };
+        words.end()?;
+        Ok(response)
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::RlimitHit(pid, count) => write!(f, "RLIMIT_HIT {pid} {count}"),
+            Event::BudgetExceeded(pid, bytes) => write!(f, "BUDGET_EXCEEDED {pid} {bytes}"),
+            Event::Denied(pid, bytes) => write!(f, "DENIED {pid} {bytes}"),
+            Event::Exited(pid, status) => write!(f, "EXITED {pid} {}", EncodedStatus(status)),
+            Event::Stopped(pid) => write!(f, "STOPPED {pid}"),
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = Words::new(line);
+        let event = match words.next()? {
+            "RLIMIT_HIT" => Event::RlimitHit(words.parse()?, words.parse()?),
+            "BUDGET_EXCEEDED" => Event::BudgetExceeded(words.parse()?, words.parse()?),
+            "DENIED" => Event::Denied(words.parse()?, words.parse()?),
+            "EXITED" => Event::Exited(words.parse()?, Box::new(decode_status(words.next()?)?)),
+            "STOPPED" => Event::Stopped(words.parse()?),
+            event => return Err(ProtocolError(format!("unknown event {event}"))),
+        };
+        words.end()?;
+        Ok(event)
+    }
+}
+
+fn encode_action(action: BudgetAction) -> &'static str {
+    match action {
+        BudgetAction::Kill => "kill",
+        BudgetAction::CpuLimitExceeded => "xcpu",
+        BudgetAction::User1 => "usr1",
+    }
+}
+
+fn decode_action(word: &str) -> Result<BudgetAction, ProtocolError> {
+    match word {
+        "kill" => Ok(BudgetAction::Kill),
+        "xcpu" => Ok(BudgetAction::CpuLimitExceeded),
+        "usr1" => Ok(BudgetAction::User1),
+        word => Err(ProtocolError(format!("unknown budget action {word}"))),
+    }
+}
+
+fn encode_error_kind(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::MapFull => "map_full",
+        ErrorKind::Protocol => "protocol",
+        ErrorKind::Denied => "denied",
+        ErrorKind::Failed => "failed",
+    }
+}
+
+fn decode_error_kind(word: &str) -> Result<ErrorKind, ProtocolError> {
+    match word {
+        "map_full" => Ok(ErrorKind::MapFull),
+        "protocol" => Ok(ErrorKind::Protocol),
+        "denied" => Ok(ErrorKind::Denied),
+        "failed" => Ok(ErrorKind::Failed),
+        word => Err(ProtocolError(format!("unknown error kind {word}"))),
+    }
+}
+
+// Writes each element preceded by a space.
+struct List<'a, T>(&'a [T]);
+
+impl<T: Display> Display for List<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|element| write!(f, " {element}"))
+    }
+}
+
+// Writes `-` for `None`.
+struct Opt<T>(Option<T>);
+
+impl<T: Display> Display for Opt<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(value) => write!(f, "{value}"),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+struct EncodedStatus<'a>(&'a ProcessStatus);
+
+impl Display for EncodedStatus<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let status = self.0;
+        let nanos = |duration: Duration| duration.as_nanos();
+
+        write!(f, "vm_peak={}", status.vm_peak_bytes)?;
+        write!(f, ",attempted_vm_peak={}", Opt(status.attempted_vm_peak_bytes))?;
+        // The hits are sent as count/first/max/first_time/last_time/soft/hard.
+        match &status.rlimit_hits {
+            Some(hits) => write!(
+                f,
+                ",rlimit_hits={}/{}/{}/{}/{}/{}/{}",
+                hits.count,
+                hits.first_attempted_bytes,
+                hits.max_attempted_bytes,
+                nanos(hits.first_hit_time),
+                nanos(hits.last_hit_time),
+                hits.max_attempted_soft_limit,
+                hits.max_attempted_hard_limit,
+            )?,
+            None => write!(f, ",rlimit_hits=-")?,
+        }
+        // The changes are separated by `;`, and sent as time/caller/target/resource/soft/hard.
+        write!(f, ",rlimit_changes=")?;
+        if status.rlimit_changes.is_empty() {
+            write!(f, "-")?;
+        }
+        for (i, change) in status.rlimit_changes.iter().enumerate() {
+            if i != 0 {
+                write!(f, ";")?;
+            }
+            write!(
+                f,
+                "{}/{}/{}/{}/{}/{}",
+                nanos(change.time),
+                change.caller_pid,
+                change.target_pid,
+                change.resource,
+                change.soft_limit,
+                change.hard_limit,
+            )?;
+        }
+        write!(f, ",rlimit_changes_lost={}", u8::from(status.rlimit_changes_lost))?;
+        write!(f, ",budget_exceeded={}", Opt(status.budget_exceeded_bytes))?;
+        write!(f, ",enforced_limit={}", Opt(status.enforced_limit_bytes))?;
+        write!(f, ",denied_vm_peak={}", Opt(status.denied_vm_peak_bytes))?;
+        write!(f, ",user_time={}", nanos(status.user_time))?;
+        write!(f, ",system_time={}", nanos(status.system_time))?;
+        write!(f, ",voluntary_context_switches={}", status.voluntary_context_switches)?;
+        write!(f, ",involuntary_context_switches={}", status.involuntary_context_switches)?;
+        write!(f, ",start_time={}", Opt(status.start_time.map(nanos)))?;
+        write!(f, ",exit_time={}", Opt(status.exit_time.map(nanos)))
+    }
+}
+
+fn decode_status(word: &str) -> Result<ProcessStatus, ProtocolError> {
+    let mut status = ProcessStatus {
+        vm_peak_bytes: 0,
+        attempted_vm_peak_bytes: None,
+        rlimit_hits: None,
+        rlimit_changes: Vec::new(),
+        rlimit_changes_lost: false,
+        budget_exceeded_bytes: None,
+        enforced_limit_bytes: None,
+        denied_vm_peak_bytes: None,
+        user_time: Duration::ZERO,
+        system_time: Duration::ZERO,
+        voluntary_context_switches: 0,
+        involuntary_context_switches: 0,
+        start_time: None,
+        exit_time: None,
+    };
+
+    // Unknown keys are ignored, so that fields can be added without a new version.
+    for pair in word.split(',') {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| ProtocolError(format!("invalid status field {pair}")))?;
+        match key {
+            "vm_peak" => status.vm_peak_bytes = parse(value)?,
+            "attempted_vm_peak" => status.attempted_vm_peak_bytes = parse_opt(value)?,
+            "rlimit_hits" if value != "-" => {
+                let mut values = value.split('/');
+                let mut next = || parse::<u64>(values.next().unwrap_or_default());
+                status.rlimit_hits = Some(RlimitHits {
+                    count: next()?,
+                    first_attempted_bytes: next()?,
+                    max_attempted_bytes: next()?,
+                    first_hit_time: Duration::from_nanos(next()?),
+                    last_hit_time: Duration::from_nanos(next()?),
+                    max_attempted_soft_limit: next()?,
+                    max_attempted_hard_limit: next()?,
+                });
+            }
+            "rlimit_changes" if value != "-" => {
+                for change in value.split(';') {
+                    let mut values = change.split('/');
+                    let mut next = || parse::<u64>(values.next().unwrap_or_default());
+                    status.rlimit_changes.push(RlimitChange {
+                        time: Duration::from_nanos(next()?),
+                        caller_pid: to_u32(next()?)?,
+                        target_pid: to_u32(next()?)?,
+                        resource: to_u32(next()?)?,
+                        soft_limit: next()?,
+                        hard_limit: next()?,
+                    });
+                }
+            }
+            "rlimit_changes_lost" => status.rlimit_changes_lost = parse::<u8>(value)? != 0,
+            "budget_exceeded" => status.budget_exceeded_bytes = parse_opt(value)?,
+            "enforced_limit" => status.enforced_limit_bytes = parse_opt(value)?,
+            "denied_vm_peak" => status.denied_vm_peak_bytes = parse_opt(value)?,
+            "user_time" => status.user_time = Duration::from_nanos(parse(value)?),
+            "system_time" => status.system_time = Duration::from_nanos(parse(value)?),
+            "voluntary_context_switches" => status.voluntary_context_switches = parse(value)?,
+            "involuntary_context_switches" => status.involuntary_context_switches = parse(value)?,
+            "start_time" => status.start_time = parse_opt(value)?.map(Duration::from_nanos),
+            "exit_time" => status.exit_time = parse_opt(value)?.map(Duration::from_nanos),
+            _ => {}
+        }
+    }
+
+    Ok(status)
+}
+
+fn parse<T: FromStr>(word: &str) -> Result<T, ProtocolError> {
+    word.parse()
+        .map_err(|_| ProtocolError(format!("invalid number {word}")))
+}
+
+fn parse_opt<T: FromStr>(word: &str) -> Result<Option<T>, ProtocolError> {
+    match word {
+        "-" => Ok(None),
+        word => parse(word).map(Some),
+    }
+}
+
+fn to_u32(value: u64) -> Result<u32, ProtocolError> {
+    value.try_into()
+        .map_err(|_| ProtocolError(format!("invalid number {value}")))
+}
+
+struct Words<'a>(std::str::SplitAsciiWhitespace<'a>);
+
+impl<'a> Words<'a> {
+    fn new(line: &'a str) -> Self {
+        Words(line.split_ascii_whitespace())
+    }
+
+    fn next(&mut self) -> Result<&'a str, ProtocolError> {
+        self.0.next().ok_or_else(|| ProtocolError("unexpected end of line".to_string()))
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), ProtocolError> {
+        match self.next()? {
+            word if word == expected => Ok(()),
+            word => Err(ProtocolError(format!("expected {expected}, got {word}"))),
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self) -> Result<T, ProtocolError> {
+        parse(self.next()?)
+    }
+
+    fn parse_opt<T: FromStr>(&mut self) -> Result<Option<T>, ProtocolError> {
+        parse_opt(self.next()?)
+    }
+
+    fn rest<T: FromStr>(&mut self) -> Result<Vec<T>, ProtocolError> {
+        self.0.by_ref().map(parse).collect()
+    }
+
+    fn by_ref(&mut self) -> &mut std::str::SplitAsciiWhitespace<'a> {
+        &mut self.0
+    }
+
+    fn end(&mut self) -> Result<(), ProtocolError> {
+        match self.0.next() {
+            None => Ok(()),
+            Some(word) => Err(ProtocolError(format!("unexpected {word}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    fn round_trip<T>(value: T)
+    where
+        T: Display + FromStr<Err = ProtocolError> + PartialEq + Debug,
+    {
+        let line = value.to_string();
+        assert!(!line.contains('\n'), "{line:?}");
+        assert_eq!(line.parse::<T>(), Ok(value), "{line:?}");
+    }
+
+    fn complete_status() -> ProcessStatus {
+        let change = RlimitChange {
+            time: Duration::from_nanos(999),
+            caller_pid: 1,
+            target_pid: u32::MAX,
+            resource: 9,
+            soft_limit: 2 << 30,
+            hard_limit: u64::MAX,
+        };
+        ProcessStatus {
+            vm_peak_bytes: 1 << 40,
+            attempted_vm_peak_bytes: Some(3 << 30),
+            rlimit_hits: Some(RlimitHits {
+                count: 2,
+                first_attempted_bytes: 3 << 30,
+                max_attempted_bytes: 5 << 30,
+                first_hit_time: Duration::from_nanos(1_000_000_001),
+                last_hit_time: Duration::from_nanos(2_000_000_002),
+                max_attempted_soft_limit: 2 << 30,
+                max_attempted_hard_limit: u64::MAX,
+            }),
+            rlimit_changes: vec![change.clone(), RlimitChange { resource: 7, ..change }],
+            rlimit_changes_lost: true,
+            budget_exceeded_bytes: Some(4 << 30),
+            enforced_limit_bytes: Some(6 << 30),
+            denied_vm_peak_bytes: Some(7 << 30),
+            user_time: Duration::new(12, 345_678_901),
+            system_time: Duration::from_nanos(1),
+            voluntary_context_switches: 10,
+            involuntary_context_switches: 20,
+            start_time: Some(Duration::from_nanos(5)),
+            exit_time: Some(Duration::from_nanos(u64::MAX)),
+        }
+    }
+
+    fn empty_status() -> ProcessStatus {
+        ProcessStatus {
+            vm_peak_bytes: 0,
+            attempted_vm_peak_bytes: None,
+            rlimit_hits: None,
+            rlimit_changes: Vec::new(),
+            rlimit_changes_lost: false,
+            budget_exceeded_bytes: None,
+            enforced_limit_bytes: None,
+            denied_vm_peak_bytes: None,
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            voluntary_context_switches: 0,
+            involuntary_context_switches: 0,
+            start_time: None,
+            exit_time: None,
+        }
+    }
+
+    #[test]
+    fn hello() {
+        round_trip(Hello { version: PROTOCOL_VERSION });
+        assert_eq!(Hello { version: 2 }.to_string(), "HELLO 2");
+        assert!("HELLO".parse::<Hello>().is_err());
+        assert!("HELLO 2 3".parse::<Hello>().is_err());
+    }
+
+    #[test]
+    fn requests() {
+        round_trip(Request::Start(42));
+        for action in [BudgetAction::Kill, BudgetAction::CpuLimitExceeded, BudgetAction::User1] {
+            round_trip(Request::StartWithBudget(42, 1 << 30, action));
+        }
+        round_trip(Request::SetEnforcedLimit(42, u64::MAX));
+        round_trip(Request::Take(42));
+        round_trip(Request::Usage);
+        round_trip(Request::Gc(Duration::from_secs(600)));
+        round_trip(Request::Gc(Duration::ZERO));
+
+        for pids in [vec![], vec![42], vec![1, 2, u32::MAX]] {
+            round_trip(Request::StartMany(pids.clone()));
+            round_trip(Request::Status(pids.clone()));
+            round_trip(Request::Stop(pids.clone()));
+            round_trip(Request::Subscribe(pids));
+        }
+        assert_eq!(Request::Status(vec![]).to_string(), "STATUS");
+        assert_eq!(Request::Status(vec![1, 2]).to_string(), "STATUS 1 2");
+    }
+
+    #[test]
+    fn invalid_requests() {
+        for line in ["", "START", "START x", "START 1 2", "START_BUDGET 1 2 term", "STATUS 1 x", "NOPE"] {
+            assert!(line.parse::<Request>().is_err(), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn responses() {
+        round_trip(Response::Ok);
+        round_trip(Response::Statuses(vec![]));
+        round_trip(Response::Statuses(vec![None]));
+        round_trip(Response::Statuses(vec![Some(complete_status()), None, Some(empty_status())]));
+        round_trip(Response::Usage(MapUsage { processes: 3, enforced_limits: Some(1), max_entries: 1024 }));
+        round_trip(Response::Usage(MapUsage { processes: 0, enforced_limits: None, max_entries: 1 }));
+        round_trip(Response::Pids(vec![]));
+        round_trip(Response::Pids(vec![7, 8]));
+        for kind in [ErrorKind::MapFull, ErrorKind::Protocol, ErrorKind::Denied, ErrorKind::Failed] {
+            round_trip(Response::Error(kind, "uid 1000 may not monitor process 1".to_string()));
+            round_trip(Response::Error(kind, String::new()));
+        }
+    }
+
+    #[test]
+    fn missing_values() {
+        assert_eq!(Response::Statuses(vec![None]).to_string(), "STATUSES -");
+        let usage = MapUsage { processes: 3, enforced_limits: None, max_entries: 1024 };
+        assert_eq!(Response::Usage(usage).to_string(), "USAGE 3 - 1024");
+
+        let status = EncodedStatus(&empty_status()).to_string();
+        for field in [
+            "attempted_vm_peak=-",
+            "rlimit_hits=-",
+            "rlimit_changes=-",
+            "budget_exceeded=-",
+            "enforced_limit=-",
+            "denied_vm_peak=-",
+            "start_time=-",
+            "exit_time=-",
+        ] {
+            assert!(status.split(',').any(|pair| pair == field), "{field} in {status}");
+        }
+    }
+
+    #[test]
+    fn error_messages_stay_on_one_line() {
+        let response = Response::Error(ErrorKind::Failed, "first\nsecond".to_string());
+        assert_eq!(response.to_string(), "ERR failed first second");
+    }
+
+    #[test]
+    fn events() {
+        round_trip(Event::RlimitHit(42, 3));
+        round_trip(Event::BudgetExceeded(42, 1 << 30));
+        round_trip(Event::Denied(42, u64::MAX));
+        round_trip(Event::Exited(42, Box::new(complete_status())));
+        round_trip(Event::Exited(42, Box::new(empty_status())));
+        round_trip(Event::Stopped(42));
+    }
+
+    #[test]
+    fn statuses() {
+        for status in [complete_status(), empty_status()] {
+            let word = EncodedStatus(&status).to_string();
+            assert!(!word.contains(' '), "{word}");
+            assert_eq!(decode_status(&word), Ok(status));
+        }
+    }
+
+    #[test]
+    fn status_compatibility() {
+        // Unknown keys are ignored, and missing ones keep their default.
+        let status = decode_status("vm_peak=4096,future_field=1").unwrap();
+        assert_eq!(status, ProcessStatus { vm_peak_bytes: 4096, ..empty_status() });
+
+        for word in ["vm_peak", "vm_peak=x", "rlimit_hits=1/2", "rlimit_changes=1/2/3/4/5/6;1"] {
+            assert!(decode_status(word).is_err(), "{word:?}");
+        }
+    }
+}
@@ -0,0 +1,48 @@
+"""Python bindings for ebpf-memory-monitor, which records the peak size of the virtual
+memory of processes, and the expansions rejected because of their RLIMIT_AS.
+
+Loading the eBPF programs with ``initialize`` requires root, or the CAP_SYS_RESOURCE,
+CAP_BPF and CAP_PERFMON capabilities.
+"""
+
+from ._native import (
+    Event,
+    Events,
+    MapFullError,
+    MonitorError,
+    NotInitializedError,
+    Popen,
+    ProcessStatus,
+    RlimitChange,
+    RlimitHits,
+    events,
+    get_process_status,
+    initialize,
+    monitored_processes,
+    start_monitoring_process,
+    start_monitoring_processes,
+    stop_monitoring_process,
+    stop_monitoring_processes,
+    take_process_status,
+)
+
+__all__ = [
+    "Event",
+    "Events",
+    "MapFullError",
+    "MonitorError",
+    "NotInitializedError",
+    "Popen",
+    "ProcessStatus",
+    "RlimitChange",
+    "RlimitHits",
+    "events",
+    "get_process_status",
+    "initialize",
+    "monitored_processes",
+    "start_monitoring_process",
+    "start_monitoring_processes",
+    "stop_monitoring_process",
+    "stop_monitoring_processes",
+    "take_process_status",
+]
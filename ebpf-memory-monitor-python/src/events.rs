@@ -0,0 +1,107 @@
+//! `events`, the limit hits and exits of monitored processes as a Python iterator.
+
+use crate::ensure_initialized;
+use crate::status::PyProcessStatus;
+use ebpf_memory_monitor::events::{Event, Events};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use std::thread;
+use std::time::Duration;
+
+/// Something which happened to a monitored process.
+///
+/// `kind` is one of:
+/// - "rlimit_hit": the process hit its RLIMIT_AS, and `count` is the number of hits so far.
+/// - "budget_exceeded": the process exceeded its budget, with an expansion of `bytes`.
+/// - "denied": an allocation of `bytes` was denied by the enforced limit.
+/// - "exited": the process exited, and `status` is its final status.
+/// - "stopped": the process is no longer monitored.
+#[pyclass(name = "Event", module = "ebpf_memory_monitor", frozen, get_all)]
+pub(crate) struct PyEvent {
+    kind: &'static str,
+    pid: u32,
+    count: Option<u64>,
+    bytes: Option<u64>,
+    status: Option<PyProcessStatus>,
+}
+
+#[pymethods]
+impl PyEvent {
+    fn __repr__(&self) -> String {
+        format!("Event(kind={:?}, pid={})", self.kind, self.pid)
+    }
+}
+
+impl From<Event> for PyEvent {
+    fn from(event: Event) -> Self {
+        let base = |kind, pid| PyEvent {
+            kind,
+            pid,
+            count: None,
+            bytes: None,
+            status: None,
+        };
+        match event {
+            Event::RlimitHit(pid, count) => PyEvent { count: Some(count), ..base("rlimit_hit", pid) },
+            Event::BudgetExceeded(pid, bytes) => PyEvent { bytes: Some(bytes), ..base("budget_exceeded", pid) },
+            Event::Denied(pid, bytes) => PyEvent { bytes: Some(bytes), ..base("denied", pid) },
+            Event::Exited(pid, status) => PyEvent {
+                status: Some(PyProcessStatus::from(*status)),
+                ..base("exited", pid)
+            },
+            Event::Stopped(pid) => base("stopped", pid),
+        }
+    }
+}
+
+/// An iterator over the events of processes, returned by `events`.
+#[pyclass(name = "Events", module = "ebpf_memory_monitor")]
+pub(crate) struct PyEvents {
+    events: Events,
+    pending: VecDeque<Event>,
+    polled: bool,
+}
+
+#[pymethods]
+impl PyEvents {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyEvent>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event.into()));
+            }
+            if self.events.is_finished() {
+                return Ok(None);
+            }
+            // Signals are handled between the polls, so that KeyboardInterrupt stops
+            // the iteration.
+            if self.polled {
+                let interval = self.events.interval();
+                py.allow_threads(|| thread::sleep(interval));
+                py.check_signals()?;
+            }
+            self.polled = true;
+            ensure_initialized()?;
+            self.pending.extend(self.events.poll());
+        }
+    }
+}
+
+/// Returns an iterator over the limit hits and exits of the processes, which polls their
+/// statuses every `interval` seconds. It ends once every process exited or is no longer
+/// monitored.
+#[pyfunction]
+#[pyo3(signature = (pids, interval = 0.1))]
+pub(crate) fn events(pids: Vec<u32>, interval: f64) -> PyResult<PyEvents> {
+    let interval = Duration::try_from_secs_f64(interval)
+        .map_err(|_| PyValueError::new_err(format!("invalid interval {interval}")))?;
+    Ok(PyEvents {
+        events: Events::new(&pids, interval),
+        pending: VecDeque::new(),
+        polled: false,
+    })
+}
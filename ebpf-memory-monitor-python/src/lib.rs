@@ -0,0 +1,146 @@
+//! Python bindings for `ebpf-memory-monitor`, built by maturin as the
+//! `ebpf_memory_monitor._native` extension module, which the `ebpf_memory_monitor`
+//! package in `python/` re-exports.
+
+mod events;
+mod popen;
+mod status;
+
+use ebpf_memory_monitor::init::{backend_info, InitOptions};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::path::PathBuf;
+use status::PyProcessStatus;
+
+create_exception!(ebpf_memory_monitor, MonitorError, PyException, "An operation of the monitor failed.");
+create_exception!(
+    ebpf_memory_monitor,
+    NotInitializedError,
+    MonitorError,
+    "initialize() was not called, or failed."
+);
+create_exception!(
+    ebpf_memory_monitor,
+    MapFullError,
+    MonitorError,
+    "The maps are full, and no room could be made in them."
+);
+
+/// Raises `NotInitializedError` unless the monitor is initialized, as the functions of
+/// the library panic otherwise.
+pub(crate) fn ensure_initialized() -> PyResult<()> {
+    match backend_info() {
+        Some(_) => Ok(()),
+        None => Err(NotInitializedError::new_err("initialize() must be called first")),
+    }
+}
+
+fn monitor_error(error: ebpf_memory_monitor::MonitorError) -> PyErr {
+    match error {
+        error @ ebpf_memory_monitor::MonitorError::MapFull { .. } => MapFullError::new_err(error.to_string()),
+        error => MonitorError::new_err(error.to_string()),
+    }
+}
+
+/// Loads and attaches the eBPF programs, with maps for `max_listeners` processes. Does
+/// nothing if the monitor is already initialized.
+///
+/// Requires the CAP_SYS_RESOURCE, CAP_BPF and CAP_PERFMON capabilities.
+#[pyfunction]
+#[pyo3(signature = (max_listeners = 1024, *, lsm_enforcement = false, log_rlimit_changes = false, pin_path = None))]
+fn initialize(
+    py: Python<'_>,
+    max_listeners: u32,
+    lsm_enforcement: bool,
+    log_rlimit_changes: bool,
+    pin_path: Option<PathBuf>,
+) -> PyResult<()> {
+    let mut options = InitOptions::new(max_listeners)
+        .lsm_enforcement(lsm_enforcement)
+        .log_rlimit_changes(log_rlimit_changes);
+    if let Some(pin_path) = pin_path {
+        options = options.pin_path(pin_path);
+    }
+    py.allow_threads(|| ebpf_memory_monitor::init::initialize(options))
+        .map_err(|error| MonitorError::new_err(format!("initializing the monitor failed: {error:#}")))
+}
+
+/// Starts monitoring the process.
+#[pyfunction]
+fn start_monitoring_process(pid: u32) -> PyResult<()> {
+    ensure_initialized()?;
+    ebpf_memory_monitor::start_monitoring_process(pid).map_err(monitor_error)
+}
+
+/// Starts monitoring the processes, with a single bpf() call on Linux 5.6 or above.
+#[pyfunction]
+fn start_monitoring_processes(pids: Vec<u32>) -> PyResult<()> {
+    ensure_initialized()?;
+    ebpf_memory_monitor::start_monitoring_processes(&pids).map_err(monitor_error)
+}
+
+/// Returns the status of the process, or None if it's not monitored. The process stays
+/// monitored.
+#[pyfunction]
+fn get_process_status(pid: u32) -> PyResult<Option<PyProcessStatus>> {
+    ensure_initialized()?;
+    Ok(ebpf_memory_monitor::get_process_status(pid).map(PyProcessStatus::from))
+}
+
+/// Returns the status of the process and stops monitoring it, or returns None if it's
+/// not monitored.
+#[pyfunction]
+fn take_process_status(pid: u32) -> PyResult<Option<PyProcessStatus>> {
+    ensure_initialized()?;
+    Ok(ebpf_memory_monitor::take_process_status(pid).map(PyProcessStatus::from))
+}
+
+/// Stops monitoring the process, discarding its status.
+#[pyfunction]
+fn stop_monitoring_process(pid: u32) -> PyResult<()> {
+    ensure_initialized()?;
+    ebpf_memory_monitor::stop_monitoring_process(pid);
+    Ok(())
+}
+
+/// Stops monitoring the processes, discarding their statuses.
+#[pyfunction]
+fn stop_monitoring_processes(pids: Vec<u32>) -> PyResult<()> {
+    ensure_initialized()?;
+    ebpf_memory_monitor::stop_monitoring_processes(&pids);
+    Ok(())
+}
+
+/// Returns the PIDs of the monitored processes, including the exited ones.
+#[pyfunction]
+fn monitored_processes() -> PyResult<Vec<u32>> {
+    ensure_initialized()?;
+    Ok(ebpf_memory_monitor::monitored_processes())
+}
+
+#[pymodule]
+fn _native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("MonitorError", py.get_type::<MonitorError>())?;
+    m.add("NotInitializedError", py.get_type::<NotInitializedError>())?;
+    m.add("MapFullError", py.get_type::<MapFullError>())?;
+
+    m.add_function(wrap_pyfunction!(initialize, m)?)?;
+    m.add_function(wrap_pyfunction!(start_monitoring_process, m)?)?;
+    m.add_function(wrap_pyfunction!(start_monitoring_processes, m)?)?;
+    m.add_function(wrap_pyfunction!(get_process_status, m)?)?;
+    m.add_function(wrap_pyfunction!(take_process_status, m)?)?;
+    m.add_function(wrap_pyfunction!(stop_monitoring_process, m)?)?;
+    m.add_function(wrap_pyfunction!(stop_monitoring_processes, m)?)?;
+    m.add_function(wrap_pyfunction!(monitored_processes, m)?)?;
+    m.add_function(wrap_pyfunction!(events::events, m)?)?;
+
+    m.add_class::<status::PyProcessStatus>()?;
+    m.add_class::<status::PyRlimitHits>()?;
+    m.add_class::<status::PyRlimitChange>()?;
+    m.add_class::<popen::Popen>()?;
+    m.add_class::<events::PyEvent>()?;
+    m.add_class::<events::PyEvents>()?;
+    Ok(())
+}
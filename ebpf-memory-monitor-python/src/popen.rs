@@ -0,0 +1,161 @@
+//! `Popen`, which spawns a command monitored from before it's executed.
+
+use crate::status::PyProcessStatus;
+use crate::{ensure_initialized, MonitorError};
+use ebpf_memory_monitor::run::{spawn, ExecFailed};
+use ebpf_memory_monitor::take_process_status;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often `wait` checks whether the command exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A command spawned with RLIMIT_AS set to `as_limit`, and monitored from before it's
+/// executed, like `subprocess.Popen`. The standard streams are inherited.
+///
+/// Once the command exited and was waited for, `returncode` is set like in `Popen`,
+/// negative if it was killed by a signal, and `status` is its final status. The command
+/// is no longer monitored after that.
+#[pyclass(module = "ebpf_memory_monitor")]
+pub(crate) struct Popen {
+    #[pyo3(get)]
+    args: Vec<String>,
+    #[pyo3(get)]
+    pid: u32,
+    #[pyo3(get)]
+    returncode: Option<i32>,
+    #[pyo3(get)]
+    status: Option<PyProcessStatus>,
+}
+
+#[pymethods]
+impl Popen {
+    #[new]
+    #[pyo3(signature = (args, *, as_limit = None))]
+    fn new(py: Python<'_>, args: Vec<String>, as_limit: Option<u64>) -> PyResult<Self> {
+        ensure_initialized()?;
+        let child = py.allow_threads(|| spawn(&args, as_limit)).map_err(|error| {
+            match error.downcast_ref::<ExecFailed>() {
+                // Raised as the subclass of OSError matching the errno, like by `Popen`.
+                Some(exec_failed) => {
+                    let errno = exec_failed.errno;
+                    PyOSError::new_err((errno as i32, errno.desc(), exec_failed.program.clone()))
+                }
+                None => MonitorError::new_err(format!("{error:#}")),
+            }
+        })?;
+
+        Ok(Popen {
+            args,
+            pid: child.pid(),
+            returncode: None,
+            status: None,
+        })
+    }
+
+    /// Returns the return code if the command exited, or None.
+    fn poll(&mut self) -> PyResult<Option<i32>> {
+        if self.returncode.is_none() {
+            self.reap()?;
+        }
+        Ok(self.returncode)
+    }
+
+    /// Waits for the command to exit and returns its return code. Raises
+    /// `subprocess.TimeoutExpired` if it's still running after `timeout` seconds.
+    #[pyo3(signature = (timeout = None))]
+    fn wait(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<i32> {
+        let deadline = match timeout {
+            Some(seconds) => {
+                let timeout = Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| PyValueError::new_err(format!("invalid timeout {seconds}")))?;
+                Some(Instant::now() + timeout)
+            }
+            None => None,
+        };
+
+        loop {
+            if let Some(returncode) = self.poll()? {
+                return Ok(returncode);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                let timeout_expired = py.import("subprocess")?.getattr("TimeoutExpired")?;
+                return Err(PyErr::from_value(timeout_expired.call1((self.args.clone(), timeout))?));
+            }
+            // Signals are only handled between the sleeps, so that KeyboardInterrupt
+            // interrupts the wait.
+            py.allow_threads(|| thread::sleep(WAIT_INTERVAL));
+            py.check_signals()?;
+        }
+    }
+
+    /// Sends the signal to the command, unless it was already waited for.
+    fn send_signal(&self, signal: i32) -> PyResult<()> {
+        if self.returncode.is_some() {
+            return Ok(());
+        }
+        let signal = Signal::try_from(signal).map_err(|_| PyValueError::new_err(format!("invalid signal {signal}")))?;
+        kill(Pid::from_raw(self.pid as i32), signal).map_err(os_error)
+    }
+
+    /// Sends SIGTERM to the command.
+    fn terminate(&self) -> PyResult<()> {
+        self.send_signal(Signal::SIGTERM as i32)
+    }
+
+    /// Sends SIGKILL to the command.
+    fn kill(&self) -> PyResult<()> {
+        self.send_signal(Signal::SIGKILL as i32)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type=None, _exc_value=None, _traceback=None))]
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: Option<PyObject>,
+        _exc_value: Option<PyObject>,
+        _traceback: Option<PyObject>,
+    ) -> PyResult<bool> {
+        self.wait(py, None)?;
+        Ok(false)
+    }
+
+    fn __repr__(&self) -> String {
+        let returncode = self.returncode.map_or("None".to_string(), |code| code.to_string());
+        format!("<Popen: pid={} returncode={returncode} args={:?}>", self.pid, self.args)
+    }
+}
+
+impl Popen {
+    /// Reaps the command if it exited, taking its status.
+    fn reap(&mut self) -> PyResult<()> {
+        let returncode = match waitpid(Pid::from_raw(self.pid as i32), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(_, code)) => code,
+            Ok(WaitStatus::Signaled(_, signal, _)) => -(signal as i32),
+            Ok(_) | Err(Errno::EINTR) => return Ok(()),
+            Err(errno) => return Err(os_error(errno)),
+        };
+
+        self.returncode = Some(returncode);
+        // The status is lost if the monitor was shut down in the meantime.
+        self.status = ensure_initialized()
+            .ok()
+            .and_then(|()| take_process_status(self.pid))
+            .map(PyProcessStatus::from);
+        Ok(())
+    }
+}
+
+fn os_error(errno: Errno) -> PyErr {
+    PyOSError::new_err((errno as i32, errno.desc()))
+}
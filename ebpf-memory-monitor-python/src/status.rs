@@ -0,0 +1,147 @@
+//! The Python classes of the statuses. Sizes are ints of bytes, and durations and
+//! `CLOCK_MONOTONIC` times are floats of seconds, like in the `time` module.
+
+use ebpf_memory_monitor::{ProcessStatus, RlimitChange, RlimitHits};
+use pyo3::prelude::*;
+
+/// The expansions of the virtual memory of a process rejected because of RLIMIT_AS.
+#[pyclass(name = "RlimitHits", module = "ebpf_memory_monitor", frozen, get_all)]
+#[derive(Clone)]
+pub(crate) struct PyRlimitHits {
+    count: u64,
+    first_attempted_bytes: u64,
+    max_attempted_bytes: u64,
+    first_hit_time: f64,
+    last_hit_time: f64,
+    max_attempted_soft_limit: u64,
+    max_attempted_hard_limit: u64,
+}
+
+#[pymethods]
+impl PyRlimitHits {
+    fn __repr__(&self) -> String {
+        format!(
+            "RlimitHits(count={}, max_attempted_bytes={}, max_attempted_soft_limit={})",
+            self.count, self.max_attempted_bytes, self.max_attempted_soft_limit
+        )
+    }
+}
+
+impl From<&RlimitHits> for PyRlimitHits {
+    fn from(hits: &RlimitHits) -> Self {
+        PyRlimitHits {
+            count: hits.count,
+            first_attempted_bytes: hits.first_attempted_bytes,
+            max_attempted_bytes: hits.max_attempted_bytes,
+            first_hit_time: hits.first_hit_time.as_secs_f64(),
+            last_hit_time: hits.last_hit_time.as_secs_f64(),
+            max_attempted_soft_limit: hits.max_attempted_soft_limit,
+            max_attempted_hard_limit: hits.max_attempted_hard_limit,
+        }
+    }
+}
+
+/// A setrlimit or prlimit64 call made by or on a monitored process.
+#[pyclass(name = "RlimitChange", module = "ebpf_memory_monitor", frozen, get_all)]
+#[derive(Clone)]
+pub(crate) struct PyRlimitChange {
+    time: f64,
+    caller_pid: u32,
+    target_pid: u32,
+    resource: u32,
+    soft_limit: u64,
+    hard_limit: u64,
+}
+
+#[pymethods]
+impl PyRlimitChange {
+    fn __repr__(&self) -> String {
+        format!(
+            "RlimitChange(caller_pid={}, target_pid={}, resource={}, soft_limit={}, hard_limit={})",
+            self.caller_pid, self.target_pid, self.resource, self.soft_limit, self.hard_limit
+        )
+    }
+}
+
+impl From<&RlimitChange> for PyRlimitChange {
+    fn from(change: &RlimitChange) -> Self {
+        PyRlimitChange {
+            time: change.time.as_secs_f64(),
+            caller_pid: change.caller_pid,
+            target_pid: change.target_pid,
+            resource: change.resource,
+            soft_limit: change.soft_limit,
+            hard_limit: change.hard_limit,
+        }
+    }
+}
+
+/// The resource usage of a monitored process.
+///
+/// The limits are updated while the process runs, and the rest is recorded when it
+/// exits, staying zero (or None) until then.
+#[pyclass(name = "ProcessStatus", module = "ebpf_memory_monitor", frozen, get_all)]
+#[derive(Clone)]
+pub(crate) struct PyProcessStatus {
+    vm_peak_bytes: u64,
+    attempted_vm_peak_bytes: Option<u64>,
+    rlimit_hits: Option<PyRlimitHits>,
+    rlimit_changes: Vec<PyRlimitChange>,
+    rlimit_changes_lost: bool,
+    budget_exceeded_bytes: Option<u64>,
+    enforced_limit_bytes: Option<u64>,
+    denied_vm_peak_bytes: Option<u64>,
+    user_time: f64,
+    system_time: f64,
+    voluntary_context_switches: u64,
+    involuntary_context_switches: u64,
+    start_time: Option<f64>,
+    exit_time: Option<f64>,
+}
+
+#[pymethods]
+impl PyProcessStatus {
+    /// Whether the process exited.
+    #[getter]
+    fn exited(&self) -> bool {
+        self.exit_time.is_some()
+    }
+
+    /// The time between the start and the exit of the process, or None until it exits.
+    #[getter]
+    fn wall_time(&self) -> Option<f64> {
+        Some((self.exit_time? - self.start_time?).max(0.0))
+    }
+
+    fn __repr__(&self) -> String {
+        let optional = |value: Option<u64>| value.map_or("None".to_string(), |value| value.to_string());
+        format!(
+            "ProcessStatus(vm_peak_bytes={}, attempted_vm_peak_bytes={}, rlimit_hits={}, exited={})",
+            self.vm_peak_bytes,
+            optional(self.attempted_vm_peak_bytes),
+            self.rlimit_hits.as_ref().map_or(0, |hits| hits.count),
+            if self.exited() { "True" } else { "False" },
+        )
+    }
+}
+
+impl From<ProcessStatus> for PyProcessStatus {
+    fn from(status: ProcessStatus) -> Self {
+        PyProcessStatus {
+            vm_peak_bytes: status.vm_peak_bytes,
+            attempted_vm_peak_bytes: status.attempted_vm_peak_bytes,
+            rlimit_hits: status.rlimit_hits.as_ref().map(PyRlimitHits::from),
+            rlimit_changes: status.rlimit_changes.iter().map(PyRlimitChange::from).collect(),
+            rlimit_changes_lost: status.rlimit_changes_lost,
+            budget_exceeded_bytes: status.budget_exceeded_bytes,
+            enforced_limit_bytes: status.enforced_limit_bytes,
+            denied_vm_peak_bytes: status.denied_vm_peak_bytes,
+            user_time: status.user_time.as_secs_f64(),
+            system_time: status.system_time.as_secs_f64(),
+            voluntary_context_switches: status.voluntary_context_switches,
+            involuntary_context_switches: status.involuntary_context_switches,
+            start_time: status.start_time.map(|time| time.as_secs_f64()),
+            exit_time: status.exit_time.map(|time| time.as_secs_f64()),
+        }
+    }
+}
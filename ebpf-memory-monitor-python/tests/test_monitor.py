@@ -0,0 +1,126 @@
+"""Tests of the Python bindings, which load the eBPF programs and must be run as root:
+
+    maturin develop && sudo -E python -m pytest tests
+"""
+
+import os
+import signal
+import subprocess
+import sys
+
+import pytest
+
+import ebpf_memory_monitor as emm
+
+pytestmark = pytest.mark.skipif(os.geteuid() != 0, reason="loading the eBPF programs requires root")
+
+MIB = 1 << 20
+
+
+def python(code):
+    return [sys.executable, "-c", code]
+
+
+@pytest.fixture(scope="module", autouse=True)
+def monitor():
+    emm.initialize(64)
+
+
+def test_functions_raise_before_initialize():
+    # The monitor of this process is already initialized, so a fresh interpreter is used.
+    code = (
+        "import ebpf_memory_monitor as emm\n"
+        "try:\n"
+        "    emm.get_process_status(1)\n"
+        "except emm.NotInitializedError:\n"
+        "    raise SystemExit(0)\n"
+        "raise SystemExit(1)\n"
+    )
+    assert subprocess.run(python(code)).returncode == 0
+
+
+def test_not_initialized_is_a_monitor_error():
+    assert issubclass(emm.NotInitializedError, emm.MonitorError)
+    assert issubclass(emm.MapFullError, emm.MonitorError)
+
+
+def test_unmonitored_process_has_no_status():
+    assert emm.get_process_status(os.getpid()) is None
+    assert emm.take_process_status(os.getpid()) is None
+
+
+def test_start_and_stop_monitoring():
+    with subprocess.Popen(["sleep", "10"]) as child:
+        emm.start_monitoring_process(child.pid)
+        assert child.pid in emm.monitored_processes()
+
+        status = emm.get_process_status(child.pid)
+        assert status is not None
+        assert not status.exited
+        assert status.exit_time is None
+
+        emm.stop_monitoring_process(child.pid)
+        assert emm.get_process_status(child.pid) is None
+        child.kill()
+
+
+def test_popen_records_vm_peak():
+    with emm.Popen(python(f"data = bytearray({64 * MIB})")) as process:
+        assert process.pid > 0
+    assert process.returncode == 0
+
+    status = process.status
+    assert status is not None
+    assert status.exited
+    assert status.vm_peak_bytes >= 64 * MIB
+    assert status.attempted_vm_peak_bytes is None
+    assert status.rlimit_hits is None
+    assert status.wall_time >= 0
+    # The status was taken when the process was waited for.
+    assert emm.get_process_status(process.pid) is None
+
+
+def test_popen_records_rlimit_hits():
+    as_limit = 512 * MIB
+    process = emm.Popen(python(f"data = bytearray({1024 * MIB})"), as_limit=as_limit)
+    # The allocation fails with a MemoryError.
+    assert process.wait() == 1
+
+    hits = process.status.rlimit_hits
+    assert hits is not None
+    assert hits.count >= 1
+    assert hits.max_attempted_soft_limit == as_limit
+    assert process.status.attempted_vm_peak_bytes > as_limit
+
+
+def test_popen_missing_command():
+    with pytest.raises(FileNotFoundError):
+        emm.Popen(["/nonexistent/command"])
+
+
+def test_popen_wait_timeout_and_kill():
+    process = emm.Popen(["sleep", "10"])
+    assert process.poll() is None
+    with pytest.raises(subprocess.TimeoutExpired):
+        process.wait(timeout=0.1)
+
+    process.kill()
+    assert process.wait() == -signal.SIGKILL
+    assert process.status.exited
+
+
+def test_events():
+    process = emm.Popen(python(f"data = bytearray({1024 * MIB})"), as_limit=512 * MIB)
+    events = list(emm.events([process.pid], interval=0.01))
+    process.wait()
+
+    kinds = [event.kind for event in events]
+    assert kinds[-1] == "exited"
+    assert all(event.pid == process.pid for event in events)
+    assert events[-1].status.vm_peak_bytes > 0
+    assert "rlimit_hit" in kinds
+    assert next(event for event in events if event.kind == "rlimit_hit").count >= 1
+
+
+def test_events_of_unmonitored_process():
+    assert [(event.kind, event.pid) for event in emm.events([os.getpid()])] == [("stopped", os.getpid())]
@@ -8,26 +8,16 @@ fn main() -> anyhow::Result<()> {
         .exec()
         .context("MetadataCommand::exec")?;
 
-    let rlimit_fentry: Package = packages
+    let memory_monitor_fentry: Package = packages
         .iter()
-        .find(|Package { name, .. }| name == "rlimit-fentry")
-        .ok_or_else(|| anyhow!("rlimit-fentry package not found"))?
+        .find(|Package { name, .. }| name == "memory-monitor-fentry")
+        .ok_or_else(|| anyhow!("memory-monitor-fentry package not found"))?
         .clone();
-    let rlimit_kprobe: Package = packages
+    let memory_monitor_kprobe: Package = packages
         .iter()
-        .find(|Package { name, .. }| name == "rlimit-kprobe")
-        .ok_or_else(|| anyhow!("rlimit-kprobe package not found"))?
-        .clone();
-    let hiwater_fentry: Package = packages
-        .iter()
-        .find(|Package { name, .. }| name == "hiwater-fentry")
-        .ok_or_else(|| anyhow!("hiwater-fentry package not found"))?
-        .clone();
-    let hiwater_kprobe: Package = packages
-        .iter()
-        .find(|Package { name, .. }| name == "hiwater-kprobe")
-        .ok_or_else(|| anyhow!("hiwater-kprobe package not found"))?
+        .find(|Package { name, .. }| name == "memory-monitor-kprobe")
+        .ok_or_else(|| anyhow!("memory-monitor-kprobe package not found"))?
         .clone();
 
-    aya_build::build_ebpf([rlimit_fentry, rlimit_kprobe, hiwater_fentry, hiwater_kprobe])
+    aya_build::build_ebpf([memory_monitor_fentry, memory_monitor_kprobe])
 }
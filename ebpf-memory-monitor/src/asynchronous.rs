@@ -0,0 +1,132 @@
+//! An async API on tokio, enabled by the `tokio` feature.
+//!
+//! The futures and streams are cancellation safe: dropping them loses nothing, so they can
+//! be used in `tokio::select!`. They must be used within a tokio runtime with IO and time
+//! enabled.
+
+pub use crate::events::Event;
+pub use crate::WaitError;
+
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use futures_core::Stream;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::time::{self, Interval, MissedTickBehavior};
+use crate::events::Events;
+use crate::exit::{exit_status, has_exited, watch_exit, ExitWatch, PidFd, POLL_INTERVAL};
+use crate::{get_process_status, ProcessStatus};
+
+/// Waits for all the threads of the process to exit, and returns its final status. The
+/// process stays monitored: its status must still be taken or stopped.
+///
+/// The exit is awaited with a pidfd, or by polling `/proc` before Linux 5.3. The process
+/// must not be reaped before this is called, as its PID could then be reused.
+///
+/// This function should not be called unless `initialize_with_max_listeners` was successfully called before.
+pub async fn wait_for_exit(pid: u32) -> Result<ProcessStatus, WaitError> {
+    if get_process_status(pid).is_none() {
+        return Err(WaitError::NotMonitored);
+    }
+
+    match watch_exit(pid)? {
+        ExitWatch::PidFd(pidfd) => {
+            let pidfd = AsyncFd::with_interest(pidfd, Interest::READABLE)?;
+            // A pidfd stays readable once the process exited, so the readiness is kept.
+            let _ready = pidfd.readable().await?;
+        }
+        ExitWatch::Reaped => {}
+        ExitWatch::Unsupported => {
+            while !has_exited(pid) {
+                time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+    exit_status(pid)
+}
+
+/// The events of a set of processes as a `Stream`, like `Events` but waiting between
+/// the polls without blocking the runtime.
+///
+/// The limit hits are found by polling the statuses every `interval`, while the exits are
+/// awaited with pidfds registered in the runtime, so they're reported as soon as they
+/// happen. Before Linux 5.3, they are only found by the polls.
+///
+/// The stream ends once every process exited or is no longer monitored. Events found by
+/// a poll are buffered until they're consumed, so none is lost when `next` is cancelled.
+pub struct EventStream {
+    events: Events,
+    pending: VecDeque<Event>,
+    ticks: Interval,
+    exits: Vec<(u32, AsyncFd<PidFd>)>,
+}
+
+impl EventStream {
+    /// Watches the processes, polling their statuses every `interval`, the first time
+    /// immediately. Panics if `interval` is zero.
+    ///
+    /// The processes must not be reaped before this is called, as their PIDs could then
+    /// be reused.
+    pub fn new(pids: &[u32], interval: Duration) -> Self {
+        let mut ticks = time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The processes whose pidfd can't be opened or registered are left to the polls.
+        let exits = pids
+            .iter()
+            .filter_map(|&pid| match watch_exit(pid) {
+                Ok(ExitWatch::PidFd(pidfd)) => {
+                    let pidfd = AsyncFd::with_interest(pidfd, Interest::READABLE).ok()?;
+                    Some((pid, pidfd))
+                }
+                _ => None,
+            })
+            .collect();
+        EventStream {
+            events: Events::new(pids, interval),
+            pending: VecDeque::new(),
+            ticks,
+            exits,
+        }
+    }
+
+    /// Returns the next event, or `None` once the stream ended.
+    pub async fn next(&mut self) -> Option<Event> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            if this.events.is_finished() {
+                return Poll::Ready(None);
+            }
+
+            // Every source is polled, so that each of them wakes up the task.
+            let mut due = this.ticks.poll_tick(cx).is_ready();
+            this.exits.retain(|(_, pidfd)| {
+                // A pidfd stays readable once the process exited, so it's dropped before
+                // the poll which reports the exit.
+                let exited = pidfd.poll_read_ready(cx).is_ready();
+                due |= exited;
+                !exited
+            });
+            if !due {
+                return Poll::Pending;
+            }
+
+            this.pending.extend(this.events.poll());
+            let events = &this.events;
+            this.exits.retain(|&(pid, _)| events.is_watching(pid));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use aya::maps::MapError;
+use aya::sys::SyscallError;
+use ebpf_memory_monitor_protocol::MapUsage;
+use libc::E2BIG;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::exit::has_exited;
+use crate::init::{grow_shared_state, FullMapPolicy, SharedState, SHARED_STATE};
+use crate::non_mut_modify::NonMutModify;
+
+/// An error returned when a process could not be monitored.
+#[derive(Debug)]
+pub enum MonitorError {
+    /// The maps are full, and the `FullMapPolicy` could not make room in them.
+    MapFull {
+        /// The maximum number of entries of each map.
+        max_entries: u32,
+    },
+    /// Reloading the eBPF programs with larger maps failed.
+    Grow(anyhow::Error),
+    /// A `bpf()` syscall failed.
+    Map(MapError),
+    /// `set_enforced_limit` was called without `InitOptions::lsm_enforcement`.
+    LsmDisabled,
+}
+
+impl Display for MonitorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorError::MapFull { max_entries } => {
+                write!(f, "the maps are full ({max_entries} entries)")
+            }
+            MonitorError::Grow(error) => write!(f, "growing the maps failed: {error}"),
+            MonitorError::Map(error) => write!(f, "{error}"),
+            MonitorError::LsmDisabled => write!(f, "LSM enforcement is not enabled"),
+        }
+    }
+}
+
+impl Error for MonitorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MonitorError::MapFull { .. } | MonitorError::LsmDisabled => None,
+            MonitorError::Grow(error) => Some(error.as_ref()),
+            MonitorError::Map(error) => Some(error),
+        }
+    }
+}
+
+impl From<MapError> for MonitorError {
+    fn from(error: MapError) -> Self {
+        MonitorError::Map(error)
+    }
+}
+
+/// Returns the current and the maximum number of entries of the monitoring maps.
+///
+/// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
+pub fn map_usage() -> Result<MapUsage, MapError> {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        Ok(MapUsage {
+            processes: shared_state.processes.get_all()?.len(),
+            enforced_limits: shared_state
+                .enforced_limits
+                .as_ref()
+                .map(|enforced_limits| enforced_limits.get_all().map(|limits| limits.len()))
+                .transpose()?,
+            max_entries: shared_state.max_listeners,
+        })
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+/// Runs `insert`, and if it fails because a map is full, makes room for `needed` more
+/// entries according to the `FullMapPolicy` and runs it again.
+pub(crate) fn insert_with_policy<F>(needed: usize, insert: F) -> Result<(), MonitorError>
+where
+    F: Fn(&SharedState) -> Result<(), MapError>,
+{
+    let policy = match with_shared_state(|shared_state| {
+        insert(shared_state).map_err(|error| (error, shared_state.options.full_map_policy))
+    }) {
+        Ok(()) => return Ok(()),
+        Err((error, policy)) if is_map_full(&error) => policy,
+        Err((error, _)) => return Err(error.into()),
+    };
+
+    match policy {
+        FullMapPolicy::Error => {
+            return Err(with_shared_state(|shared_state| MonitorError::MapFull {
+                max_entries: shared_state.max_listeners,
+            }));
+        }
+        FullMapPolicy::EvictExited => with_shared_state(|shared_state| evict_exited(shared_state, needed))?,
+        FullMapPolicy::Grow => {
+            let mut shared_state = SHARED_STATE.write().unwrap();
+            let shared_state = shared_state.as_mut().expect("ebpf-memory-monitor was not initialized");
+            let used = shared_state.processes.get_all()?.len();
+            let max_listeners = (shared_state.max_listeners.saturating_mul(2))
+                .max((used + needed).try_into().unwrap_or(u32::MAX));
+            grow_shared_state(shared_state, max_listeners).map_err(MonitorError::Grow)?;
+        }
+    }
+
+    with_shared_state(|shared_state| match insert(shared_state) {
+        Err(error) if is_map_full(&error) => Err(MonitorError::MapFull {
+            max_entries: shared_state.max_listeners,
+        }),
+        result => Ok(result?),
+    })
+}
+
+/// Removes the entries of the processes which exited the earliest, so that there is room
+/// for `needed` more entries, if enough processes have exited.
+fn evict_exited(shared_state: &SharedState, needed: usize) -> Result<(), MonitorError> {
+    let records = shared_state.processes.get_all()?;
+    let free = usize::try_from(shared_state.max_listeners)
+        .unwrap()
+        .saturating_sub(records.len());
+    let needed = needed.saturating_sub(free).max(1);
+
+    let mut exited: Vec<(u32, u64)> = records
+        .into_iter()
+        // Running processes are never evicted, like in `gc`.
+        .filter(|(pid, record)| record.exit_stats.exit_time != 0 && has_exited(*pid))
+        .map(|(pid, record)| (pid, record.exit_stats.exit_time))
+        .collect();
+    if exited.len() < needed {
+        return Err(MonitorError::MapFull {
+            max_entries: shared_state.max_listeners,
+        });
+    }
+
+    exited.sort_unstable_by_key(|&(_, exit_time)| exit_time);
+    let pids: Vec<u32> = exited.into_iter().take(needed).map(|(pid, _)| pid).collect();
+
+    shared_state.remove_processes(&pids)?;
+    Ok(())
+}
+
+fn with_shared_state<R>(f: impl FnOnce(&SharedState) -> R) -> R {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        f(shared_state)
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+// Inserting into a full hash map fails with E2BIG.
+fn is_map_full(error: &MapError) -> bool {
+    matches!(
+        error,
+        MapError::SyscallError(SyscallError { io_error, .. }) if io_error.raw_os_error() == Some(E2BIG)
+    )
+}
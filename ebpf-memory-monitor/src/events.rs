@@ -0,0 +1,173 @@
+//! Watching monitored processes for limit hits and exits.
+
+pub use ebpf_memory_monitor_protocol::wire::Event;
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::os::fd::AsFd;
+use std::time::Duration;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use crate::exit::{watch_exit, ExitWatch};
+use crate::get_process_status;
+
+// What was already reported about a process.
+#[derive(Default)]
+struct Reported {
+    rlimit_hits: u64,
+    budget_exceeded: bool,
+    denied: bool,
+}
+
+/// The events of a set of processes, found by polling their statuses.
+///
+/// The exits are confirmed with a pidfd of each process, or from `/proc` before Linux 5.3,
+/// so `Event::Exited` is only sent once all the threads exited and the status is final.
+/// Its `exit_time` is `None` if the process exited before it was monitored. The processes
+/// must not be reaped before `new` is called, as their PIDs could then be reused.
+///
+/// Iterating blocks between the polls, waking up early when a process exits, and ends
+/// once every process exited or is no longer monitored. The processes stay monitored:
+/// their statuses must still be taken or stopped.
+pub struct Events {
+    pids: Vec<u32>,
+    interval: Duration,
+    watches: HashMap<u32, ExitWatch>,
+    reported: HashMap<u32, Reported>,
+    pending: VecDeque<Event>,
+    polled: bool,
+}
+
+impl Events {
+    /// Watches the processes, polling their statuses every `interval` while iterating.
+    pub fn new(pids: &[u32], interval: Duration) -> Self {
+        let watches = pids
+            .iter()
+            .map(|&pid| {
+                // Without a pidfd, for example when out of file descriptors, the exit is
+                // checked in `/proc` instead.
+                (pid, watch_exit(pid).unwrap_or(ExitWatch::Unsupported))
+            })
+            .collect();
+        Events {
+            pids: pids.to_vec(),
+            interval,
+            watches,
+            reported: HashMap::new(),
+            pending: VecDeque::new(),
+            polled: false,
+        }
+    }
+
+    /// Also watches the process, which must not have been reaped either. Does nothing if
+    /// it's already watched.
+    pub fn watch(&mut self, pid: u32) {
+        if let Entry::Vacant(entry) = self.watches.entry(pid) {
+            entry.insert(watch_exit(pid).unwrap_or(ExitWatch::Unsupported));
+            self.pids.push(pid);
+        }
+    }
+
+    /// Polls the statuses once, returning the events which happened since the last poll.
+    /// Returns nothing once `is_finished`.
+    ///
+    /// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut finished = Vec::new();
+
+        for &pid in &self.pids {
+            // The exit is checked before reading the status, as the status is final once
+            // the process exited.
+            let exited = self.watches[&pid].has_exited(pid);
+            let Some(status) = get_process_status(pid) else {
+                events.push(Event::Stopped(pid));
+                finished.push(pid);
+                continue;
+            };
+            let reported = self.reported.entry(pid).or_default();
+
+            if let Some(hits) = &status.rlimit_hits
+                && hits.count > reported.rlimit_hits
+            {
+                reported.rlimit_hits = hits.count;
+                events.push(Event::RlimitHit(pid, hits.count));
+            }
+            if let Some(bytes) = status.budget_exceeded_bytes
+                && !reported.budget_exceeded
+            {
+                reported.budget_exceeded = true;
+                events.push(Event::BudgetExceeded(pid, bytes));
+            }
+            if let Some(bytes) = status.denied_vm_peak_bytes
+                && !reported.denied
+            {
+                reported.denied = true;
+                events.push(Event::Denied(pid, bytes));
+            }
+            if exited {
+                events.push(Event::Exited(pid, Box::new(status)));
+                finished.push(pid);
+            }
+        }
+
+        self.pids.retain(|pid| !finished.contains(pid));
+        for pid in finished {
+            self.watches.remove(&pid);
+            self.reported.remove(&pid);
+        }
+        events
+    }
+
+    /// Whether every process exited or is no longer monitored.
+    pub fn is_finished(&self) -> bool {
+        self.pids.is_empty()
+    }
+
+    /// Whether the process is still watched, as it neither exited nor stopped being
+    /// monitored.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn is_watching(&self, pid: u32) -> bool {
+        self.watches.contains_key(&pid)
+    }
+
+    /// The interval between the polls while iterating.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Blocks for up to `interval`, or until one of the processes exits.
+    fn wait(&self) {
+        let mut fds: Vec<PollFd> = self
+            .watches
+            .values()
+            .filter_map(|watch| match watch {
+                ExitWatch::PidFd(pidfd) => Some(PollFd::new(pidfd.as_fd(), PollFlags::POLLIN)),
+                ExitWatch::Reaped | ExitWatch::Unsupported => None,
+            })
+            .collect();
+        let timeout = PollTimeout::try_from(self.interval).unwrap_or(PollTimeout::MAX);
+        // An interrupted wait only makes the next poll happen earlier.
+        let _ = poll(&mut fds, timeout);
+    }
+}
+
+impl Iterator for Events {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if self.is_finished() {
+                return None;
+            }
+            if self.polled {
+                self.wait();
+            }
+            self.polled = true;
+            let events = self.poll();
+            self.pending.extend(events);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Waiting for monitored processes to exit, shared by the blocking and async APIs.
+//!
+//! The status holds the final values once the last thread of the process exited, which
+//! the eBPF programs record by setting its `exit_time`. A pidfd becomes readable at that
+//! point, and `has_exited` approximates it from `/proc` on kernels without pidfds.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::thread;
+use std::time::{Duration, Instant};
+use libc::{pid_t, ENOSYS, ESRCH, SYS_pidfd_open};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use crate::{get_process_status, ProcessStatus};
+
+/// How often the exit is checked on kernels without pidfds.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An error returned when waiting for a process to exit failed.
+#[derive(Debug)]
+pub enum WaitError {
+    /// The process is not monitored, or its exit was not recorded because it exited
+    /// before it was monitored.
+    NotMonitored,
+    /// The process was still running at the end of the timeout.
+    TimedOut,
+    /// Opening or polling the pidfd of the process failed.
+    Io(io::Error),
+}
+
+impl Display for WaitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaitError::NotMonitored => write!(f, "the exit of the process was not recorded"),
+            WaitError::TimedOut => write!(f, "the process did not exit before the timeout"),
+            WaitError::Io(error) => write!(f, "waiting for the exit failed: {error}"),
+        }
+    }
+}
+
+impl Error for WaitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WaitError::NotMonitored | WaitError::TimedOut => None,
+            WaitError::Io(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for WaitError {
+    fn from(error: io::Error) -> Self {
+        WaitError::Io(error)
+    }
+}
+
+/// How the exit of a process can be waited for, returned by `watch_exit`.
+pub(crate) enum ExitWatch {
+    /// The pidfd of the process, which becomes readable once all its threads exited.
+    PidFd(PidFd),
+    /// The process already exited and was reaped.
+    Reaped,
+    /// pidfds are not supported, so `has_exited` must be polled.
+    Unsupported,
+}
+
+impl ExitWatch {
+    /// Whether all threads of the process exited, without blocking.
+    pub(crate) fn has_exited(&self, pid: u32) -> bool {
+        match self {
+            ExitWatch::PidFd(pidfd) => {
+                let mut fds = [PollFd::new(pidfd.as_fd(), PollFlags::POLLIN)];
+                // A failed poll is treated like a process which is still running, so
+                // that the next one is retried.
+                matches!(poll(&mut fds, PollTimeout::ZERO), Ok(1..))
+            }
+            ExitWatch::Reaped => true,
+            ExitWatch::Unsupported => has_exited(pid),
+        }
+    }
+}
+
+/// A file descriptor referring to a process, opened with `pidfd_open`.
+pub(crate) struct PidFd(OwnedFd);
+
+impl AsFd for PidFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Opens a pidfd for the process. The process must not have been reaped, as its PID
+/// could then refer to another process.
+pub(crate) fn watch_exit(pid: u32) -> io::Result<ExitWatch> {
+    let ret = unsafe { libc::syscall(SYS_pidfd_open, pid as pid_t, 0) };
+
+    // `libc::syscall` returns i32 on armv7.
+    #[allow(clippy::useless_conversion)]
+    let fd: i64 = ret.into();
+    if fd >= 0 {
+        return Ok(ExitWatch::PidFd(PidFd(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })));
+    }
+
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(ESRCH) => Ok(ExitWatch::Reaped),
+        // Before Linux 5.3.
+        Some(ENOSYS) => Ok(ExitWatch::Unsupported),
+        _ => Err(error),
+    }
+}
+
+/// Whether all threads of the process exited, read from `/proc`. The thread group leader
+/// stays a zombie until it's reaped, and is the only task left once the other threads
+/// exited. Also `true` if no process has this PID, for example because it was reaped.
+pub fn has_exited(pid: u32) -> bool {
+    let Ok(stat) = fs::read_to_string(format!("/proc/{pid}/stat")) else {
+        return true;
+    };
+    // The state follows the command, which is in parentheses and may contain spaces.
+    let state = stat.rsplit_once(')').and_then(|(_, fields)| fields.split_whitespace().next());
+    matches!(state, Some("Z" | "X"))
+        && fs::read_dir(format!("/proc/{pid}/task")).map_or(true, |tasks| tasks.count() <= 1)
+}
+
+/// Waits up to `timeout`, or forever if `None`, for all the threads of the process to
+/// exit, and returns its final status. The process stays monitored: its status must
+/// still be taken or stopped.
+///
+/// The status is only read once the last thread went through the exit hook, so its peak
+/// is final. The exit is waited for with a pidfd, or by polling `/proc` before Linux 5.3.
+/// The process must not be reaped before this is called, as its PID could then be reused.
+///
+/// This function should not be called unless `initialize_with_max_listeners` was successfully called before.
+pub fn wait_for_exit(pid: u32, timeout: Option<Duration>) -> Result<ProcessStatus, WaitError> {
+    if get_process_status(pid).is_none() {
+        return Err(WaitError::NotMonitored);
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let remaining = || deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    match watch_exit(pid)? {
+        ExitWatch::PidFd(pidfd) => loop {
+            let mut fds = [PollFd::new(pidfd.as_fd(), PollFlags::POLLIN)];
+            let timeout = remaining().map_or(PollTimeout::NONE, |remaining| {
+                PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX)
+            });
+            match poll(&mut fds, timeout) {
+                Ok(0) => return Err(WaitError::TimedOut),
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(io::Error::from(errno).into()),
+            }
+        },
+        ExitWatch::Reaped => {}
+        ExitWatch::Unsupported => {
+            while !has_exited(pid) {
+                let remaining = remaining();
+                if remaining == Some(Duration::ZERO) {
+                    return Err(WaitError::TimedOut);
+                }
+                thread::sleep(remaining.map_or(POLL_INTERVAL, |remaining| remaining.min(POLL_INTERVAL)));
+            }
+        }
+    }
+    exit_status(pid)
+}
+
+/// Returns the final status of a process which exited.
+pub(crate) fn exit_status(pid: u32) -> Result<ProcessStatus, WaitError> {
+    match get_process_status(pid) {
+        Some(status) if status.exit_time.is_some() => Ok(status),
+        _ => Err(WaitError::NotMonitored),
+    }
+}
@@ -1,170 +1,654 @@
-use aya::maps::{Array, HashMap, MapData};
-use aya::programs::{FEntry, KProbe};
+use aya::maps::{Array, HashMap, Map, MapData, MapError};
+use aya::programs::links::FdLink;
+use aya::programs::{FEntry, KProbe, Lsm, Program, ProgramError, TracePoint};
+use aya::sys::{enable_stats, Stats};
 use aya::{Btf, Ebpf, EbpfLoader};
+use anyhow::{anyhow, bail, Context};
+use ebpf_memory_monitor_common::{ProcessRecord, PAGE_SHIFT_INDEX, RLIMIT_AS_INDEX};
 use libc::{c_long, RLIMIT_AS, RLIM_INFINITY};
 use nix::sys::resource::{setrlimit, Resource};
 use nix::unistd::{sysconf, SysconfVar};
-use std::sync::OnceLock;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+use crate::non_mut_modify::{probe_batch_support, NonMutModify};
+use crate::reaper::spawn_reaper;
+use crate::rlimit_log::RlimitChangeLog;
 
 pub(crate) struct SharedState {
-    // We hold these ebpf objects even if they are never accessed,
-    // as when they go out of scope, the programs will be unloaded.
-    #[allow(dead_code)]
-    pub(crate) rlimit_ebpf: Ebpf,
-    #[allow(dead_code)]
-    pub(crate) hiwater_ebpf: Ebpf,
-    pub(crate) attempted_vm_peak: HashMap<MapData, u32, i64>,
-    pub(crate) vm_peak: HashMap<MapData, u32, u64>,
+    // We hold the ebpf object as when it goes out of scope, the programs will be unloaded.
+    // It's `None` when reattaching to pinned programs, which stay attached by their pins.
+    pub(crate) ebpf: Option<Ebpf>,
+    pub(crate) processes: HashMap<MapData, u32, ProcessRecord>,
+    pub(crate) enforced_limits: Option<HashMap<MapData, u32, u64>>,
+    pub(crate) rlimit_change_log: Option<RlimitChangeLog>,
+    pub(crate) options: InitOptions,
+    // The current size of the maps, which can be larger than `InitOptions::max_listeners`
+    // after growing them with `FullMapPolicy::Grow`.
+    pub(crate) max_listeners: u32,
 }
 
-pub(crate) static SHARED_STATE: OnceLock<SharedState> = OnceLock::new();
+impl SharedState {
+    /// Removes the processes from all maps, ignoring the ones which are not monitored.
+    pub(crate) fn remove_processes(&self, pids: &[u32]) -> Result<(), MapError> {
+        self.processes.non_mut_remove_batch(pids)?;
+
+        if let Some(enforced_limits) = &self.enforced_limits {
+            enforced_limits.non_mut_remove_batch(pids)?;
+        }
+
+        if let Some(rlimit_change_log) = &self.rlimit_change_log {
+            for &pid in pids {
+                rlimit_change_log.remove(pid);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The maps pinned along with the links by `InitOptions::pin_path`.
+const PINNED_MAPS: [&str; 3] = ["PROCESSES", "RLIMIT_CHANGES", "ENFORCED_LIMITS"];
+
+// The file written next to the pins with the options the programs were loaded with,
+// which have to match the ones of the processes reattaching to them.
+const OPTIONS_FILE: &str = "OPTIONS";
+
+pub(crate) static SHARED_STATE: RwLock<Option<SharedState>> = RwLock::new(None);
+
+/// What to do when a process can't be monitored because the maps are full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullMapPolicy {
+    /// Return `MonitorError::MapFull`.
+    #[default]
+    Error,
+    /// Remove the entries of the processes which exited the earliest, discarding their
+    /// statuses. Returns `MonitorError::MapFull` if not enough processes have exited.
+    EvictExited,
+    /// Reload the eBPF programs with maps twice as large, and migrate the entries to them.
+    /// Expansions of the virtual memory which happen during the migration might not be
+    /// recorded.
+    Grow,
+}
+
+/// Options used by `initialize`.
+#[derive(Clone, Debug)]
+pub struct InitOptions {
+    max_listeners: u32,
+    lsm_enforcement: bool,
+    log_rlimit_changes: bool,
+    pub(crate) full_map_policy: FullMapPolicy,
+    // The TTL and the interval of the reaper thread.
+    reaper: Option<(Duration, Duration)>,
+    pin_path: Option<PathBuf>,
+}
+
+impl InitOptions {
+    /// Creates the default options for monitoring at most `max_listeners` processes at once.
+    pub fn new(max_listeners: u32) -> Self {
+        InitOptions {
+            max_listeners,
+            lsm_enforcement: false,
+            log_rlimit_changes: false,
+            full_map_policy: FullMapPolicy::Error,
+            reaper: None,
+            pin_path: None,
+        }
+    }
+
+    /// Enables the BPF-LSM programs used by `set_enforced_limit`.
+    ///
+    /// Requires Linux 5.7 or above built with `CONFIG_BPF_LSM` and
+    /// `CONFIG_BPF_KPROBE_OVERRIDE`, with `bpf` in the list of active LSMs.
+    pub fn lsm_enforcement(mut self, enabled: bool) -> Self {
+        self.lsm_enforcement = enabled;
+        self
+    }
+
+    /// Enables recording the `setrlimit` and `prlimit64` calls made by or on the
+    /// monitored processes in `ProcessStatus::rlimit_changes`. The calls are read by a
+    /// background thread, and `ProcessStatus::rlimit_changes_lost` is set if some of them
+    /// were dropped by the kernel because it fell behind.
+    pub fn log_rlimit_changes(mut self, enabled: bool) -> Self {
+        self.log_rlimit_changes = enabled;
+        self
+    }
+
+    /// Sets what to do when a process can't be monitored because the maps are full.
+    pub fn full_map_policy(mut self, policy: FullMapPolicy) -> Self {
+        self.full_map_policy = policy;
+        self
+    }
+
+    /// Starts a background thread which calls `gc(ttl)` every `interval`, to stop
+    /// monitoring the processes which exited more than `ttl` ago. The reclaimed PIDs
+    /// are logged with the `log` crate.
+    pub fn reaper(mut self, ttl: Duration, interval: Duration) -> Self {
+        self.reaper = Some((ttl, interval));
+        self
+    }
+
+    /// Pins the maps and the links of the programs in the `path` directory on the BPF
+    /// filesystem, so that the programs keep collecting results after this process exits.
+    /// If `path` already contains pinned objects, `initialize` reattaches to them instead
+    /// of loading new programs, keeping the entries of the processes monitored before.
+    ///
+    /// The directory is created if it doesn't exist, and must not be used for anything
+    /// else. Reattaching fails if `lsm_enforcement` or `log_rlimit_changes` differ from
+    /// the options the programs were loaded with, see `InitOptions::from_pin_path`.
+    ///
+    /// A process which reattached doesn't read the `setrlimit` and `prlimit64` calls, as
+    /// it would take them over from the process which pinned the programs, so
+    /// `ProcessStatus::rlimit_changes` stays empty. The kprobe backend can only be pinned
+    /// on Linux 5.15 or above.
+    pub fn pin_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pin_path = Some(path.into());
+        self
+    }
+
+    /// Reads the options the programs pinned in `path` were loaded with, to reattach to
+    /// them with `initialize` without knowing how they were loaded.
+    pub fn from_pin_path(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let options_path = path.join(OPTIONS_FILE);
+        let contents = fs::read_to_string(&options_path)
+            .with_context(|| format!("reading {} failed", options_path.display()))?;
+
+        let mut options = InitOptions::new(0).pin_path(path);
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("invalid line in {}: {line}", options_path.display());
+            };
+            match key {
+                "max_listeners" => options.max_listeners = value.parse()?,
+                "lsm_enforcement" => options.lsm_enforcement = value.parse()?,
+                "log_rlimit_changes" => options.log_rlimit_changes = value.parse()?,
+                _ => bail!("unknown option in {}: {key}", options_path.display()),
+            }
+        }
+        Ok(options)
+    }
+
+    // The contents of `OPTIONS_FILE`, read back by `from_pin_path`.
+    fn to_pinned(&self) -> String {
+        format!(
+            "max_listeners={}\nlsm_enforcement={}\nlog_rlimit_changes={}\n",
+            self.max_listeners, self.lsm_enforcement, self.log_rlimit_changes,
+        )
+    }
+}
 
 /// Requires the:
 /// - `CAP_SYS_RESOURCE`
 /// - `CAP_SYS_BPF`
 /// - `CAP_PERFMON`
+///
 /// capabilities to be set.
 pub fn initialize_with_max_listeners(max_listeners: u32) -> anyhow::Result<()> {
-    SHARED_STATE.get_or_try_init(|| {
+    initialize(InitOptions::new(max_listeners))
+}
+
+/// Same as `initialize_with_max_listeners`, but with additional options.
+pub fn initialize(options: InitOptions) -> anyhow::Result<()> {
+    let mut shared_state = SHARED_STATE.write().unwrap();
+    if shared_state.is_none() {
         // Bump the memlock rlimit. This is needed for older kernels that don't use the
         // new memcg-based accounting, see https://lwn.net/Articles/837122/
         setrlimit(Resource::RLIMIT_MEMLOCK, RLIM_INFINITY, RLIM_INFINITY)?;
 
-        let (rlimit_ebpf, attempted_vm_peak) =
-            initialize_rlimit_fentry(max_listeners).unwrap_or(
-                initialize_rlimit_kprobe(max_listeners)?
-            );
-        let (hiwater_ebpf, vm_peak) =
-            initialize_hiwater_fentry(max_listeners).unwrap_or(
-                initialize_hiwater_kprobe(max_listeners)?
-            );
-
-        Ok::<SharedState, anyhow::Error>(SharedState {
-            rlimit_ebpf,
-            hiwater_ebpf,
-            attempted_vm_peak,
-            vm_peak,
-        })
-    })?;
+        let max_listeners = options.max_listeners;
+        let reaper = options.reaper;
+        let state = match &options.pin_path {
+            Some(pin_path) if pin_path.join("PROCESSES").exists() => reattach_shared_state(options)?,
+            Some(_) => {
+                let state = load_shared_state(options, max_listeners)?;
+                commit_pins(&state.options)?;
+                state
+            }
+            None => load_shared_state(options, max_listeners)?,
+        };
+        probe_batch_support(&state.processes)?;
+        *shared_state = Some(state);
+
+        if let Some((ttl, interval)) = reaper {
+            spawn_reaper(ttl, interval)?;
+        }
+    }
 
     Ok(())
 }
 
+/// The kind of eBPF programs monitoring the processes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// fentry programs, used on Linux 5.5 or above when the kernel has BTF.
+    FEntry,
+    /// kprobe programs, used when the fentry ones can't be attached.
+    KProbe,
+}
+
+/// How the library was initialized, returned by `backend_info`.
+#[derive(Clone, Debug)]
+pub struct BackendInfo {
+    /// The backend, or `None` if the programs were reattached from `InitOptions::pin_path`.
+    pub backend: Option<Backend>,
+    /// The names of the loaded eBPF programs, empty if they were reattached.
+    pub programs: Vec<String>,
+    /// The current capacity of the maps, which can be larger than the one passed to
+    /// `InitOptions::new` with `FullMapPolicy::Grow`.
+    pub max_listeners: u32,
+    /// The options passed to `initialize`.
+    pub options: InitOptions,
+}
+
+/// Returns how the library was initialized, or `None` if it's not initialized.
+pub fn backend_info() -> Option<BackendInfo> {
+    let shared_state = SHARED_STATE.read().unwrap();
+    let shared_state = shared_state.as_ref()?;
+    let ebpf = shared_state.ebpf.as_ref();
+
+    let backend = ebpf.and_then(|ebpf| match ebpf.program("on_do_exit") {
+        Some(Program::FEntry(_)) => Some(Backend::FEntry),
+        Some(Program::KProbe(_)) => Some(Backend::KProbe),
+        _ => None,
+    });
+    let programs = ebpf
+        .map(|ebpf| {
+            ebpf.programs()
+                .filter(|(_, program)| program.fd().is_ok())
+                .map(|(name, _)| name.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(BackendInfo {
+        backend,
+        programs,
+        max_listeners: shared_state.max_listeners,
+        options: shared_state.options.clone(),
+    })
+}
+
+/// How often an eBPF program ran, and for how long, returned by `program_stats`.
+#[derive(Clone, Debug)]
+pub struct ProgramStats {
+    /// The name of the program.
+    pub name: String,
+    /// The number of times the program ran.
+    pub run_count: u64,
+    /// The total time the program ran for.
+    pub run_time: Duration,
+}
+
+/// Returns the run statistics of the loaded eBPF programs, or an empty list if they were
+/// reattached from `InitOptions::pin_path`.
+///
+/// The kernel only collects the statistics while the file descriptor returned by
+/// `enable_program_stats` is open, so they stay at zero otherwise.
+pub fn program_stats() -> Vec<ProgramStats> {
+    let shared_state = SHARED_STATE.read().unwrap();
+    let Some(ebpf) = shared_state.as_ref().and_then(|shared_state| shared_state.ebpf.as_ref()) else {
+        return Vec::new();
+    };
+
+    ebpf.programs()
+        .filter_map(|(name, program)| {
+            let info = program.info().ok()?;
+            Some(ProgramStats {
+                name: name.to_string(),
+                run_count: info.run_count(),
+                run_time: info.run_time(),
+            })
+        })
+        .collect()
+}
+
+/// Makes the kernel collect the statistics returned by `program_stats`, for all eBPF
+/// programs, until the returned file descriptor is closed.
+///
+/// Requires Linux 5.8 or above and the `CAP_SYS_ADMIN` capability.
+pub fn enable_program_stats() -> anyhow::Result<OwnedFd> {
+    Ok(enable_stats(Stats::RunTime)?)
+}
+
+/// Loads and attaches the programs. When pinning, the maps and the links are pinned in
+/// a staging directory, which is moved to the pin path by `commit_pins`.
+fn load_shared_state(options: InitOptions, max_listeners: u32) -> anyhow::Result<SharedState> {
+    let staging_path = options.pin_path.as_deref().map(staging_pin_path);
+    if let Some(staging_path) = &staging_path {
+        // The directory might be left over from a previous failure.
+        let _ = fs::remove_dir_all(staging_path);
+    }
+
+    let result = load_programs(&options, max_listeners, staging_path.as_deref());
+    if result.is_err()
+        && let Some(staging_path) = &staging_path
+    {
+        let _ = fs::remove_dir_all(staging_path);
+    }
+    let mut ebpf = result?;
 
-fn initialize_rlimit_kprobe(max_listeners: u32) -> anyhow::Result<(Ebpf, HashMap<MapData, u32, i64>)> {
-    Ok(initialize_rlimit_program(
+    let rlimit_change_log = if options.log_rlimit_changes {
+        Some(RlimitChangeLog::new(ebpf.take_map("RLIMIT_CHANGES").unwrap())?)
+    } else {
+        None
+    };
+    let enforced_limits = if options.lsm_enforcement {
+        Some(HashMap::try_from(ebpf.take_map("ENFORCED_LIMITS").unwrap())?)
+    } else {
+        None
+    };
+    let processes = HashMap::try_from(ebpf.take_map("PROCESSES").unwrap())?;
+
+    Ok(SharedState {
+        ebpf: Some(ebpf),
+        processes,
+        enforced_limits,
+        rlimit_change_log,
+        options,
         max_listeners,
-        aya::include_bytes_aligned!(concat!(
-            env!("OUT_DIR"),
-            "/rlimit-kprobe-bin"
-        )),
-        |ebpf| {
-            let program: &mut KProbe =
-                ebpf.program_mut("on_may_expand_vm").unwrap().try_into()?;
-            program.load()?;
-            program.attach("may_expand_vm", 0)?;
-            Ok(())
+    })
+}
+
+fn load_programs(options: &InitOptions, max_listeners: u32, pin_path: Option<&Path>) -> anyhow::Result<Ebpf> {
+    // The links are only taken out of the programs when they have to be pinned,
+    // as the kprobe and tracepoint links can't be pinned on older kernels.
+    let mut links = pin_path.map(|_| Vec::new());
+
+    let mut ebpf = initialize_fentry(max_listeners, &mut links)
+        .or_else(|_| {
+            // Dropping the links of the fentry programs detaches them.
+            if let Some(links) = &mut links {
+                links.clear();
+            }
+            initialize_kprobe(max_listeners, &mut links)
+        })?;
+    if options.log_rlimit_changes {
+        attach_rlimit_change_log(&mut ebpf, &mut links)?;
+    }
+    if options.lsm_enforcement {
+        attach_enforce_lsm(&mut ebpf, &mut links)?;
+    }
+
+    if let Some(pin_path) = pin_path
+        && let Some(links) = links
+    {
+        fs::create_dir_all(pin_path)?;
+        fs::write(pin_path.join(OPTIONS_FILE), options.to_pinned())?;
+        for name in PINNED_MAPS {
+            if let Some(map) = ebpf.map(name) {
+                map.pin(pin_path.join(name))?;
+            }
+        }
+        // The programs stay attached as long as the pins exist.
+        for (name, link) in links {
+            link.pin(pin_path.join(name))?;
         }
-    )?)
+    }
+
+    Ok(ebpf)
 }
 
-fn initialize_rlimit_fentry(max_listeners: u32) -> anyhow::Result<(Ebpf, HashMap<MapData, u32, i64>)> {
-    Ok(initialize_rlimit_program(
+/// Opens the maps pinned by a previous `initialize`, whose programs are kept attached by
+/// their pinned links. The perf buffers of `RLIMIT_CHANGES` are left to the process which
+/// pinned them.
+fn reattach_shared_state(options: InitOptions) -> anyhow::Result<SharedState> {
+    let pin_path = options.pin_path.clone().unwrap();
+    let pinned_options = InitOptions::from_pin_path(&pin_path)?;
+    if pinned_options.lsm_enforcement != options.lsm_enforcement
+        || pinned_options.log_rlimit_changes != options.log_rlimit_changes
+    {
+        bail!(
+            "the programs pinned in {} were loaded with lsm_enforcement={} and \
+             log_rlimit_changes={}, which differ from the given options",
+            pin_path.display(),
+            pinned_options.lsm_enforcement,
+            pinned_options.log_rlimit_changes,
+        );
+    }
+    let open_pinned = |name: &str| {
+        MapData::from_pin(pin_path.join(name))
+            .map_err(|error| anyhow!("opening the pinned map {name} failed: {error}"))
+    };
+
+    let processes = open_pinned("PROCESSES")?;
+    let max_listeners = processes.info()?.max_entries();
+    let processes = HashMap::try_from(Map::HashMap(processes))?;
+
+    let enforced_limits = if options.lsm_enforcement {
+        Some(HashMap::try_from(Map::HashMap(open_pinned("ENFORCED_LIMITS")?))?)
+    } else {
+        None
+    };
+
+    Ok(SharedState {
+        ebpf: None,
+        processes,
+        enforced_limits,
+        rlimit_change_log: None,
+        options,
         max_listeners,
-        aya::include_bytes_aligned!(concat!(
-            env!("OUT_DIR"),
-            "/rlimit-fentry-bin"
-        )),
-        |ebpf| {
-            let program: &mut FEntry =
-                ebpf.program_mut("on_may_expand_vm").unwrap().try_into()?;
-            program.load("may_expand_vm", &Btf::from_sys_fs()?)?;
-            program.attach()?;
-            Ok(())
+    })
+}
+
+/// Replaces the pinned objects with the ones pinned by `load_shared_state`. Removing the
+/// old pins detaches the programs which were only kept attached by them.
+fn commit_pins(options: &InitOptions) -> anyhow::Result<()> {
+    if let Some(pin_path) = &options.pin_path {
+        match fs::remove_dir_all(pin_path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
         }
-    )?)
+        fs::rename(staging_pin_path(pin_path), pin_path)?;
+    }
+    Ok(())
 }
 
-fn initialize_rlimit_program<F>(max_listeners: u32, program_data: &[u8], program_loader: F)
-    -> anyhow::Result<(Ebpf, HashMap<MapData, u32, i64>)>
-where
-    F: Fn(&mut Ebpf) -> anyhow::Result<()>,
-{
-    let mut ebpf: Ebpf = EbpfLoader::new()
-        .set_max_entries("ATTEMPTED_VM_PEAK", max_listeners)
-        .load(program_data)?;
+fn staging_pin_path(pin_path: &Path) -> PathBuf {
+    let mut staging_path = pin_path.as_os_str().to_owned();
+    staging_path.push(".staging");
+    PathBuf::from(staging_path)
+}
 
-    let mut constants: Array<&mut MapData, u64> =
-        Array::try_from(ebpf.map_mut("CONSTANTS").unwrap())?;
-    constants.set(0, &(RLIMIT_AS.try_into().unwrap()), 0)?;
-    constants.set(1, &get_page_shift()?, 0)?;
+/// Removes the pins, so that the programs are detached once the shared state is dropped.
+pub(crate) fn remove_pins(options: &InitOptions) -> anyhow::Result<()> {
+    if let Some(pin_path) = &options.pin_path {
+        fs::remove_dir_all(pin_path)?;
+    }
+    Ok(())
+}
+
+/// Replaces the eBPF programs with ones whose maps have room for at least `max_listeners`
+/// processes, and migrates the entries to them.
+///
+/// The new programs are attached before the old ones are detached, so that only the
+/// updates made to the old maps after they are copied can be lost.
+pub(crate) fn grow_shared_state(shared_state: &mut SharedState, max_listeners: u32) -> anyhow::Result<()> {
+    let new_state = load_shared_state(shared_state.options.clone(), max_listeners)?;
+
+    let processes = shared_state.processes.get_all()?;
+    let (pids, records): (Vec<u32>, Vec<ProcessRecord>) = processes.into_iter().unzip();
+    new_state.processes.non_mut_insert_batch(&pids, &records, 0)?;
+
+    if let Some(enforced_limits) = &shared_state.enforced_limits
+        && let Some(new_enforced_limits) = &new_state.enforced_limits
+    {
+        let (pids, limits): (Vec<u32>, Vec<u64>) = enforced_limits.get_all()?.into_iter().unzip();
+        new_enforced_limits.non_mut_insert_batch(&pids, &limits, 0)?;
+    }
 
-    program_loader(&mut ebpf)?;
+    if let Some(rlimit_change_log) = &mut shared_state.rlimit_change_log
+        && let Some(new_rlimit_change_log) = &new_state.rlimit_change_log
+    {
+        rlimit_change_log.migrate_to(new_rlimit_change_log);
+    }
 
-    let attempted_vm_peak = HashMap::try_from(ebpf.take_map("ATTEMPTED_VM_PEAK").unwrap())?;
-    Ok((
-        ebpf,
-        attempted_vm_peak,
-    ))
+    commit_pins(&new_state.options)?;
+    // Dropping the old state detaches the old programs.
+    *shared_state = new_state;
+    Ok(())
 }
 
+/// Takes the link of an attached program out of it, if the links have to be pinned.
+fn keep_link<L>(
+    links: &mut Option<Vec<(&'static str, FdLink)>>,
+    name: &'static str,
+    take_link: impl FnOnce() -> Result<L, ProgramError>,
+) -> anyhow::Result<()>
+where
+    FdLink: TryFrom<L>,
+    anyhow::Error: From<<FdLink as TryFrom<L>>::Error>,
+{
+    if let Some(links) = links {
+        links.push((name, FdLink::try_from(take_link()?)?));
+    }
+    Ok(())
+}
 
-fn initialize_hiwater_kprobe(max_listeners: u32) -> anyhow::Result<(Ebpf, HashMap<MapData, u32, u64>)> {
-    Ok(initialize_hiwater_program(
+fn initialize_kprobe(
+    max_listeners: u32,
+    links: &mut Option<Vec<(&'static str, FdLink)>>,
+) -> anyhow::Result<Ebpf> {
+    let mut ebpf = load_ebpf(
         max_listeners,
         aya::include_bytes_aligned!(concat!(
             env!("OUT_DIR"),
-            "/hiwater-kprobe-bin"
+            "/memory-monitor-kprobe-bin"
         )),
-        |ebpf| {
-            let program: &mut KProbe = ebpf.program_mut("on_do_exit").unwrap().try_into()?;
-            program.load()?;
-            program.attach("do_exit", 0)?;
-            Ok(())
-        }
-    )?)
+    )?;
+
+    let program: &mut KProbe =
+        ebpf.program_mut("on_may_expand_vm").unwrap().try_into()?;
+    program.load()?;
+    let link_id = program.attach("may_expand_vm", 0)?;
+    keep_link(links, "on_may_expand_vm", || program.take_link(link_id))?;
+
+    let program: &mut KProbe = ebpf.program_mut("on_do_exit").unwrap().try_into()?;
+    program.load()?;
+    let link_id = program.attach("do_exit", 0)?;
+    keep_link(links, "on_do_exit", || program.take_link(link_id))?;
+
+    attach_exit_marker(&mut ebpf, links)?;
+
+    Ok(ebpf)
 }
 
-fn initialize_hiwater_fentry(max_listeners: u32) -> anyhow::Result<(Ebpf, HashMap<MapData, u32, u64>)> {
-    Ok(initialize_hiwater_program(
+fn initialize_fentry(
+    max_listeners: u32,
+    links: &mut Option<Vec<(&'static str, FdLink)>>,
+) -> anyhow::Result<Ebpf> {
+    let mut ebpf = load_ebpf(
         max_listeners,
         aya::include_bytes_aligned!(concat!(
             env!("OUT_DIR"),
-            "/hiwater-fentry-bin"
+            "/memory-monitor-fentry-bin"
         )),
-        |ebpf| {
-            let program: &mut FEntry =
-                ebpf.program_mut("on_do_exit").unwrap().try_into()?;
-            program.load("do_exit", &Btf::from_sys_fs()?)?;
-            program.attach()?;
-            Ok(())
-        }
-    )?)
+    )?;
+
+    let btf = Btf::from_sys_fs()?;
+
+    let program: &mut FEntry =
+        ebpf.program_mut("on_may_expand_vm").unwrap().try_into()?;
+    program.load("may_expand_vm", &btf)?;
+    let link_id = program.attach()?;
+    keep_link(links, "on_may_expand_vm", || program.take_link(link_id))?;
+
+    let program: &mut FEntry = ebpf.program_mut("on_do_exit").unwrap().try_into()?;
+    program.load("do_exit", &btf)?;
+    let link_id = program.attach()?;
+    keep_link(links, "on_do_exit", || program.take_link(link_id))?;
+
+    attach_exit_marker(&mut ebpf, links)?;
+
+    Ok(ebpf)
 }
 
-fn initialize_hiwater_program<F>(max_listeners: u32, program_data: &[u8], program_loader: F)
-    -> anyhow::Result<(Ebpf, HashMap<MapData, u32, u64>)>
-where
-    F: Fn(&mut Ebpf) -> anyhow::Result<()>,
-{
+fn load_ebpf(max_listeners: u32, program_data: &[u8]) -> anyhow::Result<Ebpf> {
+    // The maps which don't exist in the kprobe object are ignored.
     let mut ebpf: Ebpf = EbpfLoader::new()
-        .set_max_entries("VM_PEAK", max_listeners)
+        .set_max_entries("PROCESSES", max_listeners)
+        .set_max_entries("ENFORCED_LIMITS", max_listeners)
+        .set_max_entries("MMAP_LENGTHS", max_listeners)
         .load(program_data)?;
 
     let mut constants: Array<&mut MapData, u64> =
         Array::try_from(ebpf.map_mut("CONSTANTS").unwrap())?;
-    constants.set(0, &get_page_shift()?, 0)?;
+    constants.set(RLIMIT_AS_INDEX, u64::from(RLIMIT_AS), 0)?;
+    constants.set(PAGE_SHIFT_INDEX, get_page_shift()?, 0)?;
 
-    program_loader(&mut ebpf)?;
+    Ok(ebpf)
+}
+
+fn attach_exit_marker(
+    ebpf: &mut Ebpf,
+    links: &mut Option<Vec<(&'static str, FdLink)>>,
+) -> anyhow::Result<()> {
+    let program: &mut TracePoint =
+        ebpf.program_mut("on_sched_process_exit").unwrap().try_into()?;
+    program.load()?;
+    let link_id = program.attach("sched", "sched_process_exit")?;
+    keep_link(links, "on_sched_process_exit", || program.take_link(link_id))?;
 
-    let vm_peak = HashMap::try_from(ebpf.take_map("VM_PEAK").unwrap())?;
-    Ok((
-        ebpf,
-        vm_peak,
-    ))
+    Ok(())
+}
+
+fn attach_rlimit_change_log(
+    ebpf: &mut Ebpf,
+    links: &mut Option<Vec<(&'static str, FdLink)>>,
+) -> anyhow::Result<()> {
+    let program: &mut TracePoint =
+        ebpf.program_mut("on_sys_enter_setrlimit").unwrap().try_into()?;
+    program.load()?;
+    let link_id = program.attach("syscalls", "sys_enter_setrlimit")?;
+    keep_link(links, "on_sys_enter_setrlimit", || program.take_link(link_id))?;
+
+    let program: &mut TracePoint =
+        ebpf.program_mut("on_sys_enter_prlimit64").unwrap().try_into()?;
+    program.load()?;
+    let link_id = program.attach("syscalls", "sys_enter_prlimit64")?;
+    keep_link(links, "on_sys_enter_prlimit64", || program.take_link(link_id))?;
+
+    Ok(())
+}
+
+fn attach_enforce_lsm(
+    ebpf: &mut Ebpf,
+    links: &mut Option<Vec<(&'static str, FdLink)>>,
+) -> anyhow::Result<()> {
+    // The LSM programs only exist in the fentry object, as both need BTF and trampolines.
+    let program: &mut TracePoint = ebpf.program_mut("on_sys_enter_mmap")
+        .ok_or_else(|| anyhow!("LSM enforcement is not supported by the kprobe backend"))?
+        .try_into()?;
+    program.load()?;
+    let link_id = program.attach("syscalls", "sys_enter_mmap")?;
+    keep_link(links, "on_sys_enter_mmap", || program.take_link(link_id))?;
+
+    let program: &mut TracePoint = ebpf.program_mut("on_sys_exit_mmap").unwrap().try_into()?;
+    program.load()?;
+    let link_id = program.attach("syscalls", "sys_exit_mmap")?;
+    keep_link(links, "on_sys_exit_mmap", || program.take_link(link_id))?;
+
+    let btf = Btf::from_sys_fs()?;
+
+    let program: &mut Lsm = ebpf.program_mut("on_mmap_file").unwrap().try_into()?;
+    program.load("mmap_file", &btf)?;
+    let link_id = program.attach()?;
+    keep_link(links, "on_mmap_file", || program.take_link(link_id))?;
+
+    // The brk and mremap calls are denied by overriding the return value of the syscalls,
+    // as the LSM hooks can't make them fail.
+    for (name, function) in [("on_sys_brk", "__x64_sys_brk"), ("on_sys_mremap", "__x64_sys_mremap")] {
+        let program: &mut KProbe = ebpf.program_mut(name).unwrap().try_into()?;
+        program.load()?;
+        let link_id = program.attach(function, 0)?;
+        keep_link(links, name, || program.take_link(link_id))?;
+    }
+
+    Ok(())
 }
 
 fn get_page_shift() -> anyhow::Result<u64> {
     let page_size: c_long = sysconf(SysconfVar::PAGE_SIZE)?.expect("page size is invalid");
-    Ok(page_size.ilog2().try_into()?)
+    Ok(page_size.ilog2().into())
 }
@@ -1,138 +1,390 @@
+//! Monitors the virtual memory of processes with eBPF programs: the peak they reached, the
+//! expansions rejected because of `RLIMIT_AS`, and optionally budgets and enforced limits.
+//! Call `init::initialize` before any other function.
+
 #![warn(missing_docs)]
-#![feature(once_cell_try)]
 
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+mod capacity;
+pub mod events;
+mod exit;
+/// Loading the eBPF programs, and the options and information about them.
 pub mod init;
 mod non_mut_modify;
+mod reaper;
+mod rlimit_log;
+pub mod run;
+
+pub use capacity::{map_usage, MonitorError};
+pub use exit::{has_exited, wait_for_exit, WaitError};
+pub use ebpf_memory_monitor_protocol::{
+    BudgetAction, MapUsage, ProcessStatus, RlimitChange, RlimitHits,
+};
+pub use reaper::{gc, take_expired};
 
-use std::borrow::Borrow;
-use std::fmt::{Debug, Display};
-use ebpf_memory_monitor_common::RLIMIT_AS_NOT_HIT;
-use std::io;
-use std::io::BufRead;
-use aya::maps::{HashMap, MapData};
-use aya::Pod;
-use crate::init::{initialize_with_max_listeners, SHARED_STATE};
+use std::collections::HashMap as StdHashMap;
+use ebpf_memory_monitor_common::{ProcessRecord, VmLimits};
+use std::time::Duration;
+use libc::{SIGKILL, SIGUSR1, SIGXCPU};
+use crate::capacity::insert_with_policy;
+use crate::init::{remove_pins, SHARED_STATE};
 use crate::non_mut_modify::NonMutModify;
+use crate::reaper::stop_reaper;
+use crate::rlimit_log::{ProcessChanges, RlimitChangeLog};
 
-#[test]
-fn test_not_main() {
-    initialize_with_max_listeners(1024).unwrap();
-
-    println!("Waiting for PIDs to listen to...");
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let line = line.expect("Failed to read line");
-
-        fn parse_command(line: &str, command: &str) -> Option<u32> {
-            if line.starts_with(command) {
-                let pid = line.split_whitespace().nth(1)?;
-                pid.parse::<u32>().ok()
-            } else {
-                None
-            }
-        }
+/// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
+///
+/// Fails with `MonitorError::MapFull` if the maps are full and the `FullMapPolicy` could
+/// not make room in them.
+pub fn start_monitoring_process(pid: u32) -> Result<(), MonitorError> {
+    start_monitoring(pid, ProcessRecord::default())
+}
 
-        if line.starts_with("exit") {
-            println!("Exiting...");
-            return;
-        }
-        else if line.starts_with("debug") {
-            if let Some(shared_state) = SHARED_STATE.get() {
-                fn print_hash_map<A, B, C>(name: &str, map: &HashMap<A, B, C>)
-                where
-                    A: Borrow<MapData>,
-                    B: Pod + Display,
-                    C: Pod + Display
-                {
-                    println!(
-                        "{}: {}",
-                        name,
-                        map.iter()
-                            .map(|el| {
-                                let el = el.unwrap();
-                                format!("({}: {})", el.0, el.1)
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )
-                }
-
-                print_hash_map("VM_PEAK", &shared_state.vm_peak);
-                print_hash_map("ATTEMPTED_VM_PEAK", &shared_state.attempted_vm_peak);
-            } else {
-                panic!("ebpf-memory-monitor was not initialized");
-            }
-        }
-        else if let Some(pid) = parse_command(&line, "start ") {
-            start_monitoring_process(pid);
-            println!("Started monitoring PID {}", pid);
-        }
-        else if let Some(pid) = parse_command(&line, "stop ") {
-            stop_monitoring_process(pid);
-            println!("Stopped monitoring PID {}", pid);
-        }
-        else if let Some(pid) = parse_command(&line, "status ") {
-            println!("Process status: {:?}", get_process_status(pid));
-        }
-        else {
-            println!("Invalid command. Please enter a valid command.");
+/// Same as calling `start_monitoring_process` for each of the PIDs, but inserts all of them
+/// with a single `bpf()` syscall on Linux 5.6 or above.
+///
+/// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
+pub fn start_monitoring_processes(pids: &[u32]) -> Result<(), MonitorError> {
+    let records = vec![ProcessRecord::default(); pids.len()];
+    with_rlimit_change_log(pids, || {
+        insert_with_policy(pids.len(), |shared_state| {
+            shared_state.processes.non_mut_insert_batch(pids, &records, 0)
+        })
+    })
+}
+
+/// Starts collecting the rlimit changes of the processes before running `insert`, so that
+/// none are missed once they are monitored, and stops again if it fails.
+fn with_rlimit_change_log(
+    pids: &[u32],
+    insert: impl FnOnce() -> Result<(), MonitorError>,
+) -> Result<(), MonitorError> {
+    update_rlimit_change_log(pids, RlimitChangeLog::add);
+    let result = insert();
+    if result.is_err() {
+        update_rlimit_change_log(pids, RlimitChangeLog::remove);
+    }
+    result
+}
+
+fn update_rlimit_change_log(pids: &[u32], update: fn(&RlimitChangeLog, u32)) {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref()
+        && let Some(rlimit_change_log) = &shared_state.rlimit_change_log
+    {
+        for &pid in pids {
+            update(rlimit_change_log, pid);
         }
     }
 }
 
+fn budget_signal(action: BudgetAction) -> u32 {
+    let signal = match action {
+        BudgetAction::Kill => SIGKILL,
+        BudgetAction::CpuLimitExceeded => SIGXCPU,
+        BudgetAction::User1 => SIGUSR1,
+    };
+    signal.try_into().unwrap()
+}
+
+
+/// Starts monitoring the process like `start_monitoring_process`, and additionally sends
+/// the signal specified by `action` to it once it tries to grow its virtual memory above
+/// `budget_bytes`. Unlike `RLIMIT_AS`, the budget does not make the allocation fail, and
+/// it does not require changing the rlimits of the monitored process.
+///
+/// The signal is sent at most once. Requires Linux 5.3 or above.
+///
 /// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
-pub fn start_monitoring_process(pid: u32) {
-    if let Some(shared_state) = SHARED_STATE.get() {
-        shared_state.attempted_vm_peak
-            .non_mut_insert(pid, RLIMIT_AS_NOT_HIT, 0)
-            .expect("insert to attempted_vm_peak failed");
-
-        shared_state.vm_peak
-            .non_mut_insert(pid, 0, 0)
-            .expect("insert to vm_peak failed");
-    } else {
-        panic!("ebpf-memory-monitor was not initialized");
+pub fn start_monitoring_process_with_budget(
+    pid: u32,
+    budget_bytes: u64,
+    action: BudgetAction,
+) -> Result<(), MonitorError> {
+    start_monitoring(pid, ProcessRecord {
+        vm_limits: VmLimits {
+            budget: budget_bytes,
+            budget_signal: budget_signal(action),
+            ..VmLimits::default()
+        },
+        ..ProcessRecord::default()
+    })
+}
+
+/// Makes `mmap`, `mremap` and `brk` calls of the process fail once its virtual memory
+/// would grow above `limit_bytes`, like `RLIMIT_AS`. The limit can be changed at any time,
+/// and it's removed by `stop_monitoring_process`.
+///
+/// Fails with `MonitorError::LsmDisabled` if `InitOptions::lsm_enforcement` was not enabled.
+///
+/// This method should not be called unless `initialize` was successfully called before.
+pub fn set_enforced_limit(pid: u32, limit_bytes: u64) -> Result<(), MonitorError> {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref()
+        && shared_state.enforced_limits.is_none()
+    {
+        return Err(MonitorError::LsmDisabled);
     }
+
+    // Growing the maps keeps the options, so the limits map still exists afterwards.
+    insert_with_policy(1, |shared_state| match &shared_state.enforced_limits {
+        Some(enforced_limits) => enforced_limits.non_mut_insert(pid, limit_bytes, 0),
+        None => Ok(()),
+    })
 }
 
-#[derive(Debug)]
-pub struct ProcessStatus {
-    pub vm_peak_bytes: u64,
-    pub attempted_vm_peak_bytes: Option<u64>,
+fn start_monitoring(pid: u32, record: ProcessRecord) -> Result<(), MonitorError> {
+    with_rlimit_change_log(&[pid], || {
+        insert_with_policy(1, |shared_state| {
+            shared_state.processes.non_mut_insert(pid, record, 0)
+        })
+    })
 }
 
+fn process_status(
+    record: &ProcessRecord,
+    enforced_limit: Option<u64>,
+    rlimit_changes: ProcessChanges,
+) -> ProcessStatus {
+    let vm_limits = &record.vm_limits;
+    let exit_stats = &record.exit_stats;
+    let exited = exit_stats.exit_time != 0;
+
+    ProcessStatus {
+        vm_peak_bytes: exit_stats.vm_peak,
+        attempted_vm_peak_bytes: (vm_limits.rlimit_hits != 0)
+            .then_some(vm_limits.first_attempted_vm),
+        rlimit_hits: (vm_limits.rlimit_hits != 0).then(|| RlimitHits {
+            count: vm_limits.rlimit_hits,
+            first_attempted_bytes: vm_limits.first_attempted_vm,
+            max_attempted_bytes: vm_limits.max_attempted_vm,
+            first_hit_time: Duration::from_nanos(vm_limits.first_hit_time),
+            last_hit_time: Duration::from_nanos(vm_limits.last_hit_time),
+            max_attempted_soft_limit: vm_limits.max_attempted_rlimit_cur,
+            max_attempted_hard_limit: vm_limits.max_attempted_rlimit_max,
+        }),
+        rlimit_changes: rlimit_changes.changes,
+        rlimit_changes_lost: rlimit_changes.lost,
+        budget_exceeded_bytes: (vm_limits.budget_exceeded != 0)
+            .then_some(vm_limits.budget_exceeded),
+        enforced_limit_bytes: enforced_limit,
+        denied_vm_peak_bytes: (record.denied_vm != 0).then_some(record.denied_vm),
+        user_time: Duration::from_nanos(exit_stats.utime),
+        system_time: Duration::from_nanos(exit_stats.stime),
+        voluntary_context_switches: exit_stats.nvcsw,
+        involuntary_context_switches: exit_stats.nivcsw,
+        start_time: exited.then(|| Duration::from_nanos(exit_stats.start_time)),
+        exit_time: exited.then(|| Duration::from_nanos(exit_stats.exit_time)),
+    }
+}
+
+/// Returns the status of the process, or `None` if it's not monitored. The process stays
+/// monitored.
 pub fn get_process_status(pid: u32) -> Option<ProcessStatus> {
-    if let Some(shared_state) = SHARED_STATE.get() {
-        let attempted_vm_peak = shared_state
-            .attempted_vm_peak
-            .get(&pid, 0).ok()?;
-        let vm_peak = shared_state
-            .vm_peak
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        let record = shared_state
+            .processes
             .get(&pid, 0).ok()?;
+        let enforced_limit = shared_state
+            .enforced_limits
+            .as_ref()
+            .and_then(|enforced_limits| enforced_limits.get(&pid, 0).ok());
+        let rlimit_changes = shared_state
+            .rlimit_change_log
+            .as_ref()
+            .map(|log| log.get(pid))
+            .unwrap_or_default();
 
-        Some(ProcessStatus {
-            vm_peak_bytes: vm_peak,
-            attempted_vm_peak_bytes: if attempted_vm_peak == RLIMIT_AS_NOT_HIT {
-                None
-            } else {
-                Some(attempted_vm_peak.try_into().unwrap())
-            },
-        })
+        Some(process_status(&record, enforced_limit, rlimit_changes))
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+/// Same as calling `get_process_status` for each of the PIDs, but reads all the monitored
+/// processes with a few `bpf()` syscalls on Linux 5.6 or above. The statuses are returned
+/// in the order of `pids`.
+pub fn get_process_statuses(pids: &[u32]) -> Vec<Option<ProcessStatus>> {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        let records: StdHashMap<u32, ProcessRecord> = shared_state
+            .processes
+            .get_all()
+            .expect("lookup in processes failed")
+            .into_iter()
+            .collect();
+        let enforced_limits: StdHashMap<u32, u64> = shared_state
+            .enforced_limits
+            .as_ref()
+            .map(|enforced_limits| {
+                enforced_limits
+                    .get_all()
+                    .expect("lookup in enforced_limits failed")
+                    .into_iter()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let rlimit_change_log = shared_state.rlimit_change_log.as_ref();
+
+        pids.iter()
+            .map(|pid| {
+                let record = records.get(pid)?;
+                let rlimit_changes = rlimit_change_log
+                    .map(|log| log.get(*pid))
+                    .unwrap_or_default();
+
+                Some(process_status(
+                    record,
+                    enforced_limits.get(pid).copied(),
+                    rlimit_changes,
+                ))
+            })
+            .collect()
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+/// Stops monitoring the process and returns its final status, like `get_process_status`
+/// followed by `stop_monitoring_process`, but without a window in which the eBPF programs
+/// could update the status between the two calls.
+///
+/// The record is removed with a single `BPF_MAP_LOOKUP_AND_DELETE_ELEM` syscall on
+/// Linux 5.14 or above. Returns `None` if the process is not monitored.
+pub fn take_process_status(pid: u32) -> Option<ProcessStatus> {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        let record = shared_state
+            .processes
+            .non_mut_take(&pid)
+            .expect("remove from processes failed");
+        let enforced_limit = shared_state
+            .enforced_limits
+            .as_ref()
+            .and_then(|enforced_limits| {
+                enforced_limits
+                    .non_mut_take(&pid)
+                    .expect("remove from enforced_limits failed")
+            });
+        let rlimit_changes = shared_state
+            .rlimit_change_log
+            .as_ref()
+            .map(|log| {
+                let rlimit_changes = log.get(pid);
+                log.remove(pid);
+                rlimit_changes
+            })
+            .unwrap_or_default();
+
+        Some(process_status(&record?, enforced_limit, rlimit_changes))
     } else {
         panic!("ebpf-memory-monitor was not initialized");
     }
 }
 
+/// Stops monitoring the process, discarding its status.
 pub fn stop_monitoring_process(pid: u32) {
-    if let Some(shared_state) = SHARED_STATE.get() {
-        shared_state.attempted_vm_peak
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        shared_state.processes
             .non_mut_remove(&pid)
-            .expect("remove from attempted_vm_peak failed");
+            .expect("remove from processes failed");
 
-        shared_state.vm_peak
-            .non_mut_remove(&pid)
-            .expect("remove from vm_peak failed");
+        if let Some(enforced_limits) = &shared_state.enforced_limits {
+            // The entry only exists if set_enforced_limit was called for this process.
+            let _ = enforced_limits.non_mut_remove(&pid);
+        }
+
+        if let Some(rlimit_change_log) = &shared_state.rlimit_change_log {
+            rlimit_change_log.remove(pid);
+        }
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+/// Same as calling `stop_monitoring_process` for each of the PIDs, but removes all of them
+/// with a single `bpf()` syscall per map on Linux 5.6 or above. PIDs which are not
+/// monitored are ignored.
+pub fn stop_monitoring_processes(pids: &[u32]) {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        shared_state
+            .remove_processes(pids)
+            .expect("remove from processes failed");
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+/// Returns the PIDs of all monitored processes, including the ones which already exited
+/// and were not stopped yet.
+pub fn monitored_processes() -> Vec<u32> {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        shared_state
+            .processes
+            .get_all()
+            .expect("lookup in processes failed")
+            .into_iter()
+            .map(|(pid, _)| pid)
+            .collect()
     } else {
         panic!("ebpf-memory-monitor was not initialized");
     }
 }
+
+/// Formats the raw entries of the maps, one per line, for debugging.
+/// The format is not stable.
+pub fn dump_maps() -> String {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        let mut dump = String::new();
+        for (pid, record) in shared_state.processes.get_all().expect("lookup in processes failed") {
+            dump.push_str(&format!("PROCESSES[{pid}] = {record:?}\n"));
+        }
+        if let Some(enforced_limits) = &shared_state.enforced_limits {
+            for (pid, limit) in enforced_limits.get_all().expect("lookup in enforced_limits failed") {
+                dump.push_str(&format!("ENFORCED_LIMITS[{pid}] = {limit}\n"));
+            }
+        }
+        dump
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+/// Detaches the eBPF programs, frees the maps, and returns the statuses of the processes
+/// which were still monitored. Afterwards, `initialize` can be called again, for example
+/// with a different capacity or different options.
+///
+/// The pinned objects created with `InitOptions::pin_path` are removed as well.
+/// Does nothing if the library is not initialized.
+pub fn shutdown() -> anyhow::Result<StdHashMap<u32, ProcessStatus>> {
+    let Some(shared_state) = SHARED_STATE.write().unwrap().take() else {
+        return Ok(StdHashMap::new());
+    };
+    stop_reaper();
+    // The maps stay readable after removing the pins, as the shared state holds them.
+    remove_pins(&shared_state.options)?;
+
+    let records = shared_state.processes.get_all()?;
+    let enforced_limits: StdHashMap<u32, u64> = match &shared_state.enforced_limits {
+        Some(enforced_limits) => enforced_limits.get_all()?.into_iter().collect(),
+        None => StdHashMap::new(),
+    };
+    let rlimit_change_log = shared_state.rlimit_change_log.as_ref();
+
+    let statuses = records
+        .into_iter()
+        .map(|(pid, record)| {
+            let rlimit_changes = rlimit_change_log
+                .map(|log| log.get(pid))
+                .unwrap_or_default();
+            let status = process_status(
+                &record,
+                enforced_limits.get(&pid).copied(),
+                rlimit_changes,
+            );
+            (pid, status)
+        })
+        .collect();
+
+    // Dropping the shared state detaches the programs and frees the maps.
+    drop(shared_state);
+
+    Ok(statuses)
+}
@@ -1,13 +1,14 @@
-/// The code in this module is based on the source code of `aya`, version 0.13.1,
-/// available at https://github.com/aya-rs/aya under the terms of the MIT license.
+//! The code in this module is based on the source code of `aya`, version 0.13.1,
+//! available at https://github.com/aya-rs/aya under the terms of the MIT license.
 
 use aya::maps::{HashMap, IterableMap, MapData, MapError};
 use aya::sys::SyscallError;
 use aya::Pod;
 use aya_obj::generated::{bpf_attr, bpf_cmd};
-use libc::SYS_bpf;
+use libc::{EINVAL, ENOENT, ENOSPC, EOPNOTSUPP, SYS_bpf};
 use std::borrow::{Borrow, BorrowMut};
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{io, mem};
 
 pub(crate) trait NonMutModify<T: BorrowMut<MapData>, K: Pod, V: Pod> {
@@ -19,6 +20,14 @@ pub(crate) trait NonMutModify<T: BorrowMut<MapData>, K: Pod, V: Pod> {
     ) -> Result<(), MapError>;
 
     fn non_mut_remove(&self, key: &K) -> Result<(), MapError>;
+
+    fn non_mut_take(&self, key: &K) -> Result<Option<V>, MapError>;
+
+    fn non_mut_insert_batch(&self, keys: &[K], values: &[V], flags: u64) -> Result<(), MapError>;
+
+    fn non_mut_remove_batch(&self, keys: &[K]) -> Result<(), MapError>;
+
+    fn get_all(&self) -> Result<Vec<(K, V)>, MapError>;
 }
 
 impl<T: BorrowMut<MapData>, K: Pod, V: Pod> NonMutModify<T, K, V> for HashMap<T, K, V> {
@@ -36,6 +45,207 @@ impl<T: BorrowMut<MapData>, K: Pod, V: Pod> NonMutModify<T, K, V> for HashMap<T,
     fn non_mut_remove(&self, key: &K) -> Result<(), MapError> {
         remove(self.map(), key)
     }
+
+    /// Removes a key from the map and returns its value with `BPF_MAP_LOOKUP_AND_DELETE_ELEM`,
+    /// or with a lookup followed by a deletion if the kernel does not support it for hash
+    /// maps (before 5.14). Returns `None` if the key is not in the map.
+    fn non_mut_take(&self, key: &K) -> Result<Option<V>, MapError> {
+        match take(self.map(), key) {
+            Err(OpError::Unsupported) => {
+                let value = match self.get(key, 0) {
+                    Ok(value) => value,
+                    Err(MapError::KeyNotFound) => return Ok(None),
+                    Err(error) => return Err(error),
+                };
+                match remove(self.map(), key) {
+                    // The key was removed concurrently, after the lookup.
+                    Err(MapError::SyscallError(SyscallError { io_error, .. }))
+                        if io_error.raw_os_error() == Some(ENOENT) => Ok(None),
+                    result => result.map(|_| Some(value)),
+                }
+            }
+            Err(OpError::Map(error)) => Err(error),
+            Ok(result) => Ok(result),
+        }
+    }
+
+    /// Inserts the key-value pairs into the map with `BPF_MAP_UPDATE_BATCH`, or one by one
+    /// if the kernel does not support it.
+    fn non_mut_insert_batch(&self, keys: &[K], values: &[V], flags: u64) -> Result<(), MapError> {
+        assert_eq!(keys.len(), values.len());
+
+        match insert_batch(self.map(), keys, values, flags) {
+            Err(OpError::Unsupported) => {
+                for (key, value) in keys.iter().zip(values) {
+                    insert(self.map(), key, value, flags)?;
+                }
+                Ok(())
+            }
+            Err(OpError::Map(error)) => Err(error),
+            Ok(result) => Ok(result),
+        }
+    }
+
+    /// Removes the keys from the map with `BPF_MAP_DELETE_BATCH`, or one by one if the
+    /// kernel does not support it. Keys which are not in the map are ignored.
+    fn non_mut_remove_batch(&self, keys: &[K]) -> Result<(), MapError> {
+        match remove_batch(self.map(), keys) {
+            Err(OpError::Unsupported) => {
+                for key in keys {
+                    match remove(self.map(), key) {
+                        Err(MapError::SyscallError(SyscallError { io_error, .. }))
+                            if io_error.raw_os_error() == Some(ENOENT) => {}
+                        result => result?,
+                    }
+                }
+                Ok(())
+            }
+            Err(OpError::Map(error)) => Err(error),
+            Ok(result) => Ok(result),
+        }
+    }
+
+    /// Returns all the key-value pairs of the map, read with `BPF_MAP_LOOKUP_BATCH`, or
+    /// one by one if the kernel does not support it.
+    fn get_all(&self) -> Result<Vec<(K, V)>, MapError> {
+        match lookup_batch(self.map()) {
+            Err(OpError::Unsupported) => self.iter().collect(),
+            Err(OpError::Map(error)) => Err(error),
+            Ok(result) => Ok(result),
+        }
+    }
+}
+
+// The error returned by the kernel for maps without batch operations.
+const ENOTSUPP: i32 = 524;
+
+// Whether the kernel supports the batch operations (5.6 or above), set by
+// `probe_batch_support`. Until then, the batch operations are done one by one.
+static BATCH_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+// The number of elements read at once by `lookup_batch`, doubled whenever a single
+// bucket of the hash map holds more elements.
+const LOOKUP_BATCH_SIZE: usize = 256;
+
+enum OpError {
+    // The kernel does not support the operation, either at all, or for this map type.
+    Unsupported,
+    Map(MapError),
+}
+
+/// Checks whether the kernel supports the batch operations on the map with a
+/// `BPF_MAP_LOOKUP_BATCH` reading no elements. Kernels older than 5.6 don't know the
+/// command and fail with `EINVAL`, which the batch operations then can't tell apart from
+/// invalid arguments.
+pub(crate) fn probe_batch_support<K: Pod, V: Pod>(map: &HashMap<MapData, K, V>) -> Result<(), MapError> {
+    let fd = map.map().fd().as_fd();
+    let mut out_batch: u32 = 0;
+    let supported = match bpf_map_lookup_batch::<K, V>(fd, None, &mut out_batch, &mut [], &mut []) {
+        Ok(_) => true,
+        Err((_, io_error)) => match io_error.raw_os_error() {
+            Some(EINVAL | EOPNOTSUPP | ENOTSUPP) => false,
+            _ => {
+                return Err(SyscallError {
+                    call: "bpf_map_lookup_batch",
+                    io_error,
+                }.into());
+            }
+        },
+    };
+    BATCH_SUPPORTED.store(supported, Ordering::Relaxed);
+    Ok(())
+}
+
+fn batch_supported() -> Result<(), OpError> {
+    if BATCH_SUPPORTED.load(Ordering::Relaxed) {
+        Ok(())
+    } else {
+        Err(OpError::Unsupported)
+    }
+}
+
+fn op_error(call: &'static str, io_error: io::Error) -> OpError {
+    match io_error.raw_os_error() {
+        Some(EOPNOTSUPP | ENOTSUPP) => OpError::Unsupported,
+        _ => OpError::Map(SyscallError { call, io_error }.into()),
+    }
+}
+
+fn take<K: Pod, V: Pod>(map: &MapData, key: &K) -> Result<Option<V>, OpError> {
+    let fd = map.fd().as_fd();
+    let mut value = unsafe { mem::zeroed::<V>() };
+    match bpf_map_lookup_and_delete_elem(fd, key, &mut value) {
+        Ok(_) => Ok(Some(value)),
+        Err(io_error) if io_error.raw_os_error() == Some(ENOENT) => Ok(None),
+        Err(io_error) => Err(op_error("bpf_map_lookup_and_delete_elem", io_error)),
+    }
+}
+
+fn insert_batch<K: Pod, V: Pod>(
+    map: &MapData,
+    keys: &[K],
+    values: &[V],
+    flags: u64,
+) -> Result<(), OpError> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    batch_supported()?;
+
+    let fd = map.fd().as_fd();
+    bpf_map_update_batch(fd, keys, values, flags)
+        .map(|_| ())
+        .map_err(|io_error| op_error("bpf_map_update_batch", io_error))
+}
+
+fn remove_batch<K: Pod>(map: &MapData, mut keys: &[K]) -> Result<(), OpError> {
+    batch_supported()?;
+    let fd = map.fd().as_fd();
+    while !keys.is_empty() {
+        match bpf_map_delete_batch(fd, keys) {
+            Ok(_) => return Ok(()),
+            // The deletion stops at the first key which is not in the map,
+            // so skip it and delete the rest.
+            Err((deleted, io_error)) if io_error.raw_os_error() == Some(ENOENT) => {
+                keys = &keys[deleted + 1..];
+            }
+            Err((_, io_error)) => return Err(op_error("bpf_map_delete_batch", io_error)),
+        }
+    }
+    Ok(())
+}
+
+fn lookup_batch<K: Pod, V: Pod>(map: &MapData) -> Result<Vec<(K, V)>, OpError> {
+    batch_supported()?;
+    let fd = map.fd().as_fd();
+    let mut elements = Vec::new();
+    let mut batch_size = LOOKUP_BATCH_SIZE;
+    let mut in_batch: Option<u32> = None;
+    let mut out_batch: u32 = 0;
+
+    loop {
+        let mut keys = vec![unsafe { mem::zeroed::<K>() }; batch_size];
+        let mut values = vec![unsafe { mem::zeroed::<V>() }; batch_size];
+
+        let (count, done) =
+            match bpf_map_lookup_batch(fd, in_batch.as_ref(), &mut out_batch, &mut keys, &mut values) {
+                Ok(count) => (count, false),
+                // ENOENT means that the end of the map was reached.
+                Err((count, io_error)) if io_error.raw_os_error() == Some(ENOENT) => (count, true),
+                // ENOSPC means that a single bucket holds more elements than the batch size.
+                Err((0, io_error)) if io_error.raw_os_error() == Some(ENOSPC) => {
+                    batch_size *= 2;
+                    continue;
+                }
+                Err((_, io_error)) => return Err(op_error("bpf_map_lookup_batch", io_error)),
+            };
+
+        elements.extend(keys.into_iter().zip(values).take(count));
+        if done {
+            return Ok(elements);
+        }
+        in_batch = Some(out_batch);
+    }
 }
 
 fn insert<K: Pod, V: Pod>(
@@ -96,6 +306,82 @@ fn bpf_map_delete_elem<K: Pod>(fd: BorrowedFd<'_>, key: &K) -> io::Result<i64> {
     sys_bpf(bpf_cmd::BPF_MAP_DELETE_ELEM, &mut attr)
 }
 
+fn bpf_map_lookup_and_delete_elem<K: Pod, V: Pod>(
+    fd: BorrowedFd<'_>,
+    key: &K,
+    value: &mut V,
+) -> io::Result<i64> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = fd.as_raw_fd() as u32;
+    u.key = key as *const _ as u64;
+    u.__bindgen_anon_1.value = value as *mut _ as u64;
+
+    sys_bpf(bpf_cmd::BPF_MAP_LOOKUP_AND_DELETE_ELEM, &mut attr)
+}
+
+fn bpf_map_update_batch<K: Pod, V: Pod>(
+    fd: BorrowedFd<'_>,
+    keys: &[K],
+    values: &[V],
+    flags: u64,
+) -> io::Result<i64> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.batch };
+    u.map_fd = fd.as_raw_fd() as u32;
+    u.keys = keys.as_ptr() as u64;
+    u.values = values.as_ptr() as u64;
+    u.count = keys.len() as u32;
+    u.elem_flags = flags;
+
+    sys_bpf(bpf_cmd::BPF_MAP_UPDATE_BATCH, &mut attr)
+}
+
+/// On failure, also returns the number of keys deleted before the error.
+fn bpf_map_delete_batch<K: Pod>(
+    fd: BorrowedFd<'_>,
+    keys: &[K],
+) -> Result<i64, (usize, io::Error)> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.batch };
+    u.map_fd = fd.as_raw_fd() as u32;
+    u.keys = keys.as_ptr() as u64;
+    u.count = keys.len() as u32;
+
+    sys_bpf(bpf_cmd::BPF_MAP_DELETE_BATCH, &mut attr)
+        .map_err(|io_error| (unsafe { attr.batch.count } as usize, io_error))
+}
+
+/// Returns the number of elements read into `keys` and `values`, also on failure.
+fn bpf_map_lookup_batch<K: Pod, V: Pod>(
+    fd: BorrowedFd<'_>,
+    in_batch: Option<&u32>,
+    out_batch: &mut u32,
+    keys: &mut [K],
+    values: &mut [V],
+) -> Result<usize, (usize, io::Error)> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    let u = unsafe { &mut attr.batch };
+    u.map_fd = fd.as_raw_fd() as u32;
+    if let Some(in_batch) = in_batch {
+        u.in_batch = in_batch as *const _ as u64;
+    }
+    u.out_batch = out_batch as *mut _ as u64;
+    u.keys = keys.as_mut_ptr() as u64;
+    u.values = values.as_mut_ptr() as u64;
+    u.count = keys.len() as u32;
+
+    let result = sys_bpf(bpf_cmd::BPF_MAP_LOOKUP_BATCH, &mut attr);
+    let count = unsafe { attr.batch.count } as usize;
+    result
+        .map(|_| count)
+        .map_err(|io_error| (count, io_error))
+}
+
 fn sys_bpf(cmd: bpf_cmd, attr: &mut bpf_attr) -> io::Result<i64> {
     let ret = unsafe {
         libc::syscall(SYS_bpf, cmd, attr, size_of::<bpf_attr>())
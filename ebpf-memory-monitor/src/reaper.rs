@@ -0,0 +1,112 @@
+use aya::maps::MapError;
+use aya::sys::SyscallError;
+use log::{info, warn};
+use nix::time::{clock_gettime, ClockId};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+use ebpf_memory_monitor_common::ProcessRecord;
+use crate::exit::has_exited;
+use crate::init::SHARED_STATE;
+use crate::non_mut_modify::NonMutModify;
+use crate::{process_status, ProcessStatus};
+
+/// Stops monitoring the processes which exited more than `ttl` ago, discarding their
+/// statuses, and returns their PIDs. Whether they exited is checked in `/proc` as well
+/// as in their records.
+///
+/// This is meant for reclaiming the entries of processes whose statuses were never
+/// fetched, for example because their supervisor crashed. The reaper thread enabled by
+/// `InitOptions::reaper` calls it periodically.
+///
+/// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
+pub fn gc(ttl: Duration) -> Result<Vec<u32>, MapError> {
+    Ok(take_expired(ttl)?.into_iter().map(|(pid, _)| pid).collect())
+}
+
+/// Same as `gc`, but returns the final statuses of the processes along with their PIDs.
+///
+/// This method should not be called unless `initialize_with_max_listeners` was successfully called before.
+pub fn take_expired(ttl: Duration) -> Result<Vec<(u32, ProcessStatus)>, MapError> {
+    if let Some(shared_state) = SHARED_STATE.read().unwrap().as_ref() {
+        // The exit times are taken from CLOCK_MONOTONIC by the eBPF programs.
+        let now = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .map_err(|errno| SyscallError {
+                call: "clock_gettime",
+                io_error: errno.into(),
+            })?;
+        let now = Duration::from(now);
+
+        let expired: Vec<(u32, ProcessRecord)> = shared_state
+            .processes
+            .get_all()?
+            .into_iter()
+            .filter(|(pid, record)| {
+                let exit_time = record.exit_stats.exit_time;
+                // A process which is still running is never reclaimed, even if its record
+                // looks like it exited, as it would silently stop being monitored.
+                exit_time != 0
+                    && now.saturating_sub(Duration::from_nanos(exit_time)) > ttl
+                    && has_exited(*pid)
+            })
+            .collect();
+
+        // The records don't change anymore once the processes exited, so they can be
+        // read before being removed.
+        let statuses: Vec<(u32, ProcessStatus)> = expired
+            .into_iter()
+            .map(|(pid, record)| {
+                let enforced_limit = shared_state
+                    .enforced_limits
+                    .as_ref()
+                    .and_then(|enforced_limits| enforced_limits.get(&pid, 0).ok());
+                let rlimit_changes = shared_state
+                    .rlimit_change_log
+                    .as_ref()
+                    .map(|log| log.get(pid))
+                    .unwrap_or_default();
+                (pid, process_status(&record, enforced_limit, rlimit_changes))
+            })
+            .collect();
+
+        let pids: Vec<u32> = statuses.iter().map(|(pid, _)| *pid).collect();
+        shared_state.remove_processes(&pids)?;
+        Ok(statuses)
+    } else {
+        panic!("ebpf-memory-monitor was not initialized");
+    }
+}
+
+// Incremented whenever the reaper thread has to stop, so that a thread started before
+// `shutdown` doesn't keep running after a later `initialize`.
+static REAPER_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Starts a thread which calls `gc(ttl)` every `interval`, and logs the reclaimed PIDs.
+/// The thread stops once `stop_reaper` is called or the library is no longer initialized.
+pub(crate) fn spawn_reaper(ttl: Duration, interval: Duration) -> std::io::Result<()> {
+    let generation = REAPER_GENERATION.load(Ordering::SeqCst);
+
+    thread::Builder::new()
+        .name("memory-monitor-reaper".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            if REAPER_GENERATION.load(Ordering::SeqCst) != generation
+                || SHARED_STATE.read().unwrap().is_none()
+            {
+                break;
+            }
+
+            match gc(ttl) {
+                Ok(pids) if !pids.is_empty() => {
+                    info!("reclaimed the entries of {} exited processes: {:?}", pids.len(), pids);
+                }
+                Ok(_) => {}
+                Err(error) => warn!("reclaiming the entries of exited processes failed: {error}"),
+            }
+        })?;
+    Ok(())
+}
+
+pub(crate) fn stop_reaper() {
+    REAPER_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,184 @@
+use aya::maps::perf::PerfEventArrayBuffer;
+use aya::maps::{Map, MapData, PerfEventArray};
+use aya::util::online_cpus;
+use bytes::BytesMut;
+use ebpf_memory_monitor_common::RlimitChangeEvent;
+use ebpf_memory_monitor_protocol::RlimitChange;
+use log::warn;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use std::collections::HashMap;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// How long the reader thread waits for events before checking if it has to stop, in
+// milliseconds.
+const READER_POLL_TIMEOUT_MS: u8 = 100;
+
+/// The changes collected for a monitored process.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ProcessChanges {
+    pub(crate) changes: Vec<RlimitChange>,
+    /// Whether events were lost while the process was monitored, because a perf buffer was
+    /// full. The lost events can't be attributed to a process, so they may belong to it.
+    pub(crate) lost: bool,
+}
+
+struct LogState {
+    buffers: Vec<PerfEventArrayBuffer<MapData>>,
+    changes: HashMap<u32, ProcessChanges>,
+}
+
+/// Collects the `setrlimit` and `prlimit64` calls of the monitored processes.
+///
+/// The perf buffers are drained by a reader thread as soon as events are written to them,
+/// so that they don't fill up while nobody fetches the statuses, and again before every
+/// lookup, so that the changes made right before it are included.
+pub(crate) struct RlimitChangeLog {
+    state: Arc<Mutex<LogState>>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl RlimitChangeLog {
+    pub(crate) fn new(map: Map) -> anyhow::Result<Self> {
+        let mut rlimit_changes: PerfEventArray<MapData> = PerfEventArray::try_from(map)?;
+
+        let buffers: Vec<_> = online_cpus()
+            .map_err(|(_, io_error)| io_error)?
+            .into_iter()
+            .map(|cpu| rlimit_changes.open(cpu, None))
+            .collect::<Result<_, _>>()?;
+        let fds = buffers.iter().map(|buffer| buffer.as_raw_fd()).collect();
+
+        let state = Arc::new(Mutex::new(LogState {
+            buffers,
+            changes: HashMap::new(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = spawn_reader(state.clone(), stop.clone(), fds)?;
+
+        Ok(RlimitChangeLog {
+            state,
+            stop,
+            reader: Some(reader),
+        })
+    }
+
+    /// Starts collecting the changes made by or on the process with the given PID.
+    pub(crate) fn add(&self, pid: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.read_events();
+        state.changes.insert(pid, ProcessChanges::default());
+    }
+
+    /// Returns the changes made by or on the process with the given PID.
+    pub(crate) fn get(&self, pid: u32) -> ProcessChanges {
+        let mut state = self.state.lock().unwrap();
+        state.read_events();
+        state.changes.get(&pid).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn remove(&self, pid: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.read_events();
+        state.changes.remove(&pid);
+    }
+
+    /// Moves the changes collected so far to `other`, which reads the events from
+    /// a different map.
+    pub(crate) fn migrate_to(&mut self, other: &RlimitChangeLog) {
+        self.stop_reader();
+        let mut state = self.state.lock().unwrap();
+        state.read_events();
+        other.state.lock().unwrap().changes.extend(state.changes.drain());
+    }
+
+    fn stop_reader(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Drop for RlimitChangeLog {
+    fn drop(&mut self) {
+        self.stop_reader();
+    }
+}
+
+/// Starts a thread which drains the perf buffers whenever one of them becomes readable,
+/// until `stop` is set.
+fn spawn_reader(
+    state: Arc<Mutex<LogState>>,
+    stop: Arc<AtomicBool>,
+    fds: Vec<RawFd>,
+) -> std::io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("memory-monitor-rlimit-log".to_string())
+        .spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                // SAFETY: the buffers owning the file descriptors are kept open by `state`.
+                let mut poll_fds: Vec<PollFd> = fds
+                    .iter()
+                    .map(|&fd| PollFd::new(unsafe { BorrowedFd::borrow_raw(fd) }, PollFlags::POLLIN))
+                    .collect();
+                match poll(&mut poll_fds, PollTimeout::from(READER_POLL_TIMEOUT_MS)) {
+                    Ok(0) | Err(Errno::EINTR) => continue,
+                    Ok(_) => state.lock().unwrap().read_events(),
+                    Err(errno) => {
+                        warn!("polling the rlimit change buffers failed: {errno}");
+                        break;
+                    }
+                }
+            }
+        })
+}
+
+impl LogState {
+    fn read_events(&mut self) {
+        let mut out_bufs = vec![BytesMut::with_capacity(size_of::<RlimitChangeEvent>()); 16];
+
+        for buffer in &mut self.buffers {
+            while buffer.readable() {
+                let Ok(events) = buffer.read_events(&mut out_bufs) else {
+                    break;
+                };
+
+                if events.lost != 0 {
+                    warn!("lost {} rlimit change events, as a perf buffer was full", events.lost);
+                    for changes in self.changes.values_mut() {
+                        changes.lost = true;
+                    }
+                }
+
+                for out_buf in &out_bufs[..events.read] {
+                    let event = unsafe {
+                        (out_buf.as_ptr() as *const RlimitChangeEvent).read_unaligned()
+                    };
+                    let change = RlimitChange {
+                        time: Duration::from_nanos(event.time),
+                        caller_pid: event.caller_tgid,
+                        target_pid: event.target_tgid,
+                        resource: event.resource,
+                        soft_limit: event.soft,
+                        hard_limit: event.hard,
+                    };
+
+                    if event.caller_tgid != event.target_tgid
+                        && let Some(changes) = self.changes.get_mut(&event.caller_tgid)
+                    {
+                        changes.changes.push(change.clone());
+                    }
+                    if let Some(changes) = self.changes.get_mut(&event.target_tgid) {
+                        changes.changes.push(change);
+                    }
+                }
+            }
+        }
+    }
+}
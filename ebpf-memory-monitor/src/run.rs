@@ -0,0 +1,218 @@
+//! Spawning a command under the monitor, and waiting for it.
+
+use anyhow::{bail, Context as _};
+use crate::{start_monitoring_process, take_process_status, ProcessStatus};
+use libc::c_char;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::resource::{setrlimit, Resource};
+use nix::sys::wait::{waitid, waitpid, Id, WaitPidFlag, WaitStatus};
+use nix::unistd::{fork, pipe2, read, write, ForkResult, Pid};
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use std::{iter, mem, ptr};
+
+/// How a command ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// The command exited with this code.
+    Code(i32),
+    /// The command was killed by this signal.
+    Signal(i32),
+}
+
+impl Exit {
+    /// The exit code to propagate, following the convention of the shells.
+    pub fn code(self) -> i32 {
+        match self {
+            Exit::Code(code) => code,
+            Exit::Signal(signal) => 128 + signal,
+        }
+    }
+}
+
+/// What is known about a command after it exited.
+pub struct Run {
+    /// The command and its arguments.
+    pub command: Vec<String>,
+    /// The `RLIMIT_AS` the command was spawned with.
+    pub as_limit: Option<u64>,
+    /// How the command ended.
+    pub exit: Exit,
+    /// `None` if the record of the process was lost, for example because the monitor was
+    /// shut down by another thread.
+    pub status: Option<ProcessStatus>,
+    /// The resource usage of the command, as returned by `wait4`.
+    pub rusage: libc::rusage,
+    /// The time from the start of the command to its exit.
+    pub wall_time: Duration,
+}
+
+/// The command could not be executed.
+#[derive(Debug)]
+pub struct ExecFailed {
+    /// The program which could not be executed.
+    pub program: String,
+    /// Why `execvp` failed.
+    pub errno: Errno,
+}
+
+impl Display for ExecFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "executing {} failed: {}", self.program, self.errno)
+    }
+}
+
+impl std::error::Error for ExecFailed {}
+
+/// A command spawned by `spawn`, which must be waited for with `wait`.
+pub struct Child {
+    pid: Pid,
+    command: Vec<String>,
+    as_limit: Option<u64>,
+    started: Instant,
+}
+
+/// Spawns the command with `RLIMIT_AS` set to `as_limit`, monitoring it from before it's
+/// executed. Fails with `ExecFailed` if the command could not be executed.
+///
+/// The monitor must be initialized.
+pub fn spawn(command: &[String], as_limit: Option<u64>) -> anyhow::Result<Child> {
+    let args = command
+        .iter()
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .context("the command contains a NUL byte")?;
+    if args.is_empty() {
+        bail!("no command given");
+    }
+    // `nix::unistd::execvp` allocates the array of pointers, which may deadlock in the
+    // child of a multithreaded process, so it's built before forking.
+    let argv: Vec<*const c_char> = args
+        .iter()
+        .map(|arg| arg.as_ptr())
+        .chain(iter::once(ptr::null()))
+        .collect();
+
+    // The child waits on `go` until it's monitored, and reports the errno of a failed
+    // `execvp` on `exec_error`, which is closed by a successful one.
+    let (go_read, go_write) = pipe2(OFlag::O_CLOEXEC)?;
+    let (exec_error_read, exec_error_write) = pipe2(OFlag::O_CLOEXEC)?;
+    let started = Instant::now();
+
+    // Only async-signal-safe functions may be called in the child until `execvp`.
+    let pid = match unsafe { fork() }.context("fork failed")? {
+        ForkResult::Child => {
+            drop(go_write);
+            let errno = match read(&go_read, &mut [0]) {
+                Ok(1) => match as_limit.map(|limit| setrlimit(Resource::RLIMIT_AS, limit, limit)) {
+                    Some(Err(errno)) => errno,
+                    _ => {
+                        unsafe { libc::execvp(argv[0], argv.as_ptr()) };
+                        Errno::last()
+                    }
+                },
+                // The parent failed to start monitoring the child.
+                _ => Errno::ECANCELED,
+            };
+            let _ = write(&exec_error_write, &(errno as i32).to_ne_bytes());
+            unsafe { libc::_exit(if errno == Errno::ENOENT { 127 } else { 126 }) }
+        }
+        ForkResult::Parent { child } => child,
+    };
+    drop(go_read);
+    drop(exec_error_write);
+
+    let monitoring = start_monitoring_process(pid.as_raw() as u32);
+    if monitoring.is_ok() {
+        let _ = write(&go_write, &[0]);
+    }
+    drop(go_write);
+
+    // Blocks until `execvp` closes the pipe, or the child reports why it didn't.
+    let mut errno = [0; 4];
+    let exec_error = match read(&exec_error_read, &mut errno) {
+        Ok(length) if length == errno.len() => Some(Errno::from_raw(i32::from_ne_bytes(errno))),
+        _ => None,
+    };
+    if let Err(error) = monitoring {
+        let _ = waitpid(pid, None);
+        return Err(error).with_context(|| format!("monitoring {} failed", command[0]));
+    }
+    if let Some(errno) = exec_error {
+        let _ = waitpid(pid, None);
+        take_process_status(pid.as_raw() as u32);
+        return Err(ExecFailed { program: command[0].clone(), errno }.into());
+    }
+
+    Ok(Child {
+        pid,
+        command: command.to_vec(),
+        as_limit,
+        started,
+    })
+}
+
+/// Spawns the command like `spawn`, and waits for it to exit.
+pub fn run(command: &[String], as_limit: Option<u64>) -> anyhow::Result<Run> {
+    spawn(command, as_limit)?.wait()
+}
+
+impl Child {
+    /// The PID of the command.
+    pub fn pid(&self) -> u32 {
+        self.pid.as_raw() as u32
+    }
+
+    /// Whether the command exited, without waiting for it or reaping it: `wait` then
+    /// returns immediately.
+    pub fn has_exited(&self) -> anyhow::Result<bool> {
+        let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+        loop {
+            match waitid(Id::Pid(self.pid), flags) {
+                Ok(status) => return Ok(status != WaitStatus::StillAlive),
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(errno).context("waitid failed"),
+            }
+        }
+    }
+
+    /// Waits for the command to exit, and stops monitoring it.
+    pub fn wait(self) -> anyhow::Result<Run> {
+        let (exit, rusage) = wait(self.pid)?;
+        let wall_time = self.started.elapsed();
+        let status = take_process_status(self.pid());
+
+        Ok(Run {
+            command: self.command,
+            as_limit: self.as_limit,
+            exit,
+            wall_time: status.as_ref().and_then(ProcessStatus::wall_time).unwrap_or(wall_time),
+            status,
+            rusage,
+        })
+    }
+}
+
+/// Waits for the child to exit, returning its resource usage like `wait4`.
+fn wait(pid: Pid) -> anyhow::Result<(Exit, libc::rusage)> {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    loop {
+        if unsafe { libc::wait4(pid.as_raw(), &mut status, 0, &mut rusage) } >= 0 {
+            break;
+        }
+        let errno = Errno::last();
+        if errno != Errno::EINTR {
+            return Err(errno).context("wait4 failed");
+        }
+    }
+
+    let exit = if libc::WIFSIGNALED(status) {
+        Exit::Signal(libc::WTERMSIG(status))
+    } else {
+        Exit::Code(libc::WEXITSTATUS(status))
+    };
+    Ok((exit, rusage))
+}
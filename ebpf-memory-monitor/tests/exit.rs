@@ -0,0 +1,187 @@
+//! Checks that `wait_for_exit` returns the final status of a process. Loading the eBPF
+//! programs requires root, so these tests are ignored by default, and run with:
+//!
+//!     sudo -E cargo test -p ebpf-memory-monitor --all-features -- --ignored
+
+use ebpf_memory_monitor::init::initialize_with_max_listeners;
+use ebpf_memory_monitor::{start_monitoring_process, take_process_status, wait_for_exit, ProcessStatus, WaitError};
+use nix::fcntl::OFlag;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, pipe2, read, write, ForkResult, Pid};
+use std::os::fd::OwnedFd;
+use std::ptr;
+use std::time::Duration;
+
+/// The size mapped by the children, well above the rest of their virtual memory.
+const MAPPED_BYTES: usize = 256 << 20;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Initializes the monitor, or returns `false` if the tests are not run as root.
+fn initialize() -> bool {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipped, loading the eBPF programs requires root");
+        return false;
+    }
+    initialize_with_max_listeners(16).unwrap();
+    true
+}
+
+/// A monitored child, which runs its function once released.
+struct Child {
+    pid: Pid,
+    go: OwnedFd,
+}
+
+impl Child {
+    /// Forks a child which is monitored from before `run` is called. Only
+    /// async-signal-safe functions, and the ones glibc makes safe after a fork, may be
+    /// called by `run`, as the tests run in several threads.
+    fn spawn(run: fn() -> !) -> Child {
+        let (go_read, go_write) = pipe2(OFlag::O_CLOEXEC).unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                drop(go_write);
+                if let Ok(1) = read(&go_read, &mut [0]) {
+                    run();
+                }
+                unsafe { libc::_exit(1) }
+            }
+            ForkResult::Parent { child } => {
+                drop(go_read);
+                start_monitoring_process(child.as_raw() as u32).unwrap();
+                Child { pid: child, go: go_write }
+            }
+        }
+    }
+
+    fn pid(&self) -> u32 {
+        self.pid.as_raw() as u32
+    }
+
+    fn release(&self) {
+        write(&self.go, &[0]).unwrap();
+    }
+
+    /// Reaps the child, checking that it ran successfully, and stops monitoring it.
+    fn reap(self) {
+        assert_eq!(waitpid(self.pid, None).unwrap(), WaitStatus::Exited(self.pid, 0));
+        take_process_status(self.pid());
+    }
+}
+
+fn map() {
+    let address = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            MAPPED_BYTES,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+            -1,
+            0,
+        )
+    };
+    if address == libc::MAP_FAILED {
+        unsafe { libc::_exit(2) }
+    }
+}
+
+fn map_and_exit() -> ! {
+    map();
+    unsafe { libc::_exit(0) }
+}
+
+/// Maps the memory from a second thread once the leader exited, so that the peak is
+/// only reached after the exit of the thread whose PID is monitored.
+fn map_after_the_leader_exited() -> ! {
+    extern "C" fn thread(_: *mut libc::c_void) -> *mut libc::c_void {
+        unsafe { libc::usleep(100_000) };
+        map();
+        // The process exits with 0 once its last thread returns.
+        ptr::null_mut()
+    }
+
+    let mut handle = 0;
+    if unsafe { libc::pthread_create(&mut handle, ptr::null(), thread, ptr::null_mut()) } != 0 {
+        unsafe { libc::_exit(3) }
+    }
+    unsafe { libc::pthread_exit(ptr::null_mut()) }
+}
+
+fn assert_final(status: &ProcessStatus) {
+    assert!(status.exit_time.is_some(), "{status:?}");
+    assert!(status.vm_peak_bytes >= MAPPED_BYTES as u64, "{status:?}");
+}
+
+#[test]
+#[ignore = "requires root"]
+fn wait_for_exit_returns_the_final_peak() {
+    if !initialize() {
+        return;
+    }
+    let child = Child::spawn(map_and_exit);
+    child.release();
+
+    assert_final(&wait_for_exit(child.pid(), Some(TIMEOUT)).unwrap());
+    child.reap();
+}
+
+#[test]
+#[ignore = "requires root"]
+fn wait_for_exit_waits_for_the_threads_outliving_the_leader() {
+    if !initialize() {
+        return;
+    }
+    let child = Child::spawn(map_after_the_leader_exited);
+    child.release();
+
+    assert_final(&wait_for_exit(child.pid(), Some(TIMEOUT)).unwrap());
+    child.reap();
+}
+
+#[test]
+#[ignore = "requires root"]
+fn wait_for_exit_times_out() {
+    if !initialize() {
+        return;
+    }
+    let child = Child::spawn(map_and_exit);
+
+    let error = wait_for_exit(child.pid(), Some(Duration::from_millis(100))).unwrap_err();
+    assert!(matches!(error, WaitError::TimedOut), "{error:?}");
+
+    child.release();
+    assert_final(&wait_for_exit(child.pid(), Some(TIMEOUT)).unwrap());
+    child.reap();
+}
+
+#[test]
+#[ignore = "requires root"]
+fn wait_for_exit_of_an_unmonitored_process() {
+    if !initialize() {
+        return;
+    }
+    let error = wait_for_exit(std::process::id(), Some(TIMEOUT)).unwrap_err();
+    assert!(matches!(error, WaitError::NotMonitored), "{error:?}");
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+#[ignore = "requires root"]
+fn async_wait_for_exit_returns_the_final_peak() {
+    use ebpf_memory_monitor::asynchronous;
+
+    if !initialize() {
+        return;
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let runs: [fn() -> !; 2] = [map_and_exit, map_after_the_leader_exited];
+    for run in runs {
+        let child = Child::spawn(run);
+        child.release();
+
+        let status = runtime.block_on(asynchronous::wait_for_exit(child.pid())).unwrap();
+        assert_final(&status);
+        child.reap();
+    }
+}
@@ -0,0 +1,207 @@
+//! Access control of the clients, based on the credentials of their socket.
+//!
+//! A client may only monitor the processes which have the same real UID, or which are
+//! its descendants, unless its UID or one of its groups is trusted. The processes it
+//! registered stay its own until they are stopped, and count towards the quota of its UID.
+
+use ebpf_memory_monitor::get_process_statuses;
+use nix::sys::socket::{getsockopt, sockopt};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+
+/// Who registered each process monitored through the daemon.
+static OWNERS: Mutex<Option<HashMap<u32, Owner>>> = Mutex::new(None);
+
+struct Owner {
+    /// The UID of the client.
+    uid: u32,
+    /// Whether the process is reserved by `check_start` but not monitored yet, so it must
+    /// not be forgotten by `forget_unmonitored`.
+    starting: bool,
+}
+
+/// The clients allowed to monitor any process, and how many processes others may monitor.
+pub(crate) struct AccessPolicy {
+    pub(crate) trusted_uids: HashSet<u32>,
+    pub(crate) trusted_gids: HashSet<u32>,
+    pub(crate) max_pids_per_uid: usize,
+}
+
+/// The credentials of a client, taken when it connected.
+pub(crate) struct Peer {
+    pid: u32,
+    uid: u32,
+    gids: Vec<u32>,
+}
+
+impl Peer {
+    pub(crate) fn of(stream: &UnixStream) -> io::Result<Self> {
+        let credentials = getsockopt(stream, sockopt::PeerCredentials)?;
+        let pid = credentials.pid() as u32;
+
+        let mut gids = vec![credentials.gid()];
+        // SO_PEERCRED only has the primary group.
+        if let Some(groups) = status_field(pid, "Groups") {
+            gids.extend(groups.split_whitespace().filter_map(|gid| gid.parse::<u32>().ok()));
+        }
+
+        Ok(Peer { pid, uid: credentials.uid(), gids })
+    }
+
+    pub(crate) fn is_trusted(&self, policy: &AccessPolicy) -> bool {
+        self.uid == 0
+            || policy.trusted_uids.contains(&self.uid)
+            || self.gids.iter().any(|gid| policy.trusted_gids.contains(gid))
+    }
+
+    /// Returns whether the client may see and change the process at all.
+    fn may_access(&self, pid: u32, owners: &HashMap<u32, Owner>) -> bool {
+        match owners.get(&pid) {
+            Some(owner) => owner.uid == self.uid,
+            None => real_uid(pid) == Some(self.uid) || self.is_ancestor_of(pid),
+        }
+    }
+
+    fn is_ancestor_of(&self, mut pid: u32) -> bool {
+        while pid > 1 {
+            let Some(parent) = status_field(pid, "PPid").and_then(|ppid| ppid.parse().ok()) else {
+                return false;
+            };
+            if parent == self.pid {
+                return true;
+            }
+            pid = parent;
+        }
+        false
+    }
+
+    /// Returns an error message unless the client may access all of the processes.
+    pub(crate) fn check(&self, pids: &[u32], policy: &AccessPolicy) -> Result<(), String> {
+        let mut owners = OWNERS.lock().unwrap();
+        self.check_locked(owners.get_or_insert_default(), pids, policy)
+    }
+
+    fn check_locked(
+        &self,
+        owners: &mut HashMap<u32, Owner>,
+        pids: &[u32],
+        policy: &AccessPolicy,
+    ) -> Result<(), String> {
+        if self.is_trusted(policy) {
+            return Ok(());
+        }
+
+        forget_unmonitored(owners, pids);
+        match pids.iter().find(|&&pid| !self.may_access(pid, owners)) {
+            Some(pid) => Err(format!("uid {} may not monitor process {pid}", self.uid)),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `check`, and also returns an error message if monitoring the processes would
+    /// exceed the quota of the client. Otherwise, the processes are recorded as its own,
+    /// counting towards its quota, until the returned reservation is cancelled.
+    ///
+    /// The check and the reservation are made under the same lock, so that concurrent
+    /// requests of the same UID can't exceed the quota together.
+    pub(crate) fn check_start(&self, pids: &[u32], policy: &AccessPolicy) -> Result<Reservation, String> {
+        let mut owners = OWNERS.lock().unwrap();
+        let owners = owners.get_or_insert_default();
+        self.check_locked(owners, pids, policy)?;
+
+        if !self.is_trusted(policy) {
+            let mut owned: Vec<u32> = owners
+                .iter()
+                .filter(|(_, owner)| owner.uid == self.uid)
+                .map(|(&pid, _)| pid)
+                .collect();
+            forget_unmonitored(owners, &owned);
+            owned.retain(|pid| owners.contains_key(pid));
+
+            let mut monitored: HashSet<u32> = owned.into_iter().collect();
+            monitored.extend(pids);
+            if monitored.len() > policy.max_pids_per_uid {
+                return Err(format!(
+                    "uid {} may not monitor more than {} processes",
+                    self.uid, policy.max_pids_per_uid
+                ));
+            }
+        }
+
+        // The processes the client already owns stay its own if the start fails.
+        let mut reserved = Vec::new();
+        for &pid in pids {
+            if let Entry::Vacant(entry) = owners.entry(pid) {
+                entry.insert(Owner { uid: self.uid, starting: true });
+                reserved.push(pid);
+            }
+        }
+        Ok(Reservation { pids: reserved })
+    }
+}
+
+/// The processes newly recorded as owned by a client by `Peer::check_start`, which must
+/// be either committed once they are monitored or cancelled.
+#[must_use]
+pub(crate) struct Reservation {
+    pids: Vec<u32>,
+}
+
+impl Reservation {
+    /// Records that the client started monitoring the processes.
+    pub(crate) fn commit(self) {
+        if let Some(owners) = OWNERS.lock().unwrap().as_mut() {
+            for pid in &self.pids {
+                if let Some(owner) = owners.get_mut(pid) {
+                    owner.starting = false;
+                }
+            }
+        }
+    }
+
+    /// Releases the processes, as monitoring them failed.
+    pub(crate) fn cancel(self) {
+        stopped(&self.pids);
+    }
+}
+
+/// Records that the processes are no longer monitored.
+pub(crate) fn stopped(pids: &[u32]) {
+    if let Some(owners) = OWNERS.lock().unwrap().as_mut() {
+        for pid in pids {
+            owners.remove(pid);
+        }
+    }
+}
+
+/// Forgets the owners of the processes which are no longer monitored, for example because
+/// they were removed by the reaper. Their PIDs may have been reused since.
+fn forget_unmonitored(owners: &mut HashMap<u32, Owner>, pids: &[u32]) {
+    let owned: Vec<u32> = pids
+        .iter()
+        .copied()
+        .filter(|pid| owners.get(pid).is_some_and(|owner| !owner.starting))
+        .collect();
+    for (pid, status) in owned.iter().zip(get_process_statuses(&owned)) {
+        if status.is_none() {
+            owners.remove(pid);
+        }
+    }
+}
+
+fn real_uid(pid: u32) -> Option<u32> {
+    // The line has the real, effective, saved and filesystem UIDs.
+    status_field(pid, "Uid")?.split_whitespace().next()?.parse().ok()
+}
+
+fn status_field(pid: u32, name: &str) -> Option<String> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    status.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix(':')?;
+        Some(value.trim().to_string())
+    })
+}
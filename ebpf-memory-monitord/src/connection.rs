@@ -0,0 +1,169 @@
+use ebpf_memory_monitor::events::Events;
+use ebpf_memory_monitor::{
+    get_process_statuses, map_usage, set_enforced_limit, start_monitoring_process,
+    start_monitoring_process_with_budget, start_monitoring_processes, stop_monitoring_processes,
+    take_expired, take_process_status, MonitorError,
+};
+use ebpf_memory_monitor_protocol::wire::{ErrorKind, Hello, Request, Response, PROTOCOL_VERSION};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use crate::access::{self, AccessPolicy, Peer};
+use crate::metrics;
+use crate::Config;
+
+/// Serves the requests of a client until it disconnects or subscribes.
+pub(crate) fn serve(stream: UnixStream, config: &Config) -> anyhow::Result<()> {
+    let peer = Peer::of(&stream)?;
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+    let mut writer = stream;
+
+    let hello = lines.next().transpose()?.unwrap_or_default();
+    match hello.parse::<Hello>() {
+        Ok(Hello { version: PROTOCOL_VERSION }) => send(&mut writer, &Response::Ok)?,
+        Ok(Hello { version }) => {
+            let message = format!("unsupported protocol version {version}, expected {PROTOCOL_VERSION}");
+            return send(&mut writer, &Response::Error(ErrorKind::Protocol, message));
+        }
+        Err(error) => {
+            return send(&mut writer, &Response::Error(ErrorKind::Protocol, error.to_string()));
+        }
+    }
+
+    for line in lines {
+        let response = match line?.parse::<Request>() {
+            Ok(Request::Subscribe(pids)) => match peer.check(&pids, &config.access) {
+                Ok(()) => {
+                    send(&mut writer, &Response::Ok)?;
+                    return subscribe(writer, pids, config);
+                }
+                Err(message) => Response::Error(ErrorKind::Denied, message),
+            },
+            Ok(request) => handle(request, &peer, config),
+            Err(error) => Response::Error(ErrorKind::Protocol, error.to_string()),
+        };
+        send(&mut writer, &response)?;
+    }
+
+    Ok(())
+}
+
+fn handle(request: Request, peer: &Peer, config: &Config) -> Response {
+    let policy = &config.access;
+    match request {
+        Request::Start(pid) => start(peer, &[pid], policy, || start_monitoring_process(pid)),
+        Request::StartWithBudget(pid, budget, action) => start(peer, &[pid], policy, || {
+            start_monitoring_process_with_budget(pid, budget, action)
+        }),
+        Request::StartMany(pids) => start(peer, &pids, policy, || start_monitoring_processes(&pids)),
+        Request::SetEnforcedLimit(pid, limit) => {
+            if !config.lsm_enforcement {
+                Response::Error(ErrorKind::Failed, "LSM enforcement is not enabled".to_string())
+            } else if let Err(message) = peer.check(&[pid], policy) {
+                Response::Error(ErrorKind::Denied, message)
+            } else {
+                ok_or_error(set_enforced_limit(pid, limit))
+            }
+        }
+        Request::Status(pids) => match peer.check(&pids, policy) {
+            Ok(()) => Response::Statuses(get_process_statuses(&pids)),
+            Err(message) => Response::Error(ErrorKind::Denied, message),
+        },
+        Request::Take(pid) => match peer.check(&[pid], policy) {
+            Ok(()) => {
+                let status = take_process_status(pid);
+                if let Some(status) = &status {
+                    metrics::observe(pid, status);
+                }
+                access::stopped(&[pid]);
+                Response::Statuses(vec![status])
+            }
+            Err(message) => Response::Error(ErrorKind::Denied, message),
+        },
+        Request::Stop(pids) => match peer.check(&pids, policy) {
+            Ok(()) => {
+                // The hits and the exits since the last poll of the metrics are counted
+                // before the statuses are discarded.
+                for (&pid, status) in pids.iter().zip(get_process_statuses(&pids)) {
+                    if let Some(status) = status {
+                        metrics::observe(pid, &status);
+                    }
+                }
+                stop_monitoring_processes(&pids);
+                access::stopped(&pids);
+                Response::Ok
+            }
+            Err(message) => Response::Error(ErrorKind::Denied, message),
+        },
+        Request::Usage => match map_usage() {
+            Ok(usage) => Response::Usage(usage),
+            Err(error) => Response::Error(ErrorKind::Failed, error.to_string()),
+        },
+        // Stops monitoring the processes of every client.
+        Request::Gc(_) if !peer.is_trusted(policy) => {
+            Response::Error(ErrorKind::Denied, "only trusted clients may run gc".to_string())
+        }
+        Request::Gc(ttl) => match gc(ttl) {
+            Ok(pids) => Response::Pids(pids),
+            Err(error) => Response::Error(ErrorKind::Failed, error.to_string()),
+        },
+        Request::Subscribe(_) => unreachable!("subscriptions are handled by serve"),
+    }
+}
+
+/// Stops monitoring the processes which exited more than `ttl` ago, like
+/// `ebpf_memory_monitor::gc`, counting their hits and exits in the metrics first.
+pub(crate) fn gc(ttl: Duration) -> anyhow::Result<Vec<u32>> {
+    let expired = take_expired(ttl)?;
+    for (pid, status) in &expired {
+        metrics::observe(*pid, status);
+    }
+
+    let pids: Vec<u32> = expired.into_iter().map(|(pid, _)| pid).collect();
+    access::stopped(&pids);
+    Ok(pids)
+}
+
+/// Starts monitoring the processes if the client may, and records them as its own.
+fn start(
+    peer: &Peer,
+    pids: &[u32],
+    policy: &AccessPolicy,
+    start: impl FnOnce() -> Result<(), MonitorError>,
+) -> Response {
+    let reservation = match peer.check_start(pids, policy) {
+        Ok(reservation) => reservation,
+        Err(message) => return Response::Error(ErrorKind::Denied, message),
+    };
+    let response = ok_or_error(start());
+    if response == Response::Ok {
+        reservation.commit();
+    } else {
+        reservation.cancel();
+    }
+    response
+}
+
+fn ok_or_error(result: Result<(), MonitorError>) -> Response {
+    match result {
+        Ok(()) => Response::Ok,
+        Err(error @ MonitorError::MapFull { .. }) => Response::Error(ErrorKind::MapFull, error.to_string()),
+        Err(error) => Response::Error(ErrorKind::Failed, error.to_string()),
+    }
+}
+
+/// Sends the events of the processes until all of them exited or are no longer
+/// monitored, or the client disconnects.
+fn subscribe(mut writer: UnixStream, pids: Vec<u32>, config: &Config) -> anyhow::Result<()> {
+    for event in Events::new(&pids, config.poll_interval) {
+        send(&mut writer, &event)?;
+    }
+    Ok(())
+}
+
+fn send(writer: &mut UnixStream, message: &impl ToString) -> anyhow::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! A daemon which owns the eBPF programs of `ebpf-memory-monitor`, and serves its API
+//! over a Unix socket to clients which don't have the capabilities needed to load them.
+//! See `ebpf_memory_monitor_protocol::wire` for the protocol.
+
+mod access;
+mod connection;
+mod metrics;
+
+use access::AccessPolicy;
+use anyhow::{anyhow, bail, Context as _};
+use ebpf_memory_monitor::init::{initialize, FullMapPolicy, InitOptions};
+use ebpf_memory_monitor_protocol::wire::DEFAULT_SOCKET_PATH;
+use log::{error, info, warn, LevelFilter, Log, Metadata, Record};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: ebpf-memory-monitord [OPTIONS]
+
+Options:
+  --socket <PATH>               The socket to listen on [default: /run/ebpf-memory-monitord.sock]
+  --max-listeners <COUNT>       The number of processes which can be monitored at once [default: 1024]
+  --full-map-policy <POLICY>    error, evict-exited or grow [default: error]
+  --lsm-enforcement             Enable the BPF-LSM programs used for enforced limits
+  --log-rlimit-changes          Record the setrlimit and prlimit64 calls of monitored processes
+  --reaper-ttl <SECONDS>        Stop monitoring processes which exited this long ago
+  --pin-path <PATH>             Pin the programs and maps in this directory on bpffs
+  --poll-interval-ms <MS>       How often subscriptions are checked for events [default: 100]
+  --trusted-uid <UID>           Allow clients with this UID to monitor any process (repeatable)
+  --trusted-gid <GID>           Allow clients in this group to monitor any process (repeatable)
+  --max-pids-per-uid <COUNT>    How many processes an untrusted UID can monitor at once [default: 64]
+  --metrics-address <ADDR>      Serve Prometheus metrics on http://ADDR/metrics, like 127.0.0.1:9750
+  -h, --help                    Print this help
+";
+
+/// The configuration of the daemon, parsed from the command line.
+pub(crate) struct Config {
+    socket_path: PathBuf,
+    max_listeners: u32,
+    full_map_policy: FullMapPolicy,
+    pub(crate) lsm_enforcement: bool,
+    log_rlimit_changes: bool,
+    reaper_ttl: Option<Duration>,
+    pin_path: Option<PathBuf>,
+    pub(crate) poll_interval: Duration,
+    pub(crate) access: AccessPolicy,
+    metrics_address: Option<String>,
+}
+
+impl Config {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut config = Config {
+            socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
+            max_listeners: 1024,
+            full_map_policy: FullMapPolicy::Error,
+            lsm_enforcement: false,
+            log_rlimit_changes: false,
+            reaper_ttl: None,
+            pin_path: None,
+            poll_interval: Duration::from_millis(100),
+            access: AccessPolicy {
+                trusted_uids: HashSet::new(),
+                trusted_gids: HashSet::new(),
+                max_pids_per_uid: 64,
+            },
+            metrics_address: None,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("missing value for {arg}"));
+            match arg.as_str() {
+                "--socket" => config.socket_path = PathBuf::from(value()?),
+                "--max-listeners" => config.max_listeners = parse(&value()?)?,
+                "--full-map-policy" => {
+                    config.full_map_policy = match value()?.as_str() {
+                        "error" => FullMapPolicy::Error,
+                        "evict-exited" => FullMapPolicy::EvictExited,
+                        "grow" => FullMapPolicy::Grow,
+                        policy => bail!("unknown full map policy {policy}"),
+                    }
+                }
+                "--lsm-enforcement" => config.lsm_enforcement = true,
+                "--log-rlimit-changes" => config.log_rlimit_changes = true,
+                "--reaper-ttl" => config.reaper_ttl = Some(Duration::from_secs(parse(&value()?)?)),
+                "--pin-path" => config.pin_path = Some(PathBuf::from(value()?)),
+                "--poll-interval-ms" => config.poll_interval = Duration::from_millis(parse(&value()?)?),
+                "--trusted-uid" => {
+                    config.access.trusted_uids.insert(parse(&value()?)?);
+                }
+                "--trusted-gid" => {
+                    config.access.trusted_gids.insert(parse(&value()?)?);
+                }
+                "--max-pids-per-uid" => config.access.max_pids_per_uid = parse(&value()?)?,
+                "--metrics-address" => config.metrics_address = Some(value()?),
+                "-h" | "--help" => return Ok(None),
+                arg => bail!("unknown argument {arg}"),
+            }
+        }
+
+        Ok(Some(config))
+    }
+
+    fn init_options(&self) -> InitOptions {
+        let mut options = InitOptions::new(self.max_listeners)
+            .lsm_enforcement(self.lsm_enforcement)
+            .log_rlimit_changes(self.log_rlimit_changes)
+            .full_map_policy(self.full_map_policy);
+        if let Some(pin_path) = &self.pin_path {
+            options = options.pin_path(pin_path);
+        }
+        options
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> anyhow::Result<T> {
+    value.parse().map_err(|_| anyhow!("invalid number {value}"))
+}
+
+fn main() -> anyhow::Result<()> {
+    log::set_logger(&StderrLogger)
+        .map(|()| log::set_max_level(LevelFilter::Info))
+        .map_err(|error| anyhow!("{error}"))?;
+
+    let Some(config) = Config::parse(std::env::args().skip(1))? else {
+        print!("{USAGE}");
+        return Ok(());
+    };
+
+    initialize(config.init_options()).context("initializing ebpf-memory-monitor failed")?;
+    if let Some(ttl) = config.reaper_ttl {
+        spawn_reaper(ttl)?;
+    }
+    if let Some(address) = &config.metrics_address {
+        metrics::serve(address, config.poll_interval)
+            .with_context(|| format!("serving the metrics on {address} failed"))?;
+    }
+
+    // The socket might be left over from a previous run.
+    let _ = fs::remove_file(&config.socket_path);
+    let listener = UnixListener::bind(&config.socket_path)
+        .with_context(|| format!("binding {} failed", config.socket_path.display()))?;
+    // The clients are not expected to share the user of the daemon, they are checked by
+    // `access` instead.
+    fs::set_permissions(&config.socket_path, fs::Permissions::from_mode(0o666))?;
+    info!("listening on {}", config.socket_path.display());
+
+    let config = Arc::new(config);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                error!("accepting a connection failed: {error}");
+                continue;
+            }
+        };
+
+        let config = config.clone();
+        thread::spawn(move || {
+            if let Err(error) = connection::serve(stream, &config) {
+                info!("connection closed: {error}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Starts a thread which runs `gc(ttl)` periodically, like `InitOptions::reaper`, but
+/// which also counts the hits and exits of the reclaimed processes in the metrics.
+fn spawn_reaper(ttl: Duration) -> io::Result<()> {
+    // Checking a few times per TTL is enough, as the TTL is only a lower bound.
+    let interval = (ttl / 4).max(Duration::from_secs(1));
+    thread::Builder::new()
+        .name("reaper".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            match connection::gc(ttl) {
+                Ok(pids) if !pids.is_empty() => {
+                    info!("reclaimed the entries of {} exited processes: {:?}", pids.len(), pids);
+                }
+                Ok(_) => {}
+                Err(error) => warn!("reclaiming the entries of exited processes failed: {error}"),
+            }
+        })?;
+    Ok(())
+}
+
+// Writes the log records of the daemon and of the library to stderr.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,375 @@
+//! The Prometheus metrics served over HTTP with `--metrics-address`.
+//!
+//! The counters are updated by polling the statuses of all monitored processes, like the
+//! subscriptions do for theirs, and with the statuses of the processes which the daemon is
+//! about to remove from the maps, so that their last hits and their exits are not missed.
+
+use ebpf_memory_monitor::init::{enable_program_stats, program_stats, ProgramStats};
+use ebpf_memory_monitor::{
+    get_process_statuses, has_exited, map_usage, monitored_processes, ProcessStatus,
+};
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// The upper bounds of the buckets of the VM peak histogram are the powers of two from
+/// 16 MiB (2^24) to 64 GiB (2^36).
+const PEAK_BUCKET_SHIFTS: std::ops::RangeInclusive<u32> = 24..=36;
+const PEAK_BUCKETS: usize = 13;
+
+/// How long a scrape may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static COUNTERS: Mutex<Option<Counters>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Counters {
+    /// What was already counted for each monitored process.
+    counted: HashMap<u32, Counted>,
+    rlimit_hits: u64,
+    budgets_exceeded: u64,
+    denials: u64,
+    exits: u64,
+    /// The number of exits with a VM peak below each bucket bound.
+    peak_buckets: [u64; PEAK_BUCKETS],
+    peak_sum: u64,
+}
+
+#[derive(Default)]
+struct Counted {
+    rlimit_hits: u64,
+    budget_exceeded: bool,
+    denied: bool,
+    exited: bool,
+}
+
+impl Counters {
+    /// Counts what happened to the process since it was last observed. `exited` tells
+    /// whether all its threads exited, as checked by `has_exited`.
+    fn observe(&mut self, pid: u32, status: &ProcessStatus, exited: bool) {
+        let counted = self.counted.entry(pid).or_default();
+        // The PID was reused by a new process since the exit was counted.
+        if counted.exited && status.exit_time.is_none() {
+            *counted = Counted::default();
+        }
+
+        let rlimit_hits = status.rlimit_hits.as_ref().map_or(0, |hits| hits.count);
+        if rlimit_hits > counted.rlimit_hits {
+            self.rlimit_hits += rlimit_hits - counted.rlimit_hits;
+            counted.rlimit_hits = rlimit_hits;
+        }
+        if status.budget_exceeded_bytes.is_some() && !counted.budget_exceeded {
+            counted.budget_exceeded = true;
+            self.budgets_exceeded += 1;
+        }
+        if status.denied_vm_peak_bytes.is_some() && !counted.denied {
+            counted.denied = true;
+            self.denials += 1;
+        }
+        // The exit is only counted once all the threads exited, when the peak is final.
+        if status.exit_time.is_some() && !counted.exited && exited {
+            counted.exited = true;
+            self.exits += 1;
+            self.peak_sum += status.vm_peak_bytes;
+            for (count, shift) in self.peak_buckets.iter_mut().zip(PEAK_BUCKET_SHIFTS) {
+                if status.vm_peak_bytes <= 1 << shift {
+                    *count += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Counts the hits and the exit of a process which is about to be removed from the maps.
+pub(crate) fn observe(pid: u32, status: &ProcessStatus) {
+    let exited = has_exited(pid);
+    COUNTERS.lock().unwrap().get_or_insert_default().observe(pid, status, exited);
+}
+
+/// Counts the hits and exits of all monitored processes since the last poll.
+fn poll() {
+    let pids = monitored_processes();
+    let statuses = get_process_statuses(&pids);
+
+    let mut counters = COUNTERS.lock().unwrap();
+    let counters = counters.get_or_insert_default();
+    for (&pid, status) in pids.iter().zip(&statuses) {
+        if let Some(status) = status {
+            counters.observe(pid, status, has_exited(pid));
+        }
+    }
+    let pids: HashSet<u32> = pids.into_iter().collect();
+    counters.counted.retain(|pid, _| pids.contains(pid));
+}
+
+/// Starts polling the statuses every `poll_interval`, and serving the metrics on `address`.
+pub(crate) fn serve(address: &str, poll_interval: Duration) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    // The statistics are collected for as long as the file descriptor is open.
+    let program_stats_fd = match enable_program_stats() {
+        Ok(fd) => Some(fd),
+        Err(error) => {
+            warn!("enabling the eBPF program statistics failed, their metrics will stay at zero: {error}");
+            None
+        }
+    };
+    let program_stats_enabled = program_stats_fd.is_some();
+
+    thread::spawn(move || {
+        loop {
+            poll();
+            thread::sleep(poll_interval);
+        }
+    });
+    thread::spawn(move || {
+        let _program_stats_fd = program_stats_fd;
+        // Scrapes are rare, so they are answered one at a time.
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(error) = respond(stream, program_stats_enabled) {
+                        info!("answering a metrics request failed: {error}");
+                    }
+                }
+                Err(error) => error!("accepting a metrics connection failed: {error}"),
+            }
+        }
+    });
+
+    info!("serving metrics on http://{address}/metrics");
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, program_stats_enabled: bool) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut lines = BufReader::new(&stream).lines();
+    let request = lines.next().transpose()?.unwrap_or_default();
+    // The headers are read so that closing the connection doesn't reset it.
+    for line in lines {
+        if line?.is_empty() {
+            break;
+        }
+    }
+
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next().map(|path| path.split('?').next())) {
+        (Some("GET"), Some(Some("/metrics"))) => ("200 OK", render(program_stats_enabled)),
+        (Some("GET"), _) => ("404 Not Found", "Not found, see /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "Only GET is supported\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    )?;
+    stream.flush()
+}
+
+/// Renders the metrics in the Prometheus text format.
+fn render(program_stats_enabled: bool) -> String {
+    let mut metrics = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(metrics, "# HELP ebpf_memory_monitor_{name} {help}");
+        let _ = writeln!(metrics, "# TYPE ebpf_memory_monitor_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(metrics, "ebpf_memory_monitor_{name}{labels} {value}");
+        }
+    };
+    let sample = |value: &dyn ToString| vec![(String::new(), value.to_string())];
+
+    if let Ok(usage) = map_usage() {
+        metric(
+            "monitored_processes",
+            "gauge",
+            "The number of processes in the maps, including the exited ones.",
+            &sample(&usage.processes),
+        );
+        metric("map_capacity", "gauge", "The number of processes the maps can hold.", &sample(&usage.max_entries));
+        if let Some(enforced_limits) = usage.enforced_limits {
+            metric(
+                "enforced_limits",
+                "gauge",
+                "The number of processes with an enforced limit.",
+                &sample(&enforced_limits),
+            );
+        }
+    }
+
+    {
+        let counters = COUNTERS.lock().unwrap();
+        let none = Counters::default();
+        let counters = counters.as_ref().unwrap_or(&none);
+
+        metric(
+            "limit_hits_total",
+            "counter",
+            "The number of times a monitored process hit a limit. Each expansion rejected by \
+             RLIMIT_AS is counted, but only the first one above a budget or an enforced limit.",
+            &[
+                ("{limit=\"rlimit_as\"}".to_string(), counters.rlimit_hits.to_string()),
+                ("{limit=\"budget\"}".to_string(), counters.budgets_exceeded.to_string()),
+                ("{limit=\"enforced\"}".to_string(), counters.denials.to_string()),
+            ],
+        );
+        metric("exits_total", "counter", "The number of monitored processes which exited.", &sample(&counters.exits));
+
+        let mut buckets: Vec<(String, String)> = PEAK_BUCKET_SHIFTS
+            .zip(counters.peak_buckets)
+            .map(|(shift, count)| (format!("_bucket{{le=\"{}\"}}", 1u64 << shift), count.to_string()))
+            .collect();
+        buckets.push(("_bucket{le=\"+Inf\"}".to_string(), counters.exits.to_string()));
+        buckets.push(("_sum".to_string(), counters.peak_sum.to_string()));
+        buckets.push(("_count".to_string(), counters.exits.to_string()));
+        metric(
+            "exit_vm_peak_bytes",
+            "histogram",
+            "The peak size of the virtual memory of the monitored processes which exited.",
+            &buckets,
+        );
+    }
+
+    let programs = program_stats();
+    let per_program = |value: &dyn Fn(&ProgramStats) -> String| {
+        programs
+            .iter()
+            .map(|program| (format!("{{program=\"{}\"}}", program.name), value(program)))
+            .collect::<Vec<_>>()
+    };
+    metric(
+        "program_stats_enabled",
+        "gauge",
+        "Whether the kernel collects the statistics of the eBPF programs (BPF_ENABLE_STATS).",
+        &sample(&u8::from(program_stats_enabled)),
+    );
+    metric(
+        "program_runs_total",
+        "counter",
+        "The number of times each eBPF program ran.",
+        &per_program(&|program| program.run_count.to_string()),
+    );
+    metric(
+        "program_run_seconds_total",
+        "counter",
+        "The total time each eBPF program ran for.",
+        &per_program(&|program| program.run_time.as_secs_f64().to_string()),
+    );
+
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ebpf_memory_monitor::RlimitHits;
+
+    const MIB: u64 = 1 << 20;
+
+    fn status(vm_peak_bytes: u64, exit_time: Option<u64>) -> ProcessStatus {
+        ProcessStatus {
+            vm_peak_bytes,
+            attempted_vm_peak_bytes: None,
+            rlimit_hits: None,
+            rlimit_changes: Vec::new(),
+            rlimit_changes_lost: false,
+            budget_exceeded_bytes: None,
+            enforced_limit_bytes: None,
+            denied_vm_peak_bytes: None,
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            voluntary_context_switches: 0,
+            involuntary_context_switches: 0,
+            start_time: exit_time.map(|_| Duration::from_secs(1)),
+            exit_time: exit_time.map(Duration::from_secs),
+        }
+    }
+
+    fn with_hits(mut status: ProcessStatus, count: u64) -> ProcessStatus {
+        status.rlimit_hits = Some(RlimitHits {
+            count,
+            first_attempted_bytes: 0,
+            max_attempted_bytes: 0,
+            first_hit_time: Duration::ZERO,
+            last_hit_time: Duration::ZERO,
+            max_attempted_soft_limit: 0,
+            max_attempted_hard_limit: 0,
+        });
+        status
+    }
+
+    #[test]
+    fn rlimit_hits_are_counted_once() {
+        let mut counters = Counters::default();
+        counters.observe(1, &with_hits(status(0, None), 2), false);
+        counters.observe(1, &with_hits(status(0, None), 2), false);
+        assert_eq!(counters.rlimit_hits, 2);
+
+        counters.observe(1, &with_hits(status(0, None), 5), false);
+        assert_eq!(counters.rlimit_hits, 5);
+
+        // The hits of each process are counted separately.
+        counters.observe(2, &with_hits(status(0, None), 1), false);
+        assert_eq!(counters.rlimit_hits, 6);
+    }
+
+    #[test]
+    fn budget_and_denial_are_counted_once() {
+        let mut counters = Counters::default();
+        let mut exceeded = status(0, None);
+        exceeded.budget_exceeded_bytes = Some(10 * MIB);
+        exceeded.denied_vm_peak_bytes = Some(20 * MIB);
+
+        counters.observe(1, &exceeded, false);
+        counters.observe(1, &exceeded, false);
+        assert_eq!(counters.budgets_exceeded, 1);
+        assert_eq!(counters.denials, 1);
+    }
+
+    #[test]
+    fn exit_is_counted_once_all_threads_exited() {
+        let mut counters = Counters::default();
+        // The exit time is set, but a thread is still running.
+        counters.observe(1, &status(10 * MIB, Some(2)), false);
+        assert_eq!(counters.exits, 0);
+
+        counters.observe(1, &status(10 * MIB, Some(2)), true);
+        counters.observe(1, &status(10 * MIB, Some(2)), true);
+        assert_eq!(counters.exits, 1);
+        assert_eq!(counters.peak_sum, 10 * MIB);
+    }
+
+    #[test]
+    fn reused_pid_is_counted_again() {
+        let mut counters = Counters::default();
+        counters.observe(1, &with_hits(status(MIB, Some(2)), 3), true);
+        // A new process got the PID, and is monitored again.
+        counters.observe(1, &with_hits(status(0, None), 1), false);
+        assert_eq!(counters.rlimit_hits, 4);
+
+        counters.observe(1, &with_hits(status(MIB, Some(3)), 1), true);
+        assert_eq!(counters.exits, 2);
+    }
+
+    #[test]
+    fn peak_buckets() {
+        let mut counters = Counters::default();
+        // The bounds are inclusive, like the `le` label says.
+        counters.observe(1, &status(16 * MIB, Some(1)), true);
+        counters.observe(2, &status(16 * MIB + 1, Some(1)), true);
+        counters.observe(3, &status(1 << 40, Some(1)), true);
+
+        // 16 MiB, 32 MiB, and 64 GiB.
+        assert_eq!(counters.peak_buckets[0], 1);
+        assert_eq!(counters.peak_buckets[1], 2);
+        assert_eq!(counters.peak_buckets[PEAK_BUCKETS - 1], 2);
+        // Above all bounds, so only counted in +Inf, which is the number of exits.
+        assert_eq!(counters.exits, 3);
+        assert_eq!(counters.peak_sum, 32 * MIB + 1 + (1 << 40));
+    }
+}
@@ -0,0 +1,225 @@
+//! A minimal readline-style line editor with history, for when stdin is a terminal.
+//!
+//! Supports moving with the arrow keys, Home, End, `Ctrl-A` and `Ctrl-E`, deleting with
+//! Backspace, Delete and `Ctrl-U`, and browsing the history with the up and down arrows.
+
+use memmon::terminal::{read_byte, stdin_terminal, wait_for_input, RawMode};
+use nix::sys::termios::Termios;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The number of lines kept in the history file.
+const MAX_HISTORY: usize = 1000;
+
+pub(crate) struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    // `None` if stdin is not a terminal, in which case lines are read without editing.
+    terminal: Option<Termios>,
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillLine,
+    Interrupt,
+    EndOfFile,
+    Other,
+}
+
+impl LineEditor {
+    /// Creates an editor whose history is loaded from and saved to `history_path`.
+    pub(crate) fn new(history_path: Option<PathBuf>) -> Self {
+        let history = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|history| history.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        LineEditor { history, history_path, terminal: stdin_terminal() }
+    }
+
+    /// Reads a line, or returns `None` at the end of the input.
+    pub(crate) fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        print!("{prompt}");
+        io::stdout().flush()?;
+
+        let line = match &self.terminal {
+            Some(terminal) => {
+                let _raw_mode = RawMode::enter(terminal)?;
+                self.edit_line(prompt)?
+            }
+            None => {
+                let mut line = String::new();
+                match io::stdin().lock().read_line(&mut line)? {
+                    0 => None,
+                    _ => Some(line.trim_end_matches('\n').to_string()),
+                }
+            }
+        };
+
+        if let Some(line) = &line
+            && !line.trim().is_empty()
+            && self.history.last() != Some(line)
+        {
+            self.add_history(line.clone());
+        }
+        Ok(line)
+    }
+
+    /// Waits up to `timeout` for a key, returning whether one was pressed. Always waits
+    /// for the whole timeout if stdin is not a terminal.
+    pub(crate) fn wait_for_key(&self, timeout: Duration) -> io::Result<bool> {
+        let Some(terminal) = &self.terminal else {
+            std::thread::sleep(timeout);
+            return Ok(false);
+        };
+        let _raw_mode = RawMode::enter(terminal)?;
+        if !wait_for_input(timeout)? {
+            return Ok(false);
+        }
+        read_key()?;
+        Ok(true)
+    }
+
+    fn edit_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // The position in the history, which is `history.len()` for the new line.
+        let mut position = self.history.len();
+        let mut new_line = Vec::new();
+
+        loop {
+            match read_key()? {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    println!();
+                    return Ok(Some(line.into_iter().collect()));
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(line.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::KillLine => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Up if position > 0 => {
+                    if position == self.history.len() {
+                        new_line = line;
+                    }
+                    position -= 1;
+                    line = self.history[position].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if position < self.history.len() => {
+                    position += 1;
+                    line = match self.history.get(position) {
+                        Some(entry) => entry.chars().collect(),
+                        None => new_line.clone(),
+                    };
+                    cursor = line.len();
+                }
+                Key::Interrupt => {
+                    // Like the shells, discard the line and start a new one.
+                    println!("^C");
+                    line.clear();
+                    cursor = 0;
+                    position = self.history.len();
+                }
+                Key::EndOfFile if line.is_empty() => {
+                    println!();
+                    return Ok(None);
+                }
+                _ => continue,
+            }
+
+            let line: String = line.iter().collect();
+            let back = line.chars().count() - cursor;
+            print!("\r{prompt}{line}\x1b[K");
+            if back > 0 {
+                print!("\x1b[{back}D");
+            }
+            io::stdout().flush()?;
+        }
+    }
+
+    fn add_history(&mut self, line: String) {
+        self.history.push(line);
+        if self.history.len() > MAX_HISTORY {
+            self.history.drain(..self.history.len() - MAX_HISTORY);
+        }
+        if let Some(path) = &self.history_path {
+            let _ = fs::write(path, self.history.join("\n") + "\n");
+        }
+    }
+}
+
+fn read_key() -> io::Result<Key> {
+    let Some(byte) = read_byte()? else {
+        return Ok(Key::EndOfFile);
+    };
+
+    Ok(match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x15 => Key::KillLine,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfFile,
+        0x1b => match (read_byte()?, read_byte()?) {
+            (Some(b'['), Some(b'A')) => Key::Up,
+            (Some(b'['), Some(b'B')) => Key::Down,
+            (Some(b'['), Some(b'C')) => Key::Right,
+            (Some(b'['), Some(b'D')) => Key::Left,
+            (Some(b'[' | b'O'), Some(b'H')) => Key::Home,
+            (Some(b'[' | b'O'), Some(b'F')) => Key::End,
+            // The sequences ending with `~` have one more byte to consume.
+            (Some(b'['), Some(b'1' | b'7')) => {
+                read_byte()?;
+                Key::Home
+            }
+            (Some(b'['), Some(b'4' | b'8')) => {
+                read_byte()?;
+                Key::End
+            }
+            (Some(b'['), Some(b'3')) => {
+                read_byte()?;
+                Key::Delete
+            }
+            _ => Key::Other,
+        },
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            // The length of the UTF-8 sequence is given by the leading ones of the first byte.
+            let mut bytes = vec![byte];
+            for _ in 1..byte.leading_ones().max(1) {
+                bytes.extend(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|c| c.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Other,
+            }
+        }
+    })
+}
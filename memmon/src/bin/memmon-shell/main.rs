@@ -0,0 +1,293 @@
+//! An interactive shell for monitoring processes with `ebpf-memory-monitor`.
+
+mod line_editor;
+
+use anyhow::{anyhow, bail, Context as _};
+use ebpf_memory_monitor::init::{backend_info, initialize, InitOptions};
+use ebpf_memory_monitor::run::{spawn, Child, Exit};
+use ebpf_memory_monitor::{
+    dump_maps, get_process_status, get_process_statuses, map_usage, monitored_processes,
+    start_monitoring_processes, stop_monitoring_processes, ProcessStatus,
+};
+use line_editor::LineEditor;
+use memmon::units::{format_bytes, parse_bytes};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: memmon-shell [OPTIONS]
+
+Options:
+  --max-listeners <COUNT>    The number of processes which can be monitored at once [default: 1024]
+  --lsm-enforcement          Enable the BPF-LSM programs used for enforced limits
+  --log-rlimit-changes       Record the setrlimit and prlimit64 calls of monitored processes
+  -h, --help                 Print this help
+";
+
+const HELP: &str = "\
+Commands:
+  start <PID>...                       Start monitoring the processes
+  stop <PID>...                        Stop monitoring the processes
+  status <PID>                         Print the status of a process
+  list                                 List the monitored processes
+  watch <PID>                          Wait until the process exits, or a key is pressed
+  spawn [--as-limit <BYTES>] <CMD>...  Spawn a command, monitoring it from the start
+  dump                                 Print the raw entries of the maps
+  backend                              Print how the monitor was initialized
+  help                                 Print this help
+  exit                                 Exit the shell
+";
+
+/// How often `watch` checks whether the process exited.
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Shell {
+    editor: LineEditor,
+    // The commands started with `spawn`, which have to be waited for.
+    children: HashMap<u32, Child>,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<InitOptions>> {
+    let mut max_listeners = 1024;
+    let mut lsm_enforcement = false;
+    let mut log_rlimit_changes = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-listeners" => {
+                let value = args.next().ok_or_else(|| anyhow!("missing value for {arg}"))?;
+                max_listeners = value.parse().map_err(|_| anyhow!("invalid number {value}"))?;
+            }
+            "--lsm-enforcement" => lsm_enforcement = true,
+            "--log-rlimit-changes" => log_rlimit_changes = true,
+            "-h" | "--help" => return Ok(None),
+            arg => bail!("unknown argument {arg}"),
+        }
+    }
+
+    Ok(Some(
+        InitOptions::new(max_listeners)
+            .lsm_enforcement(lsm_enforcement)
+            .log_rlimit_changes(log_rlimit_changes),
+    ))
+}
+
+fn parse_pids<'a>(args: impl Iterator<Item = &'a str>) -> anyhow::Result<Vec<u32>> {
+    let pids = args
+        .map(|pid| pid.parse().map_err(|_| anyhow!("invalid PID {pid}")))
+        .collect::<anyhow::Result<Vec<u32>>>()?;
+    if pids.is_empty() {
+        bail!("no PID given");
+    }
+    Ok(pids)
+}
+
+fn parse_pid<'a>(args: impl Iterator<Item = &'a str>) -> anyhow::Result<u32> {
+    match parse_pids(args)?.as_slice() {
+        &[pid] => Ok(pid),
+        _ => bail!("expected a single PID"),
+    }
+}
+
+impl Shell {
+    /// Runs a command line, returning `false` if the shell should exit.
+    fn execute(&mut self, line: &str) -> anyhow::Result<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+
+        match command {
+            "start" => {
+                let pids = parse_pids(words)?;
+                start_monitoring_processes(&pids)?;
+                println!("Started monitoring {}", join(&pids));
+            }
+            "stop" => {
+                let pids = parse_pids(words)?;
+                stop_monitoring_processes(&pids);
+                println!("Stopped monitoring {}", join(&pids));
+            }
+            "status" => {
+                let pid = parse_pid(words)?;
+                match get_process_status(pid) {
+                    Some(status) => println!("{status:#?}"),
+                    None => println!("PID {pid} is not monitored"),
+                }
+            }
+            "list" => self.list(),
+            "watch" => self.watch(parse_pid(words)?)?,
+            "spawn" => self.spawn(words)?,
+            "dump" | "debug" => print!("{}", dump_maps()),
+            "backend" => backend(),
+            "help" => print!("{HELP}"),
+            "exit" | "quit" => return Ok(false),
+            command => bail!("unknown command {command}, see help"),
+        }
+        Ok(true)
+    }
+
+    fn list(&self) {
+        let mut pids = monitored_processes();
+        pids.sort_unstable();
+        let statuses = get_process_statuses(&pids);
+
+        println!("{:>8}  {:<8}  VM PEAK", "PID", "STATE");
+        for (pid, status) in pids.iter().zip(statuses) {
+            let Some(status) = status else { continue };
+            let state = match (status.exit_time, self.children.contains_key(pid)) {
+                (Some(_), _) => "exited",
+                (None, true) => "spawned",
+                (None, false) => "running",
+            };
+            // The peak is only recorded when the process exits.
+            let peak = match status.exit_time {
+                Some(_) => format_bytes(status.vm_peak_bytes),
+                None => "-".to_string(),
+            };
+            println!("{pid:>8}  {state:<8}  {peak}");
+        }
+        if let Ok(usage) = map_usage() {
+            println!("{} of {} entries used", usage.processes, usage.max_entries);
+        }
+    }
+
+    fn watch(&mut self, pid: u32) -> anyhow::Result<()> {
+        println!("Watching PID {pid}, press any key to stop");
+        let status = loop {
+            match get_process_status(pid) {
+                None => {
+                    println!("PID {pid} is not monitored");
+                    return Ok(());
+                }
+                Some(status) if status.exit_time.is_some() => break status,
+                Some(_) => {}
+            }
+            if self.editor.wait_for_key(WATCH_INTERVAL)? {
+                println!("Stopped watching PID {pid}");
+                return Ok(());
+            }
+        };
+
+        match self.children.remove(&pid) {
+            Some(child) => print_exit(child, Some(&status))?,
+            None => {
+                println!("PID {pid} exited");
+                print_summary(&status);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reaps the spawned commands which exited, so that they don't stay zombies until
+    /// they are watched.
+    fn reap(&mut self) -> anyhow::Result<()> {
+        let mut exited = Vec::new();
+        for (&pid, child) in &self.children {
+            if child.has_exited()? {
+                exited.push(pid);
+            }
+        }
+        for pid in exited {
+            print_exit(self.children.remove(&pid).unwrap(), None)?;
+        }
+        Ok(())
+    }
+
+    fn spawn<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> anyhow::Result<()> {
+        let mut as_limit = None;
+        let mut command: Vec<String> = Vec::new();
+        while let Some(word) = words.next() {
+            if command.is_empty() && word == "--as-limit" {
+                let limit = words.next().ok_or_else(|| anyhow!("missing value for --as-limit"))?;
+                as_limit = Some(parse_bytes(limit)?);
+            } else {
+                command.push(word.to_string());
+            }
+        }
+
+        let child = spawn(&command, as_limit)?;
+        println!("Spawned PID {}", child.pid());
+        self.children.insert(child.pid(), child);
+        Ok(())
+    }
+}
+
+/// Waits for a spawned command which exited, which reaps it and stops monitoring it, and
+/// prints how it exited. `status` is printed if the final one was lost.
+fn print_exit(child: Child, status: Option<&ProcessStatus>) -> anyhow::Result<()> {
+    let pid = child.pid();
+    let run = child.wait()?;
+    match run.exit {
+        Exit::Code(code) => println!("PID {pid} exited with code {code}"),
+        Exit::Signal(signal) => println!("PID {pid} was killed by signal {signal}"),
+    }
+    if let Some(status) = run.status.as_ref().or(status) {
+        print_summary(status);
+    }
+    Ok(())
+}
+
+fn print_summary(status: &ProcessStatus) {
+    println!("  VM peak:            {}", format_bytes(status.vm_peak_bytes));
+    if let Some(attempted) = status.attempted_vm_peak_bytes {
+        println!("  attempted VM peak:  {}", format_bytes(attempted));
+    }
+    if let Some(hits) = &status.rlimit_hits {
+        println!("  RLIMIT_AS hits:     {}", hits.count);
+    }
+    if let Some(wall_time) = status.wall_time() {
+        println!("  wall time:          {:.3} s", wall_time.as_secs_f64());
+    }
+}
+
+fn backend() {
+    let Some(info) = backend_info() else {
+        println!("ebpf-memory-monitor is not initialized");
+        return;
+    };
+    match info.backend {
+        Some(backend) => println!("backend:        {backend:?}"),
+        None => println!("backend:        reattached to pinned programs"),
+    }
+    println!("programs:       {}", info.programs.join(", "));
+    println!("max listeners:  {}", info.max_listeners);
+    println!("options:        {:?}", info.options);
+}
+
+fn join(pids: &[u32]) -> String {
+    pids.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")
+}
+
+fn main() -> anyhow::Result<()> {
+    let Some(options) = parse_options(env::args().skip(1))? else {
+        print!("{USAGE}");
+        return Ok(());
+    };
+    initialize(options).context("initializing ebpf-memory-monitor failed")?;
+
+    let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(".memmon_history"));
+    let mut shell = Shell {
+        editor: LineEditor::new(history_path),
+        children: HashMap::new(),
+    };
+
+    println!("Type help for the list of commands");
+    loop {
+        if let Err(error) = shell.reap() {
+            println!("error: {error:#}");
+        }
+        let Some(line) = shell.editor.read_line("memmon> ")? else {
+            break;
+        };
+        match shell.execute(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(error) => println!("error: {error:#}"),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,367 @@
+//! The state of the dashboard: the rows of the monitored processes, and their events.
+
+use ebpf_memory_monitor::events::{Event, Events};
+use ebpf_memory_monitor::{get_process_statuses, monitored_processes, ProcessStatus};
+use memmon::units::format_bytes_short;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::time::Duration;
+
+/// The number of events kept for the events pane.
+const MAX_EVENTS: usize = 100;
+
+/// A monitored process, as shown in the table.
+pub(crate) struct Row {
+    pub(crate) pid: u32,
+    /// The name of the command, or `?` if the process exited before it was seen.
+    pub(crate) command: String,
+    /// The current size of the virtual memory, or `None` if the process exited.
+    pub(crate) vm_bytes: Option<u64>,
+    /// The peak size of the virtual memory so far.
+    pub(crate) peak_bytes: u64,
+    /// The soft `RLIMIT_AS`, or `None` if there is none.
+    pub(crate) as_limit: Option<u64>,
+    pub(crate) status: ProcessStatus,
+}
+
+impl Row {
+    pub(crate) fn hits(&self) -> u64 {
+        self.status.rlimit_hits.as_ref().map_or(0, |hits| hits.count)
+    }
+
+    pub(crate) fn exited(&self) -> bool {
+        self.status.exit_time.is_some()
+    }
+}
+
+/// The column the table is sorted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SortKey {
+    Pid,
+    Command,
+    Vm,
+    Peak,
+    Limit,
+    Hits,
+}
+
+impl SortKey {
+    /// The key after this one, in the order of the columns.
+    pub(crate) fn next(self) -> SortKey {
+        match self {
+            SortKey::Pid => SortKey::Command,
+            SortKey::Command => SortKey::Vm,
+            SortKey::Vm => SortKey::Peak,
+            SortKey::Peak => SortKey::Limit,
+            SortKey::Limit => SortKey::Hits,
+            SortKey::Hits => SortKey::Pid,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            SortKey::Pid => "pid",
+            SortKey::Command => "command",
+            SortKey::Vm => "vm",
+            SortKey::Peak => "peak",
+            SortKey::Limit => "limit",
+            SortKey::Hits => "hits",
+        }
+    }
+}
+
+pub(crate) struct Dashboard {
+    rows: Vec<Row>,
+    /// The events of the processes which have a row, only polled by `refresh`.
+    watched: Events,
+    /// The latest events, oldest first.
+    pub(crate) events: VecDeque<String>,
+    pub(crate) sort: SortKey,
+    /// Whether the order of `sort` is reversed. The sizes are sorted largest first, and
+    /// the PIDs and commands in ascending order.
+    pub(crate) reverse: bool,
+    /// Only the processes whose PID or command contains this are shown.
+    pub(crate) filter: String,
+    pub(crate) show_exited: bool,
+    refreshed: bool,
+}
+
+impl Dashboard {
+    pub(crate) fn new() -> Self {
+        Dashboard {
+            rows: Vec::new(),
+            watched: Events::new(&[], Duration::ZERO),
+            events: VecDeque::new(),
+            sort: SortKey::Vm,
+            reverse: false,
+            filter: String::new(),
+            show_exited: true,
+            refreshed: false,
+        }
+    }
+
+    /// Reads the monitoring maps and `/proc` again for the columns of the table, and
+    /// records the processes which hit their `RLIMIT_AS` or exited since the last refresh.
+    pub(crate) fn refresh(&mut self) {
+        let pids = monitored_processes();
+        let statuses = get_process_statuses(&pids);
+        let previous: HashMap<u32, Row> = self.rows.drain(..).map(|row| (row.pid, row)).collect();
+
+        for (pid, status) in pids.into_iter().zip(statuses) {
+            let Some(status) = status else { continue };
+            let before = previous.get(&pid);
+            if before.is_none() {
+                self.watched.watch(pid);
+            }
+            self.rows.push(new_row(pid, status, before));
+        }
+
+        // The events of the processes monitored before the first refresh already happened.
+        let events = self.watched.poll();
+        if !self.refreshed {
+            self.refreshed = true;
+            return;
+        }
+        for event in events {
+            match event {
+                Event::RlimitHit(pid, count) => {
+                    let Some(row) = self.rows.iter().find(|row| row.pid == pid) else { continue };
+                    let Some(hits) = &row.status.rlimit_hits else { continue };
+                    let event = format!(
+                        "PID {} ({}) hit RLIMIT_AS {} times, tried to grow to {} (limit {})",
+                        pid,
+                        row.command,
+                        count,
+                        format_bytes_short(hits.max_attempted_bytes),
+                        format_bytes_short(hits.max_attempted_soft_limit),
+                    );
+                    self.push_event(event);
+                }
+                Event::Exited(pid, status) => {
+                    let command = self
+                        .rows
+                        .iter()
+                        .find(|row| row.pid == pid)
+                        .map_or("?", |row| row.command.as_str());
+                    let event = format!(
+                        "PID {} ({}) exited, VM peak {}",
+                        pid,
+                        command,
+                        format_bytes_short(status.vm_peak_bytes),
+                    );
+                    self.push_event(event);
+                }
+                Event::BudgetExceeded(..) | Event::Denied(..) | Event::Stopped(_) => {}
+            }
+        }
+    }
+
+    fn push_event(&mut self, event: String) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// The number of monitored processes, the number of those which exited, and the
+    /// number of those which hit their `RLIMIT_AS`.
+    pub(crate) fn counts(&self) -> (usize, usize, usize) {
+        let exited = self.rows.iter().filter(|row| row.exited()).count();
+        let hit = self.rows.iter().filter(|row| row.hits() > 0).count();
+        (self.rows.len(), exited, hit)
+    }
+
+    /// The rows to show, filtered and sorted.
+    pub(crate) fn visible_rows(&self) -> Vec<&Row> {
+        let mut rows: Vec<&Row> = self
+            .rows
+            .iter()
+            .filter(|row| self.show_exited || !row.exited())
+            .filter(|row| row.command.contains(&self.filter) || row.pid.to_string().contains(&self.filter))
+            .collect();
+
+        rows.sort_by(|a, b| {
+            let order = match self.sort {
+                SortKey::Pid => a.pid.cmp(&b.pid),
+                SortKey::Command => a.command.cmp(&b.command),
+                SortKey::Vm => b.vm_bytes.cmp(&a.vm_bytes),
+                SortKey::Peak => b.peak_bytes.cmp(&a.peak_bytes),
+                SortKey::Limit => b.as_limit.cmp(&a.as_limit),
+                SortKey::Hits => b.hits().cmp(&a.hits()),
+            };
+            order.then(a.pid.cmp(&b.pid))
+        });
+        if self.reverse {
+            rows.reverse();
+        }
+        rows
+    }
+}
+
+fn new_row(pid: u32, status: ProcessStatus, before: Option<&Row>) -> Row {
+    // Once the process exited, `/proc` is gone or describes a zombie, so what was read
+    // before is kept.
+    let proc = if status.exit_time.is_none() { read_proc(pid) } else { None };
+
+    let command = proc
+        .as_ref()
+        .map(|proc| proc.command.clone())
+        .or_else(|| before.map(|row| row.command.clone()))
+        .unwrap_or_else(|| "?".to_string());
+    // The monitor only records the peak when the process exits.
+    let peak_bytes = match (&proc, status.exit_time) {
+        (Some(proc), None) => proc.vm_peak.unwrap_or(0),
+        _ => status.vm_peak_bytes,
+    };
+    let as_limit = match &proc {
+        Some(proc) => proc.as_limit,
+        None => before
+            .and_then(|row| row.as_limit)
+            .or_else(|| status.rlimit_hits.as_ref().map(|hits| hits.max_attempted_soft_limit)),
+    };
+
+    Row {
+        pid,
+        command,
+        vm_bytes: proc.and_then(|proc| proc.vm_size),
+        peak_bytes,
+        as_limit,
+        status,
+    }
+}
+
+/// What `/proc` says about a running process.
+struct Proc {
+    command: String,
+    vm_size: Option<u64>,
+    vm_peak: Option<u64>,
+    as_limit: Option<u64>,
+}
+
+fn read_proc(pid: u32) -> Option<Proc> {
+    let command = fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let limits = fs::read_to_string(format!("/proc/{pid}/limits")).ok()?;
+
+    let status_kib = |field: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+            .and_then(|value| value.trim().strip_suffix(" kB")?.parse::<u64>().ok())
+            .map(|kib| kib * 1024)
+    };
+    // The soft limit is the first column, or `unlimited`.
+    let as_limit = limits
+        .lines()
+        .find_map(|line| line.strip_prefix("Max address space"))
+        .and_then(|values| values.split_whitespace().next()?.parse().ok());
+
+    Some(Proc {
+        command: command.trim_end().to_string(),
+        vm_size: status_kib("VmSize"),
+        vm_peak: status_kib("VmPeak"),
+        as_limit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ebpf_memory_monitor::RlimitHits;
+
+    const MIB: u64 = 1 << 20;
+
+    fn row(pid: u32, command: &str, vm_bytes: Option<u64>, as_limit: Option<u64>, hits: u64) -> Row {
+        let status = ProcessStatus {
+            vm_peak_bytes: 0,
+            attempted_vm_peak_bytes: None,
+            rlimit_hits: (hits > 0).then_some(RlimitHits {
+                count: hits,
+                first_attempted_bytes: 0,
+                max_attempted_bytes: 0,
+                first_hit_time: Duration::ZERO,
+                last_hit_time: Duration::ZERO,
+                max_attempted_soft_limit: 0,
+                max_attempted_hard_limit: 0,
+            }),
+            rlimit_changes: Vec::new(),
+            rlimit_changes_lost: false,
+            budget_exceeded_bytes: None,
+            enforced_limit_bytes: None,
+            denied_vm_peak_bytes: None,
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            voluntary_context_switches: 0,
+            involuntary_context_switches: 0,
+            start_time: None,
+            // The exited processes have no current size.
+            exit_time: vm_bytes.is_none().then_some(Duration::from_secs(1)),
+        };
+        Row {
+            pid,
+            command: command.to_string(),
+            vm_bytes,
+            peak_bytes: vm_bytes.unwrap_or(0) + MIB,
+            as_limit,
+            status,
+        }
+    }
+
+    fn dashboard() -> Dashboard {
+        let mut dashboard = Dashboard::new();
+        dashboard.rows = vec![
+            row(30, "cc1plus", Some(300 * MIB), Some(512 * MIB), 2),
+            row(4, "bash", Some(10 * MIB), None, 0),
+            row(120, "ld", None, Some(512 * MIB), 0),
+            row(12, "cc1plus", Some(200 * MIB), Some(256 * MIB), 5),
+        ];
+        dashboard
+    }
+
+    fn visible_pids(dashboard: &Dashboard) -> Vec<u32> {
+        dashboard.visible_rows().iter().map(|row| row.pid).collect()
+    }
+
+    #[test]
+    fn sorting() {
+        let mut dashboard = dashboard();
+        // The sizes are sorted largest first, with the exited processes last.
+        assert_eq!(visible_pids(&dashboard), [30, 12, 4, 120]);
+
+        let expected: [(SortKey, [u32; 4]); 6] = [
+            (SortKey::Pid, [4, 12, 30, 120]),
+            // Equal keys are sorted by PID.
+            (SortKey::Command, [4, 12, 30, 120]),
+            (SortKey::Vm, [30, 12, 4, 120]),
+            (SortKey::Peak, [30, 12, 4, 120]),
+            (SortKey::Limit, [30, 120, 12, 4]),
+            (SortKey::Hits, [12, 30, 4, 120]),
+        ];
+        for (sort, pids) in expected {
+            dashboard.sort = sort;
+            dashboard.reverse = false;
+            assert_eq!(visible_pids(&dashboard), pids, "{}", sort.name());
+            dashboard.reverse = true;
+            assert_eq!(visible_pids(&dashboard), pids.into_iter().rev().collect::<Vec<_>>(), "{}", sort.name());
+        }
+    }
+
+    #[test]
+    fn filtering() {
+        let mut dashboard = dashboard();
+        dashboard.sort = SortKey::Pid;
+
+        dashboard.filter = "cc1".to_string();
+        assert_eq!(visible_pids(&dashboard), [12, 30]);
+        // The PIDs are matched as well.
+        dashboard.filter = "12".to_string();
+        assert_eq!(visible_pids(&dashboard), [12, 120]);
+        dashboard.filter = "nothing".to_string();
+        assert!(visible_pids(&dashboard).is_empty());
+
+        dashboard.filter.clear();
+        dashboard.show_exited = false;
+        assert_eq!(visible_pids(&dashboard), [4, 12, 30]);
+        assert_eq!(dashboard.counts(), (4, 1, 2));
+    }
+}
@@ -0,0 +1,247 @@
+//! A live dashboard of the processes monitored through the maps pinned by another
+//! process, such as `ebpf-memory-monitord --pin-path`.
+
+mod dashboard;
+
+use anyhow::{anyhow, bail, Context as _};
+use dashboard::Dashboard;
+use ebpf_memory_monitor::init::{initialize, InitOptions};
+use ebpf_memory_monitor::map_usage;
+use memmon::terminal::{read_byte, stdin_terminal, terminal_size, wait_for_input, RawMode};
+use memmon::units::format_bytes_short;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: memmon-top [OPTIONS] --pin-path <PATH>
+
+Options:
+  --pin-path <PATH>       The directory in which the monitor pinned its maps
+  --interval <SECONDS>    How often the maps are read [default: 1]
+  -h, --help              Print this help
+
+Keys:
+  s  sort by the next column      r  reverse the order
+  e  show or hide exited          /  filter by PID or command, Enter or Esc to finish
+  q  quit
+";
+
+/// The number of lines of the events pane.
+const EVENT_LINES: usize = 5;
+
+struct Options {
+    pin_path: PathBuf,
+    interval: Duration,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Options>> {
+    let mut pin_path = None;
+    let mut interval = Duration::from_secs(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pin-path" => pin_path = Some(args.next().ok_or_else(|| anyhow!("missing value for {arg}"))?.into()),
+            "--interval" => {
+                let value = args.next().ok_or_else(|| anyhow!("missing value for {arg}"))?;
+                interval = value
+                    .parse()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .filter(|interval| !interval.is_zero())
+                    .ok_or_else(|| anyhow!("invalid interval {value}"))?;
+            }
+            "-h" | "--help" => return Ok(None),
+            arg => bail!("unknown argument {arg}"),
+        }
+    }
+
+    let pin_path = pin_path.ok_or_else(|| anyhow!("missing --pin-path"))?;
+    Ok(Some(Options { pin_path, interval }))
+}
+
+/// What the keys do.
+enum Mode {
+    Normal,
+    /// The keys edit the filter.
+    Filter,
+}
+
+/// Shows the alternate screen of the terminal until dropped, like the full-screen
+/// programs, so that the shell is left as it was.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+fn draw(dashboard: &Dashboard, mode: &Mode) -> io::Result<()> {
+    let (height, width) = terminal_size().unwrap_or((24, 80));
+    let mut lines = Vec::new();
+
+    let (monitored, exited, hit) = dashboard.counts();
+    let mut header = format!("memmon-top  {monitored} monitored, {exited} exited, {hit} hit RLIMIT_AS");
+    if let Ok(usage) = map_usage() {
+        let _ = write!(header, "  map {}/{}", usage.processes, usage.max_entries);
+    }
+    let _ = write!(header, "  sort: {}{}", dashboard.sort.name(), if dashboard.reverse { " (reversed)" } else { "" });
+    if !dashboard.filter.is_empty() || matches!(mode, Mode::Filter) {
+        let _ = write!(header, "  filter: {}", dashboard.filter);
+    }
+    lines.push(header);
+    lines.push(String::new());
+    lines.push(format!(
+        "\x1b[7m{:>8}  {:<16}  {:>7}  {:>7}  {:>9}  {:>6}  {:<7}\x1b[0m",
+        "PID", "COMMAND", "VM", "PEAK", "RLIMIT_AS", "HITS", "STATE"
+    ));
+
+    let rows = dashboard.visible_rows();
+    let table_lines = height.saturating_sub(lines.len() + EVENT_LINES + 3);
+    for row in rows.iter().take(table_lines) {
+        let size = |bytes: Option<u64>| bytes.map_or("-".to_string(), format_bytes_short);
+        let line = format!(
+            "{:>8}  {:<16.16}  {:>7}  {:>7}  {:>9}  {:>6}  {:<7}",
+            row.pid,
+            row.command,
+            size(row.vm_bytes),
+            format_bytes_short(row.peak_bytes),
+            size(row.as_limit),
+            row.hits(),
+            if row.exited() { "exited" } else { "running" },
+        );
+        // The processes which hit their limit are shown in red.
+        lines.push(if row.hits() > 0 { format!("\x1b[31m{line}\x1b[0m") } else { line });
+    }
+    if rows.len() > table_lines {
+        lines.push(format!("... {} more", rows.len() - table_lines));
+    }
+
+    // The events pane is at the bottom, followed by the help line.
+    while lines.len() < height.saturating_sub(EVENT_LINES + 2) {
+        lines.push(String::new());
+    }
+    lines.push("\x1b[1mEvents\x1b[0m".to_string());
+    let skip = dashboard.events.len().saturating_sub(EVENT_LINES);
+    lines.extend(dashboard.events.iter().skip(skip).cloned());
+    while lines.len() < height.saturating_sub(1) {
+        lines.push(String::new());
+    }
+    lines.push(match mode {
+        Mode::Normal => "q quit  s sort  r reverse  e exited  / filter".to_string(),
+        Mode::Filter => "type to filter, Enter to keep, Esc to clear".to_string(),
+    });
+
+    let mut screen = String::from("\x1b[H");
+    for line in lines.iter().take(height) {
+        let _ = write!(screen, "{}\x1b[K\r\n", truncate(line, width));
+    }
+    screen.truncate(screen.len() - 2);
+    print!("{screen}\x1b[J");
+    io::stdout().flush()
+}
+
+/// Truncates the line to the width of the terminal, ignoring the escape sequences.
+fn truncate(line: &str, width: usize) -> String {
+    let mut truncated = String::new();
+    let mut shown = 0;
+    let mut escape = false;
+    for c in line.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => {}
+            _ if shown == width => continue,
+            _ => shown += 1,
+        }
+        truncated.push(c);
+    }
+    truncated
+}
+
+/// Handles a key, returning `false` if the dashboard should be closed.
+fn handle_key(dashboard: &mut Dashboard, mode: &mut Mode, byte: u8) -> io::Result<bool> {
+    // The escape sequences of the other keys are skipped.
+    if byte == 0x1b && wait_for_input(Duration::from_millis(10))? {
+        while wait_for_input(Duration::ZERO)? {
+            read_byte()?;
+        }
+        return Ok(true);
+    }
+
+    match mode {
+        Mode::Normal => match byte {
+            b'q' | 0x03 => return Ok(false),
+            b's' => dashboard.sort = dashboard.sort.next(),
+            b'r' => dashboard.reverse = !dashboard.reverse,
+            b'e' => dashboard.show_exited = !dashboard.show_exited,
+            b'/' => *mode = Mode::Filter,
+            _ => {}
+        },
+        Mode::Filter => match byte {
+            b'\r' | b'\n' => *mode = Mode::Normal,
+            0x1b => {
+                dashboard.filter.clear();
+                *mode = Mode::Normal;
+            }
+            0x7f | 0x08 => {
+                dashboard.filter.pop();
+            }
+            0x03 => return Ok(false),
+            byte if byte.is_ascii_graphic() || byte == b' ' => dashboard.filter.push(byte as char),
+            _ => {}
+        },
+    }
+    Ok(true)
+}
+
+fn main() -> anyhow::Result<()> {
+    let Some(options) = parse_options(env::args().skip(1))? else {
+        print!("{USAGE}");
+        return Ok(());
+    };
+    // Without pinned maps, `initialize` would load new programs which monitor nothing.
+    if !options.pin_path.join("PROCESSES").exists() {
+        bail!("no monitor pinned its maps in {}", options.pin_path.display());
+    }
+    let Some(terminal) = stdin_terminal() else {
+        bail!("memmon-top must be run in a terminal");
+    };
+    initialize(InitOptions::from_pin_path(&options.pin_path)?)
+        .context("reattaching to the pinned maps failed")?;
+
+    let _raw_mode = RawMode::enter(&terminal)?;
+    let _screen = Screen::enter()?;
+    let mut dashboard = Dashboard::new();
+    let mut mode = Mode::Normal;
+    let mut next_refresh = Instant::now();
+
+    loop {
+        if Instant::now() >= next_refresh {
+            dashboard.refresh();
+            next_refresh = Instant::now() + options.interval;
+        }
+        draw(&dashboard, &mode)?;
+
+        if wait_for_input(next_refresh.saturating_duration_since(Instant::now()))? {
+            match read_byte()? {
+                Some(byte) if handle_key(&mut dashboard, &mut mode, byte)? => {}
+                _ => break,
+            }
+        }
+    }
+
+    Ok(())
+}
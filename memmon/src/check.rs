@@ -0,0 +1,174 @@
+//! `memmon check`, which fails when a command uses more virtual memory than allowed.
+
+use anyhow::{anyhow, bail, Context as _};
+use ebpf_memory_monitor::run::Run;
+use memmon::units::format_bytes;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// The limits a command is checked against.
+pub(crate) struct Limits {
+    /// The maximum VM peak, in bytes.
+    pub(crate) budget: Option<u64>,
+    /// The VM peak of a previous run, in bytes.
+    pub(crate) baseline: Option<u64>,
+    /// How much larger than `baseline` the VM peak may be, in percent.
+    pub(crate) max_regression: f64,
+}
+
+/// The VM peak the command would have had without `RLIMIT_AS`, or `None` if its record
+/// was lost.
+pub(crate) fn vm_peak(run: &Run) -> Option<u64> {
+    let status = run.status.as_ref()?;
+    let max_attempted = status.rlimit_hits.as_ref().map_or(0, |hits| hits.max_attempted_bytes);
+    Some(status.vm_peak_bytes.max(max_attempted))
+}
+
+/// Checks the VM peak against the limits, returning the report and whether it passed.
+pub(crate) fn check(peak: u64, limits: &Limits) -> (String, bool) {
+    let mut report = String::new();
+    let mut passed = true;
+    let _ = writeln!(report, "VM peak:   {}", format_bytes(peak));
+
+    if let Some(budget) = limits.budget {
+        let _ = write!(report, "budget:    {}", format_bytes(budget));
+        if peak > budget {
+            passed = false;
+            let _ = write!(report, "  FAILED, exceeded by {} ({})", format_bytes(peak - budget), percent(peak, budget));
+        }
+        report.push('\n');
+    }
+
+    if let Some(baseline) = limits.baseline {
+        let _ = write!(report, "baseline:  {}  {}", format_bytes(baseline), percent(peak, baseline));
+        if peak as f64 > baseline as f64 * (1.0 + limits.max_regression / 100.0) {
+            passed = false;
+            let _ = write!(report, "  FAILED, allowed +{:.1}%", limits.max_regression);
+        }
+        report.push('\n');
+    }
+
+    let _ = writeln!(report, "{}", if passed { "PASSED" } else { "FAILED" });
+    (report, passed)
+}
+
+fn percent(value: u64, reference: u64) -> String {
+    if reference == 0 {
+        return "-".to_string();
+    }
+    format!("{:+.1}%", (value as f64 / reference as f64 - 1.0) * 100.0)
+}
+
+/// Reads the VM peak stored in a baseline file by `write_baseline`.
+pub(crate) fn read_baseline(path: &Path) -> anyhow::Result<u64> {
+    let baseline = fs::read_to_string(path).with_context(|| format!("reading {} failed", path.display()))?;
+    let peak = baseline
+        .lines()
+        .find_map(|line| line.strip_prefix("vm_peak_bytes="))
+        .ok_or_else(|| anyhow!("{} has no vm_peak_bytes", path.display()))?;
+    match peak.trim().parse() {
+        Ok(peak) => Ok(peak),
+        Err(_) => bail!("{} has an invalid vm_peak_bytes {peak}", path.display()),
+    }
+}
+
+/// Stores the VM peak in a baseline file, as `key=value` lines.
+pub(crate) fn write_baseline(path: &Path, peak: u64) -> anyhow::Result<()> {
+    fs::write(path, format!("vm_peak_bytes={peak}\n")).with_context(|| format!("writing {} failed", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const MIB: u64 = 1 << 20;
+
+    fn limits(budget: Option<u64>, baseline: Option<u64>, max_regression: f64) -> Limits {
+        Limits { budget, baseline, max_regression }
+    }
+
+    // A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("memmon-{}-{name}", std::process::id()));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn check_budget() {
+        let (report, passed) = check(100 * MIB, &limits(Some(100 * MIB), None, 0.0));
+        assert!(passed);
+        assert!(report.ends_with("PASSED\n"));
+
+        let (report, passed) = check(110 * MIB, &limits(Some(100 * MIB), None, 0.0));
+        assert!(!passed);
+        assert!(report.contains("FAILED, exceeded by 10.0 MiB (10485760 bytes) (+10.0%)"), "{report}");
+        assert!(report.ends_with("FAILED\n"));
+    }
+
+    #[test]
+    fn check_baseline() {
+        let (_, passed) = check(105 * MIB, &limits(None, Some(100 * MIB), 5.0));
+        assert!(passed);
+
+        let (report, passed) = check(106 * MIB, &limits(None, Some(100 * MIB), 5.0));
+        assert!(!passed);
+        assert!(report.contains("+6.0%  FAILED, allowed +5.0%"), "{report}");
+
+        // Shrinking is never a regression.
+        let (_, passed) = check(50 * MIB, &limits(None, Some(100 * MIB), 0.0));
+        assert!(passed);
+    }
+
+    #[test]
+    fn check_budget_and_baseline() {
+        // Within the baseline, but above the budget.
+        let (_, passed) = check(100 * MIB, &limits(Some(90 * MIB), Some(100 * MIB), 0.0));
+        assert!(!passed);
+    }
+
+    #[test]
+    fn percent_of_reference() {
+        assert_eq!(percent(110, 100), "+10.0%");
+        assert_eq!(percent(90, 100), "-10.0%");
+        assert_eq!(percent(100, 100), "+0.0%");
+        assert_eq!(percent(100, 0), "-");
+    }
+
+    #[test]
+    fn baseline_round_trip() {
+        let file = TempFile::new("round-trip", "");
+        write_baseline(&file.0, 123 * MIB).unwrap();
+        assert_eq!(read_baseline(&file.0).unwrap(), 123 * MIB);
+    }
+
+    #[test]
+    fn read_baseline_ignores_other_lines() {
+        let file = TempFile::new("other-lines", "version=2\nvm_peak_bytes= 4096 \n");
+        assert_eq!(read_baseline(&file.0).unwrap(), 4096);
+    }
+
+    #[test]
+    fn read_baseline_errors() {
+        let file = TempFile::new("missing-key", "peak=4096\n");
+        assert!(read_baseline(&file.0).unwrap_err().to_string().contains("has no vm_peak_bytes"));
+
+        let file = TempFile::new("invalid", "vm_peak_bytes=4K\n");
+        assert!(read_baseline(&file.0).unwrap_err().to_string().contains("invalid vm_peak_bytes 4K"));
+
+        let missing = std::env::temp_dir().join(format!("memmon-{}-missing", std::process::id()));
+        assert!(read_baseline(&missing).unwrap_err().to_string().starts_with("reading "));
+    }
+}
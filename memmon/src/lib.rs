@@ -0,0 +1,4 @@
+//! The parts shared by the `memmon` binaries.
+
+pub mod terminal;
+pub mod units;
@@ -0,0 +1,234 @@
+//! Command-line tools built on `ebpf-memory-monitor`.
+//!
+//! `memmon run -- <command>` runs a command like `/usr/bin/time -v`, and reports the peak
+//! size of its virtual memory along with the usual resource usage. `memmon check` runs it
+//! the same way, and fails if the peak is above a budget or regressed against a baseline.
+
+mod check;
+mod report;
+
+use anyhow::{anyhow, bail, Context as _};
+use check::{check, read_baseline, vm_peak, write_baseline, Limits};
+use ebpf_memory_monitor::init::{initialize, InitOptions};
+use ebpf_memory_monitor::run::{spawn, ExecFailed, Run};
+use memmon::units::parse_bytes;
+use nix::errno::Errno;
+use nix::sys::signal::{signal, SigHandler, Signal};
+use report::Format;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: memmon run [OPTIONS] -- <COMMAND>...
+       memmon check [OPTIONS] -- <COMMAND>...
+
+memmon run runs the command, and reports the peak size of its virtual memory once it exits.
+Exits with the exit code of the command, or 128 plus the signal which killed it.
+
+Options:
+  --as-limit <BYTES>    Set RLIMIT_AS of the command, with an optional K, M or G suffix
+  --format <FORMAT>     human, json or time (like /usr/bin/time -v) [default: human]
+  -o, --output <PATH>   Write the report to this file instead of stderr
+  -h, --help            Print this help
+
+memmon check runs the command the same way, and exits with 3 if the peak size of its virtual
+memory, including the expansions rejected by RLIMIT_AS, is above the budget or regressed
+against the baseline. A failure of the command itself is propagated as by memmon run.
+
+Options:
+  --as-limit <BYTES>           Set RLIMIT_AS of the command
+  --budget <BYTES>             Fail if the peak is above this size
+  --baseline <PATH>            Fail if the peak regressed against the one stored in this file
+  --max-regression <PERCENT>   How much the peak may grow over the baseline [default: 0]
+  --update-baseline            Store the peak in the baseline file instead of checking it
+";
+
+/// The exit code of `memmon check` when the command used too much memory. It's unlikely
+/// to be used by the command itself, unlike 1 and 2.
+const EXIT_CHECK_FAILED: u8 = 3;
+
+/// The exit code when `memmon` itself fails, as with `timeout` and `env`.
+const EXIT_FAILED: u8 = 125;
+
+struct RunArgs {
+    as_limit: Option<u64>,
+    format: Format,
+    output: Option<PathBuf>,
+    command: Vec<String>,
+}
+
+impl RunArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut run_args = RunArgs { as_limit: None, format: Format::Human, output: None, command: Vec::new() };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("missing value for {arg}"));
+            match arg.as_str() {
+                "--as-limit" => run_args.as_limit = Some(parse_bytes(&value()?)?),
+                "--format" => {
+                    let format = value()?;
+                    run_args.format = Format::parse(&format).ok_or_else(|| anyhow!("unknown format {format}"))?;
+                }
+                "-o" | "--output" => run_args.output = Some(PathBuf::from(value()?)),
+                "-h" | "--help" => return Ok(None),
+                "--" => break,
+                arg => bail!("unknown argument {arg}"),
+            }
+        }
+
+        run_args.command = args.collect();
+        if run_args.command.is_empty() {
+            bail!("no command given");
+        }
+        Ok(Some(run_args))
+    }
+}
+
+struct CheckArgs {
+    as_limit: Option<u64>,
+    budget: Option<u64>,
+    baseline: Option<PathBuf>,
+    max_regression: f64,
+    update_baseline: bool,
+    command: Vec<String>,
+}
+
+impl CheckArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut check_args = CheckArgs {
+            as_limit: None,
+            budget: None,
+            baseline: None,
+            max_regression: 0.0,
+            update_baseline: false,
+            command: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("missing value for {arg}"));
+            match arg.as_str() {
+                "--as-limit" => check_args.as_limit = Some(parse_bytes(&value()?)?),
+                "--budget" => check_args.budget = Some(parse_bytes(&value()?)?),
+                "--baseline" => check_args.baseline = Some(PathBuf::from(value()?)),
+                "--max-regression" => {
+                    let percent = value()?;
+                    check_args.max_regression = percent
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|percent| *percent >= 0.0)
+                        .ok_or_else(|| anyhow!("invalid percentage {percent}"))?;
+                }
+                "--update-baseline" => check_args.update_baseline = true,
+                "-h" | "--help" => return Ok(None),
+                "--" => break,
+                arg => bail!("unknown argument {arg}"),
+            }
+        }
+
+        check_args.command = args.collect();
+        if check_args.command.is_empty() {
+            bail!("no command given");
+        }
+        if check_args.budget.is_none() && check_args.baseline.is_none() {
+            bail!("--budget or --baseline is required");
+        }
+        if check_args.update_baseline && check_args.baseline.is_none() {
+            bail!("--update-baseline requires --baseline");
+        }
+        Ok(Some(check_args))
+    }
+}
+
+fn run_command(args: RunArgs) -> anyhow::Result<u8> {
+    // Only the command is monitored.
+    initialize(InitOptions::new(1)).context("initializing ebpf-memory-monitor failed")?;
+    let run = run(&args.command, args.as_limit)?;
+
+    let report = args.format.report(&run);
+    match &args.output {
+        Some(path) => fs::write(path, report).with_context(|| format!("writing {} failed", path.display()))?,
+        None => io::stderr().write_all(report.as_bytes())?,
+    }
+
+    Ok(run.exit.code() as u8)
+}
+
+fn check_command(args: CheckArgs) -> anyhow::Result<u8> {
+    let baseline = match &args.baseline {
+        Some(path) if !args.update_baseline => Some(read_baseline(path)?),
+        _ => None,
+    };
+
+    initialize(InitOptions::new(1)).context("initializing ebpf-memory-monitor failed")?;
+    let run = run(&args.command, args.as_limit)?;
+    let peak = vm_peak(&run).ok_or_else(|| anyhow!("the record of {} was lost", args.command[0]))?;
+
+    let limits = Limits { budget: args.budget, baseline, max_regression: args.max_regression };
+    let (report, passed) = check(peak, &limits);
+    io::stderr().write_all(report.as_bytes())?;
+
+    if let Some(path) = &args.baseline
+        && args.update_baseline
+        && passed
+        && run.exit.code() == 0
+    {
+        write_baseline(path, peak)?;
+    }
+
+    Ok(match run.exit.code() {
+        0 if !passed => EXIT_CHECK_FAILED,
+        code => code as u8,
+    })
+}
+
+/// Runs the command, leaving the interrupts from the terminal to it like the shells do.
+fn run(command: &[String], as_limit: Option<u64>) -> anyhow::Result<Run> {
+    let child = spawn(command, as_limit)?;
+    for interrupt in [Signal::SIGINT, Signal::SIGQUIT] {
+        unsafe { signal(interrupt, SigHandler::SigIgn) }?;
+    }
+    child.wait()
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("run") => match RunArgs::parse(args) {
+            Ok(Some(run_args)) => run_command(run_args),
+            Ok(None) => {
+                print!("{USAGE}");
+                Ok(0)
+            }
+            Err(error) => Err(error),
+        },
+        Some("check") => match CheckArgs::parse(args) {
+            Ok(Some(check_args)) => check_command(check_args),
+            Ok(None) => {
+                print!("{USAGE}");
+                Ok(0)
+            }
+            Err(error) => Err(error),
+        },
+        Some("-h" | "--help") => {
+            print!("{USAGE}");
+            Ok(0)
+        }
+        Some(command) => Err(anyhow!("unknown command {command}")),
+        None => Err(anyhow!("no command given")),
+    };
+
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(error) => {
+            eprintln!("memmon: {error:#}");
+            // Like the shells, tell a missing command apart from one which can't be executed.
+            match error.downcast_ref::<ExecFailed>() {
+                Some(ExecFailed { errno: Errno::ENOENT, .. }) => ExitCode::from(127),
+                Some(_) => ExitCode::from(126),
+                None => ExitCode::from(EXIT_FAILED),
+            }
+        }
+    }
+}
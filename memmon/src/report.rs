@@ -0,0 +1,283 @@
+//! The formats in which `memmon run` reports a command.
+
+use ebpf_memory_monitor::run::{Exit, Run};
+use memmon::units::format_bytes;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/// A format of the report of `memmon run`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Human,
+    Json,
+    /// The format of `/usr/bin/time -v`, with the VM peaks appended.
+    Time,
+}
+
+impl Format {
+    pub(crate) fn parse(format: &str) -> Option<Self> {
+        match format {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            "time" => Some(Format::Time),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn report(self, run: &Run) -> String {
+        match self {
+            Format::Human => human(run),
+            Format::Json => json(run),
+            Format::Time => time(run),
+        }
+    }
+}
+
+fn human(run: &Run) -> String {
+    let mut report = String::new();
+    let status = run.status.as_ref();
+    let _ = writeln!(report, "command:            {}", run.command.join(" "));
+    let _ = match run.exit {
+        Exit::Code(code) => writeln!(report, "exit status:        {code}"),
+        Exit::Signal(signal) => writeln!(report, "exit status:        killed by signal {signal}"),
+    };
+    let _ = writeln!(report, "VM peak:            {}", optional_bytes(status.map(|status| status.vm_peak_bytes)));
+    let _ = writeln!(
+        report,
+        "attempted VM peak:  {}",
+        optional_bytes(status.and_then(|status| status.attempted_vm_peak_bytes))
+    );
+    if let Some(limit) = run.as_limit {
+        let hits = status.and_then(|status| status.rlimit_hits.as_ref()).map_or(0, |hits| hits.count);
+        let _ = writeln!(report, "RLIMIT_AS:          {}, hit {hits} times", format_bytes(limit));
+    }
+    let _ = writeln!(report, "max RSS:            {}", format_bytes(run.rusage.ru_maxrss as u64 * 1024));
+    let _ = writeln!(report, "user time:          {:.3} s", user_time(run).as_secs_f64());
+    let _ = writeln!(report, "system time:        {:.3} s", system_time(run).as_secs_f64());
+    let _ = writeln!(report, "wall time:          {:.3} s", run.wall_time.as_secs_f64());
+    report
+}
+
+fn optional_bytes(value: Option<u64>) -> String {
+    value.map_or_else(|| "-".to_string(), format_bytes)
+}
+
+// The report of the `json` format, whose fields are written in this order.
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    command: &'a [String],
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    vm_peak_bytes: Option<u64>,
+    attempted_vm_peak_bytes: Option<u64>,
+    as_limit_bytes: Option<u64>,
+    rlimit_hits: u64,
+    max_rss_bytes: u64,
+    user_time_secs: f64,
+    system_time_secs: f64,
+    wall_time_secs: f64,
+}
+
+fn json(run: &Run) -> String {
+    let status = run.status.as_ref();
+    let (exit_code, signal) = match run.exit {
+        Exit::Code(code) => (Some(code), None),
+        Exit::Signal(signal) => (None, Some(signal)),
+    };
+    let report = JsonReport {
+        command: &run.command,
+        exit_code,
+        signal,
+        vm_peak_bytes: status.map(|status| status.vm_peak_bytes),
+        attempted_vm_peak_bytes: status.and_then(|status| status.attempted_vm_peak_bytes),
+        as_limit_bytes: run.as_limit,
+        rlimit_hits: status.and_then(|status| status.rlimit_hits.as_ref()).map_or(0, |hits| hits.count),
+        max_rss_bytes: run.rusage.ru_maxrss as u64 * 1024,
+        user_time_secs: user_time(run).as_secs_f64(),
+        system_time_secs: system_time(run).as_secs_f64(),
+        wall_time_secs: run.wall_time.as_secs_f64(),
+    };
+    // Serializing can't fail, as all the keys are strings.
+    serde_json::to_string(&report).unwrap() + "\n"
+}
+
+fn time(run: &Run) -> String {
+    let rusage = &run.rusage;
+    let status = run.status.as_ref();
+    let cpu_time = user_time(run) + system_time(run);
+    let wall_time = run.wall_time.as_secs_f64();
+    let percent = if wall_time > 0.0 { cpu_time.as_secs_f64() / wall_time * 100.0 } else { 0.0 };
+    let elapsed = elapsed(run.wall_time);
+    let kbytes = |value: Option<u64>| value.map_or(0, |value| value / 1024);
+
+    let mut report = String::new();
+    if let Exit::Signal(signal) = run.exit {
+        let _ = writeln!(report, "Command terminated by signal {signal}");
+    }
+    let _ = writeln!(report, "\tCommand being timed: \"{}\"", run.command.join(" "));
+    let _ = writeln!(report, "\tUser time (seconds): {:.2}", user_time(run).as_secs_f64());
+    let _ = writeln!(report, "\tSystem time (seconds): {:.2}", system_time(run).as_secs_f64());
+    let _ = writeln!(report, "\tPercent of CPU this job got: {percent:.0}%");
+    let _ = writeln!(report, "\tElapsed (wall clock) time (h:mm:ss or m:ss): {elapsed}");
+    let _ = writeln!(report, "\tAverage shared text size (kbytes): 0");
+    let _ = writeln!(report, "\tAverage unshared data size (kbytes): 0");
+    let _ = writeln!(report, "\tAverage stack size (kbytes): 0");
+    let _ = writeln!(report, "\tAverage total size (kbytes): 0");
+    let _ = writeln!(report, "\tMaximum resident set size (kbytes): {}", rusage.ru_maxrss);
+    let _ = writeln!(report, "\tAverage resident set size (kbytes): 0");
+    let _ = writeln!(report, "\tMajor (requiring I/O) page faults: {}", rusage.ru_majflt);
+    let _ = writeln!(report, "\tMinor (reclaiming a frame) page faults: {}", rusage.ru_minflt);
+    let _ = writeln!(report, "\tVoluntary context switches: {}", rusage.ru_nvcsw);
+    let _ = writeln!(report, "\tInvoluntary context switches: {}", rusage.ru_nivcsw);
+    let _ = writeln!(report, "\tSwaps: {}", rusage.ru_nswap);
+    let _ = writeln!(report, "\tFile system inputs: {}", rusage.ru_inblock);
+    let _ = writeln!(report, "\tFile system outputs: {}", rusage.ru_oublock);
+    let _ = writeln!(report, "\tSocket messages sent: {}", rusage.ru_msgsnd);
+    let _ = writeln!(report, "\tSocket messages received: {}", rusage.ru_msgrcv);
+    let _ = writeln!(report, "\tSignals delivered: {}", rusage.ru_nsignals);
+    let _ = writeln!(report, "\tPage size (bytes): {}", page_size());
+    let _ = match run.exit {
+        Exit::Code(code) => writeln!(report, "\tExit status: {code}"),
+        Exit::Signal(_) => writeln!(report, "\tExit status: 0"),
+    };
+    let _ = writeln!(
+        report,
+        "\tMaximum virtual memory size (kbytes): {}",
+        kbytes(status.map(|status| status.vm_peak_bytes))
+    );
+    let _ = writeln!(
+        report,
+        "\tAttempted virtual memory size (kbytes): {}",
+        kbytes(status.and_then(|status| status.attempted_vm_peak_bytes))
+    );
+    report
+}
+
+/// Formats the wall time as `/usr/bin/time` does, as h:mm:ss from an hour on, and as
+/// m:ss.cc below, truncating to hundredths of a second.
+fn elapsed(wall_time: Duration) -> String {
+    let secs = wall_time.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}.{:02}", secs / 60, secs % 60, wall_time.subsec_millis() / 10)
+    }
+}
+
+fn user_time(run: &Run) -> Duration {
+    timeval(run.rusage.ru_utime)
+}
+
+fn system_time(run: &Run) -> Duration {
+    timeval(run.rusage.ru_stime)
+}
+
+fn timeval(value: libc::timeval) -> Duration {
+    Duration::new(value.tv_sec as u64, value.tv_usec as u32 * 1000)
+}
+
+fn page_size() -> i64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ebpf_memory_monitor::ProcessStatus;
+    use serde_json::{json, Value};
+    use std::mem;
+
+    fn run(command: &[&str], exit: Exit, status: Option<ProcessStatus>) -> Run {
+        let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+        rusage.ru_maxrss = 2048;
+        rusage.ru_utime = libc::timeval { tv_sec: 1, tv_usec: 500_000 };
+        Run {
+            command: command.iter().map(|arg| arg.to_string()).collect(),
+            as_limit: Some(1 << 30),
+            exit,
+            status,
+            rusage,
+            wall_time: Duration::from_millis(2250),
+        }
+    }
+
+    #[test]
+    fn json_fields() {
+        let status = ProcessStatus {
+            vm_peak_bytes: 1 << 20,
+            attempted_vm_peak_bytes: None,
+            rlimit_hits: None,
+            rlimit_changes: Vec::new(),
+            rlimit_changes_lost: false,
+            budget_exceeded_bytes: None,
+            enforced_limit_bytes: None,
+            denied_vm_peak_bytes: None,
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            voluntary_context_switches: 0,
+            involuntary_context_switches: 0,
+            start_time: None,
+            exit_time: None,
+        };
+        let report = Format::Json.report(&run(&["true"], Exit::Code(3), Some(status)));
+        assert!(report.ends_with("}\n"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&report).unwrap(),
+            json!({
+                "command": ["true"],
+                "exit_code": 3,
+                "signal": null,
+                "vm_peak_bytes": 1 << 20,
+                "attempted_vm_peak_bytes": null,
+                "as_limit_bytes": 1 << 30,
+                "rlimit_hits": 0,
+                "max_rss_bytes": 2048 * 1024,
+                "user_time_secs": 1.5,
+                "system_time_secs": 0.0,
+                "wall_time_secs": 2.25,
+            })
+        );
+
+        let report = Format::Json.report(&run(&["sleep", "1"], Exit::Signal(9), None));
+        let value = serde_json::from_str::<Value>(&report).unwrap();
+        assert_eq!(value["exit_code"], Value::Null);
+        assert_eq!(value["signal"], json!(9));
+        assert_eq!(value["vm_peak_bytes"], Value::Null);
+    }
+
+    #[test]
+    fn json_escaping() {
+        let command = [
+            "echo",
+            "\"quoted\"",
+            "back\\slash",
+            "new\nline\ttab\u{1}\u{7f}",
+            "caf\u{e9} \u{2028} \u{1f600}",
+            // What a non-UTF-8 argument is replaced with.
+            "\u{fffd}",
+        ];
+        let report = Format::Json.report(&run(&command, Exit::Code(0), None));
+        assert!(!report.trim_end().contains(['\n', '\t', '\u{1}']), "{report}");
+        assert!(report.contains(r#""\"quoted\"""#), "{report}");
+        assert!(report.contains(r#""new\nline\ttab\u0001"#), "{report}");
+
+        let value = serde_json::from_str::<Value>(&report).unwrap();
+        assert_eq!(value["command"], json!(command));
+    }
+
+    #[test]
+    fn time_elapsed() {
+        assert_eq!(elapsed(Duration::ZERO), "0:00.00");
+        assert_eq!(elapsed(Duration::from_millis(1_050)), "0:01.05");
+        // Truncated like `/usr/bin/time`, rather than rounded up to 0:60.00.
+        assert_eq!(elapsed(Duration::from_millis(59_999)), "0:59.99");
+        assert_eq!(elapsed(Duration::from_millis(61_500)), "1:01.50");
+        assert_eq!(elapsed(Duration::from_millis(3_599_999)), "59:59.99");
+        assert_eq!(elapsed(Duration::from_secs(3_600)), "1:00:00");
+        assert_eq!(elapsed(Duration::from_millis(90_061_900)), "25:01:01");
+
+        let report = Format::Time.report(&run(&["true"], Exit::Code(0), None));
+        assert!(report.contains("\tElapsed (wall clock) time (h:mm:ss or m:ss): 0:02.25\n"), "{report}");
+    }
+}
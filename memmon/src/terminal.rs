@@ -0,0 +1,66 @@
+//! Raw-mode input for the interactive binaries.
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::termios::{tcgetattr, tcsetattr, InputFlags, LocalFlags, SetArg, Termios};
+use std::io::{self, IsTerminal};
+use std::mem;
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+/// The settings of the terminal on stdin, or `None` if stdin is not a terminal.
+pub fn stdin_terminal() -> Option<Termios> {
+    let stdin = io::stdin();
+    if stdin.is_terminal() { tcgetattr(stdin.as_fd()).ok() } else { None }
+}
+
+/// Disables the line discipline of the terminal until dropped, so that keys are read
+/// as they are pressed, without being echoed or turned into signals.
+pub struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    /// Switches the terminal from the `original` settings to raw mode.
+    pub fn enter(original: &Termios) -> io::Result<Self> {
+        let mut raw = original.clone();
+        raw.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.input_flags.remove(InputFlags::IXON | InputFlags::ICRNL);
+        tcsetattr(io::stdin().as_fd(), SetArg::TCSADRAIN, &raw)?;
+        Ok(RawMode { original: original.clone() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(io::stdin().as_fd(), SetArg::TCSADRAIN, &self.original);
+    }
+}
+
+/// Waits up to `timeout` for input on stdin, returning whether there is some.
+pub fn wait_for_input(timeout: Duration) -> io::Result<bool> {
+    let stdin = io::stdin();
+    let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+    let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+    Ok(poll(&mut fds, timeout)? > 0)
+}
+
+/// Reads a byte from stdin, or returns `None` at the end of the input.
+///
+/// Reads the file descriptor directly, as the buffer of `io::Stdin` would hide pending
+/// input from `wait_for_input`.
+pub fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match nix::unistd::read(io::stdin().as_fd(), &mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Returns the number of rows and columns of the terminal on stdout.
+pub fn terminal_size() -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_row > 0 => Some((size.ws_row.into(), size.ws_col.into())),
+        _ => None,
+    }
+}
@@ -0,0 +1,55 @@
+//! Parsing and formatting sizes.
+
+use anyhow::anyhow;
+
+/// Parses a number of bytes, with an optional binary `K`, `M` or `G` suffix.
+pub fn parse_bytes(value: &str) -> anyhow::Result<u64> {
+    let (number, shift) = match value.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&value[..index], 10),
+        Some((index, 'M' | 'm')) => (&value[..index], 20),
+        Some((index, 'G' | 'g')) => (&value[..index], 30),
+        _ => (value, 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| anyhow!("invalid size {value}"))
+}
+
+/// Formats a number of bytes with a binary unit, followed by the exact number.
+pub fn format_bytes(value: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    let mut scaled = value as f64;
+    let mut unit = None;
+    for next in UNITS {
+        if scaled < 1024.0 {
+            break;
+        }
+        scaled /= 1024.0;
+        unit = Some(next);
+    }
+    match unit {
+        Some(unit) => format!("{scaled:.1} {unit} ({value} bytes)"),
+        None => format!("{value} bytes"),
+    }
+}
+
+/// Formats a number of bytes with a single-letter binary unit, for the columns of tables.
+pub fn format_bytes_short(value: u64) -> String {
+    const UNITS: [char; 4] = ['K', 'M', 'G', 'T'];
+    let mut scaled = value as f64;
+    let mut unit = 'B';
+    for next in UNITS {
+        if scaled < 1024.0 {
+            break;
+        }
+        scaled /= 1024.0;
+        unit = next;
+    }
+    match unit {
+        'B' => format!("{value}B"),
+        _ if scaled < 10.0 => format!("{scaled:.1}{unit}"),
+        _ => format!("{scaled:.0}{unit}"),
+    }
+}
@@ -0,0 +1,158 @@
+#![no_std]
+#![no_main]
+
+use aya_ebpf::bindings::{BPF_F_NO_PREALLOC, BPF_F_RDONLY_PROG, BPF_F_WRONLY};
+use aya_ebpf::helpers::{bpf_get_current_pid_tgid, bpf_override_return};
+use aya_ebpf::EbpfContext;
+use aya_ebpf::macros::{fentry, kprobe, lsm, map, tracepoint};
+use aya_ebpf::maps::{Array, HashMap, LruHashMap, PerfEventArray};
+use aya_ebpf::programs::{FEntryContext, LsmContext, ProbeContext, TracePointContext};
+use ebpf_common::{
+    try_on_do_exit, try_on_may_expand_vm, try_on_mmap_file, try_on_sched_process_exit,
+    try_on_sys_brk, try_on_sys_enter_mmap, try_on_sys_enter_prlimit64,
+    try_on_sys_enter_setrlimit, try_on_sys_exit_mmap, try_on_sys_mremap,
+};
+use ebpf_memory_monitor_common::{ProcessRecord, RlimitChangeEvent, CONSTANTS_COUNT};
+
+#[map]
+// Constants passed from userspace to the ebpf program before it is loaded.
+// CONSTANTS[RLIMIT_AS_INDEX] = RLIMIT_AS
+// CONSTANTS[PAGE_SHIFT_INDEX] = PAGE_SHIFT
+static CONSTANTS: Array<u64> =
+    Array::with_max_entries(CONSTANTS_COUNT, BPF_F_WRONLY | BPF_F_RDONLY_PROG);
+
+#[map]
+// The value of max_entries is temporary, and it's set when the ebpf program is loaded.
+static PROCESSES: HashMap<u32, ProcessRecord> =
+    HashMap::<u32, ProcessRecord>::with_max_entries(0, BPF_F_NO_PREALLOC);
+
+#[map]
+static RLIMIT_CHANGES: PerfEventArray<RlimitChangeEvent> = PerfEventArray::new(0);
+
+#[map]
+// The limits enforced by the LSM and syscall programs, in bytes.
+// The value of max_entries is temporary, and it's set when the ebpf program is loaded.
+static ENFORCED_LIMITS: HashMap<u32, u64> =
+    HashMap::<u32, u64>::with_max_entries(0, BPF_F_NO_PREALLOC);
+
+#[map]
+// The length of the mmap call in progress, keyed by the pid_tgid of the calling thread.
+// The value of max_entries is temporary, and it's set when the ebpf program is loaded.
+static MMAP_LENGTHS: LruHashMap<u64, u64> =
+    LruHashMap::<u64, u64>::with_max_entries(0, 0);
+
+#[fentry(function = "may_expand_vm")]
+pub fn on_may_expand_vm(ctx: FEntryContext) -> u32 {
+    try_on_may_expand_vm(
+        unsafe { ctx.arg(0) },
+        unsafe { ctx.arg(2) },
+        ctx.tgid(),
+        &PROCESSES,
+        &CONSTANTS,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[fentry(function = "do_exit")]
+pub fn on_do_exit(ctx: FEntryContext) -> u32 {
+    try_on_do_exit(
+        ctx.tgid(),
+        &PROCESSES,
+        &CONSTANTS
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "sched", name = "sched_process_exit")]
+pub fn on_sched_process_exit(ctx: TracePointContext) -> u32 {
+    try_on_sched_process_exit(
+        ctx.tgid(),
+        &PROCESSES,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "syscalls", name = "sys_enter_setrlimit")]
+pub fn on_sys_enter_setrlimit(ctx: TracePointContext) -> u32 {
+    try_on_sys_enter_setrlimit(
+        &ctx,
+        &PROCESSES,
+        &RLIMIT_CHANGES,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "syscalls", name = "sys_enter_prlimit64")]
+pub fn on_sys_enter_prlimit64(ctx: TracePointContext) -> u32 {
+    try_on_sys_enter_prlimit64(
+        &ctx,
+        &PROCESSES,
+        &RLIMIT_CHANGES,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "syscalls", name = "sys_enter_mmap")]
+pub fn on_sys_enter_mmap(ctx: TracePointContext) -> u32 {
+    try_on_sys_enter_mmap(
+        &ctx,
+        &ENFORCED_LIMITS,
+        &MMAP_LENGTHS,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "syscalls", name = "sys_exit_mmap")]
+pub fn on_sys_exit_mmap(_ctx: TracePointContext) -> u32 {
+    try_on_sys_exit_mmap(
+        &MMAP_LENGTHS,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[lsm(hook = "mmap_file")]
+pub fn on_mmap_file(ctx: LsmContext) -> i32 {
+    // The return value of the previous LSM program attached to this hook.
+    let previous: i32 = unsafe { ctx.arg(4) };
+    if previous != 0 {
+        return previous;
+    }
+
+    try_on_mmap_file(
+        ctx.tgid(),
+        bpf_get_current_pid_tgid(),
+        &PROCESSES,
+        &ENFORCED_LIMITS,
+        &MMAP_LENGTHS,
+        &CONSTANTS,
+    ).unwrap_or(0)
+}
+
+// The LSM hooks can't deny brk and mremap calls, so the syscalls are made to fail by
+// overriding their return value, which the kernel allows on syscall entry points.
+#[kprobe]
+pub fn on_sys_brk(ctx: ProbeContext) -> u32 {
+    match try_on_sys_brk(&ctx, &PROCESSES, &ENFORCED_LIMITS, &CONSTANTS) {
+        Ok(Some(ret)) => {
+            unsafe { bpf_override_return(ctx.regs, ret) };
+            0
+        }
+        Ok(None) => 0,
+        Err(ret) => ret.try_into().unwrap_or(1),
+    }
+}
+
+#[kprobe]
+pub fn on_sys_mremap(ctx: ProbeContext) -> u32 {
+    match try_on_sys_mremap(&ctx, &PROCESSES, &ENFORCED_LIMITS, &CONSTANTS) {
+        Ok(Some(ret)) => {
+            unsafe { bpf_override_return(ctx.regs, ret) };
+            0
+        }
+        Ok(None) => 0,
+        Err(ret) => ret.try_into().unwrap_or(1),
+    }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+
+#[unsafe(link_section = "license")]
+#[unsafe(no_mangle)]
+static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+use aya_ebpf::bindings::{BPF_F_NO_PREALLOC, BPF_F_RDONLY_PROG, BPF_F_WRONLY};
+use aya_ebpf::cty::c_ulong;
+use aya_ebpf::macros::{kprobe, map, tracepoint};
+use aya_ebpf::maps::{Array, HashMap, PerfEventArray};
+use aya_ebpf::programs::{ProbeContext, TracePointContext};
+use aya_ebpf::EbpfContext;
+use ebpf_common::{
+    try_on_do_exit, try_on_may_expand_vm, try_on_sched_process_exit, try_on_sys_enter_prlimit64,
+    try_on_sys_enter_setrlimit,
+};
+use ebpf_common::vmlinux::mm_struct;
+use ebpf_memory_monitor_common::{ProcessRecord, RlimitChangeEvent, CONSTANTS_COUNT};
+
+#[map]
+// Constants passed from userspace to the ebpf program before it is loaded.
+// CONSTANTS[RLIMIT_AS_INDEX] = RLIMIT_AS
+// CONSTANTS[PAGE_SHIFT_INDEX] = PAGE_SHIFT
+static CONSTANTS: Array<u64> =
+    Array::with_max_entries(CONSTANTS_COUNT, BPF_F_WRONLY | BPF_F_RDONLY_PROG);
+
+#[map]
+// The value of max_entries is temporary, and it's set when the ebpf program is loaded.
+static PROCESSES: HashMap<u32, ProcessRecord> =
+    HashMap::<u32, ProcessRecord>::with_max_entries(0, BPF_F_NO_PREALLOC);
+
+#[map]
+static RLIMIT_CHANGES: PerfEventArray<RlimitChangeEvent> = PerfEventArray::new(0);
+
+#[kprobe]
+pub fn on_may_expand_vm(ctx: ProbeContext) -> u32 {
+    let mm: Option<*const mm_struct> = ctx.arg(0);
+    let npages: Option<c_ulong> = ctx.arg(2);
+
+    if let Some(mm) = mm && let Some(npages) = npages {
+        try_on_may_expand_vm(
+            mm,
+            npages,
+            ctx.tgid(),
+            &PROCESSES,
+            &CONSTANTS,
+        ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+    } else {
+        1
+    }
+}
+
+#[kprobe]
+pub fn on_do_exit(ctx: ProbeContext) -> u32 {
+    try_on_do_exit(
+        ctx.tgid(),
+        &PROCESSES,
+        &CONSTANTS
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "sched", name = "sched_process_exit")]
+pub fn on_sched_process_exit(ctx: TracePointContext) -> u32 {
+    try_on_sched_process_exit(
+        ctx.tgid(),
+        &PROCESSES,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "syscalls", name = "sys_enter_setrlimit")]
+pub fn on_sys_enter_setrlimit(ctx: TracePointContext) -> u32 {
+    try_on_sys_enter_setrlimit(
+        &ctx,
+        &PROCESSES,
+        &RLIMIT_CHANGES,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[tracepoint(category = "syscalls", name = "sys_enter_prlimit64")]
+pub fn on_sys_enter_prlimit64(ctx: TracePointContext) -> u32 {
+    try_on_sys_enter_prlimit64(
+        &ctx,
+        &PROCESSES,
+        &RLIMIT_CHANGES,
+    ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
+
+#[unsafe(link_section = "license")]
+#[unsafe(no_mangle)]
+static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
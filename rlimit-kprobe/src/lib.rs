@@ -1,3 +0,0 @@
-#![no_std]
-
-// This file exists to enable the library target.
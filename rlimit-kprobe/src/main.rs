@@ -1,51 +0,0 @@
-#![no_std]
-#![no_main]
-
-use aya_ebpf::bindings::{BPF_F_NO_PREALLOC, BPF_F_RDONLY_PROG, BPF_F_WRONLY};
-use aya_ebpf::cty::c_ulong;
-use aya_ebpf::macros::{kprobe, map};
-use aya_ebpf::maps::{Array, HashMap};
-use aya_ebpf::programs::ProbeContext;
-use aya_ebpf::EbpfContext;
-use ebpf_common::try_on_may_expand_vm;
-use ebpf_common::vmlinux::mm_struct;
-
-#[map]
-// Constants passed from userspace to the ebpf program before it is loaded.
-// CONSTANTS[0] = RLIMIT_AS
-// CONSTANTS[1] = PAGE_SHIFT
-static CONSTANTS: Array<u64> =
-    Array::with_max_entries(2, BPF_F_WRONLY | BPF_F_RDONLY_PROG);
-
-#[map]
-// The value of max_entries is temporary, and it's set when the ebpf program is loaded.
-static ATTEMPTED_VM_PEAK: HashMap<u32, i64> =
-    HashMap::<u32, i64>::with_max_entries(0, BPF_F_NO_PREALLOC);
-
-#[kprobe]
-pub fn on_may_expand_vm(ctx: ProbeContext) -> u32 {
-    let mm: Option<*const mm_struct> = ctx.arg(0);
-    let npages: Option<c_ulong> = ctx.arg(2);
-
-    if let Some(mm) = mm && let Some(npages) = npages {
-        try_on_may_expand_vm(
-            mm,
-            npages,
-            ctx.tgid(),
-            &ATTEMPTED_VM_PEAK,
-            &CONSTANTS,
-        ).unwrap_or_else(|ret| ret.try_into().unwrap_or(1))
-    } else {
-        1
-    }
-}
-
-#[cfg(not(test))]
-#[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    loop {}
-}
-
-#[unsafe(link_section = "license")]
-#[unsafe(no_mangle)]
-static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";